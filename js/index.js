@@ -1 +1,6 @@
-import("../pkg/index.js").catch(console.error);
+import("../pkg/index.js")
+  .then((wasm) => wasm.start_game())
+  .then((game) => {
+    window.game = game;
+  })
+  .catch(console.error);
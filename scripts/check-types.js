@@ -0,0 +1,16 @@
+// Fails when the generated type definitions are missing part of the public API.
+const fs = require("fs");
+const path = require("path");
+
+const definitions = fs.readFileSync(
+  path.resolve(__dirname, "..", "pkg", "index.d.ts"),
+  "utf8"
+);
+const missing = ["GameHandle", "DifficultyLevel", "KeyBindings", "GameConfig"].filter(
+  (name) => !new RegExp(`export (class|type|interface) ${name}\\b`).test(definitions)
+);
+
+if (missing.length > 0) {
+  console.error(`pkg/index.d.ts is missing ${missing.join(", ")}`);
+  process.exit(1);
+}
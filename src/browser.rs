@@ -1,12 +1,16 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use futures::channel::oneshot;
 use js_sys::ArrayBuffer;
+use std::cell::RefCell;
 use std::future::Future;
+use std::rc::Rc;
 use wasm_bindgen::closure::{Closure, WasmClosure, WasmClosureFnOnce};
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
     CanvasRenderingContext2d, Document, Element, HtmlCanvasElement, HtmlElement, HtmlImageElement,
-    Response, Window,
+    HtmlInputElement, HtmlTextAreaElement, IdbDatabase, IdbObjectStore, IdbRequest,
+    IdbTransactionMode, KeyboardEvent, MouseEvent, Response, Storage, VisibilityState, Window,
 };
 
 macro_rules! log {
@@ -26,6 +30,13 @@ pub fn document() -> Result<Document> {
         .ok_or_else(|| anyhow!("No Document Found"))
 }
 
+/// Does nothing without a document, the title is only for show.
+pub fn set_document_title(title: &str) {
+    if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+        document.set_title(title);
+    }
+}
+
 pub fn canvas() -> Result<HtmlCanvasElement> {
     document()?
         .get_element_by_id("canvas")
@@ -41,7 +52,28 @@ to HtmlCanvasElement",
 }
 
 pub fn context() -> Result<CanvasRenderingContext2d> {
-    canvas()?
+    context_for(&canvas()?)
+}
+
+pub fn create_canvas(width: u32, height: u32) -> Result<HtmlCanvasElement> {
+    let canvas = document()?
+        .create_element("canvas")
+        .map_err(|err| anyhow!("Could not create canvas {:#?}", err))?
+        .dyn_into::<HtmlCanvasElement>()
+        .map_err(|element| anyhow!("Error converting {:#?} to HtmlCanvasElement", element))?;
+    canvas.set_width(width);
+    canvas.set_height(height);
+    Ok(canvas)
+}
+
+pub fn data_url(canvas: &HtmlCanvasElement) -> Result<String> {
+    canvas
+        .to_data_url()
+        .map_err(|err| anyhow!("Could not read the canvas as a data URL {:#?}", err))
+}
+
+pub fn context_for(canvas: &HtmlCanvasElement) -> Result<CanvasRenderingContext2d> {
+    canvas
         .get_context("2d")
         .map_err(|js_value| {
             anyhow!(
@@ -68,6 +100,50 @@ where
     wasm_bindgen_futures::spawn_local(future);
 }
 
+const RETRY_BUTTON_ID: &str = "retry";
+
+/// Like `spawn_local` for the futures nothing awaits. If the future fails,
+/// the error is shown on the page with a button to reload it.
+pub fn spawn_local_reporting<F>(future: F)
+where
+    F: Future<Output = Result<(), JsValue>> + 'static,
+{
+    spawn_local(async move {
+        if let Err(err) = future.await {
+            let message = err.as_string().unwrap_or_else(|| format!("{:#?}", err));
+            if let Err(err) = draw_error_ui(&message) {
+                log!("Could not show the error {} {:#?}", message, err);
+            }
+        }
+    });
+}
+
+fn draw_error_ui(message: &str) -> Result<()> {
+    let ui = UiBuilder::new()
+        .with_css_class("error")
+        .heading("Something went wrong")
+        .text(message)
+        .button("Retry", RETRY_BUTTON_ID)
+        .build()?;
+    draw_ui(&ui)?;
+    let on_click = closure_once(|| {
+        if let Err(err) = reload() {
+            log!("Could not reload the page {:#?}", err);
+        }
+    });
+    expect_html_element_by_id(RETRY_BUTTON_ID)?
+        .set_onclick(Some(on_click.as_ref().unchecked_ref()));
+    on_click.forget();
+    Ok(())
+}
+
+fn reload() -> Result<()> {
+    window()?
+        .location()
+        .reload()
+        .map_err(|err| anyhow!("Could not reload {:#?}", err))
+}
+
 pub async fn fetch_with_str(resource: &str) -> Result<JsValue> {
     JsFuture::from(window()?.fetch_with_str(resource))
         .await
@@ -75,6 +151,10 @@ pub async fn fetch_with_str(resource: &str) -> Result<JsValue> {
 }
 pub async fn fetch_json(json_path: &str) -> Result<JsValue> {
     let resp = fetch_response(json_path).await?;
+    response_json(&resp).await
+}
+
+pub async fn response_json(resp: &Response) -> Result<JsValue> {
     JsFuture::from(
         resp.json()
             .map_err(|err| anyhow!("Could not get JSON from response {:#?}", err))?,
@@ -133,6 +213,116 @@ pub fn closure_wrap<T: WasmClosure + ?Sized>(data: Box<T>) -> Closure<T> {
     Closure::wrap(data)
 }
 
+pub fn add_visibility_change_handler(callback: Closure<dyn FnMut()>) -> Result<()> {
+    document()?
+        .add_event_listener_with_callback("visibilitychange", callback.as_ref().unchecked_ref())
+        .map_err(|err| anyhow!("Could not add visibilitychange listener {:#?}", err))?;
+    callback.forget();
+    Ok(())
+}
+
+pub fn add_pointer_lock_error_handler(callback: Closure<dyn FnMut()>) -> Result<()> {
+    document()?
+        .add_event_listener_with_callback("pointerlockerror", callback.as_ref().unchecked_ref())
+        .map_err(|err| anyhow!("Could not add pointerlockerror listener {:#?}", err))?;
+    callback.forget();
+    Ok(())
+}
+
+pub fn add_mouse_move_handler(callback: Closure<dyn FnMut(MouseEvent)>) -> Result<()> {
+    document()?
+        .add_event_listener_with_callback("mousemove", callback.as_ref().unchecked_ref())
+        .map_err(|err| anyhow!("Could not add mousemove listener {:#?}", err))?;
+    callback.forget();
+    Ok(())
+}
+
+/// Hides the mouse and keeps it from leaving the canvas, so it can be moved
+/// as far as the player likes. Only works right after the player pressed
+/// something, when it doesn't the `pointerlockerror` handler hears of it.
+pub fn request_pointer_lock() -> Result<()> {
+    canvas()?.request_pointer_lock();
+    Ok(())
+}
+
+pub fn exit_pointer_lock() -> Result<()> {
+    document()?.exit_pointer_lock();
+    Ok(())
+}
+
+fn matches_media(query: &str) -> Result<bool> {
+    Ok(window()?
+        .match_media(query)
+        .map_err(|err| anyhow!("Could not query media {:#?}", err))?
+        .map_or(false, |media_query| media_query.matches()))
+}
+
+pub fn prefers_reduced_motion() -> Result<bool> {
+    matches_media("(prefers-reduced-motion: reduce)")
+}
+
+/// True when the primary pointer is a finger rather than a mouse.
+pub fn is_touch_device() -> Result<bool> {
+    matches_media("(pointer: coarse)")
+}
+
+pub fn is_document_hidden() -> Result<bool> {
+    Ok(document()?.visibility_state() == VisibilityState::Hidden)
+}
+
+/// Legacy and layout dependent names mapped to their `KeyboardEvent.code`.
+const KEY_SYNONYMS: [(&str, &str); 16] = [
+    ("Right", "ArrowRight"),
+    ("Left", "ArrowLeft"),
+    ("Up", "ArrowUp"),
+    ("Down", "ArrowDown"),
+    (" ", "Space"),
+    ("Spacebar", "Space"),
+    ("Esc", "Escape"),
+    ("Del", "Delete"),
+    ("Apps", "ContextMenu"),
+    ("Scroll", "ScrollLock"),
+    ("Win", "MetaLeft"),
+    ("OS", "MetaLeft"),
+    ("Add", "NumpadAdd"),
+    ("Subtract", "NumpadSubtract"),
+    ("=", "Equal"),
+    ("-", "Minus"),
+];
+
+/// The physical key of `event`. Falls back to the logical key when the
+/// browser reports no code, as mobile virtual keyboards do.
+pub fn normalize_key_code(event: &KeyboardEvent) -> String {
+    normalize_key(&event.code(), &event.key())
+}
+
+/// Whether `event` was typed into a text field, like the leaderboard name,
+/// instead of being meant for the game.
+pub fn is_typing(event: &KeyboardEvent) -> bool {
+    event.target().map_or(false, |target| {
+        target.has_type::<HtmlInputElement>() || target.has_type::<HtmlTextAreaElement>()
+    })
+}
+
+fn normalize_key(code: &str, key: &str) -> String {
+    let name = if code.is_empty() { key } else { code };
+    if let Some((_synonym, code)) = KEY_SYNONYMS
+        .iter()
+        .find(|(synonym, _code)| *synonym == name)
+    {
+        return code.to_string();
+    }
+
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), None) if letter.is_ascii_alphabetic() => {
+            format!("Key{}", letter.to_ascii_uppercase())
+        }
+        (Some(digit), None) if digit.is_ascii_digit() => format!("Digit{}", digit),
+        _ => name.to_string(),
+    }
+}
+
 pub fn now() -> Result<f64> {
     Ok(window()?
         .performance()
@@ -140,10 +330,129 @@ pub fn now() -> Result<f64> {
         .now())
 }
 
-pub fn draw_ui(html: &str) -> Result<()> {
+pub fn draw_ui(ui: &HtmlElement) -> Result<()> {
     find_ui()?
-        .insert_adjacent_html("afterbegin", html)
-        .map_err(|err| anyhow!("Could not insert html {:#?}", err))
+        .insert_adjacent_element("afterbegin", ui)
+        .map(|_inserted| ())
+        .map_err(|err| anyhow!("Could not insert ui element {:#?}", err))
+}
+
+enum UiNode {
+    Heading(String),
+    Text(String),
+    ScoreLine(u32),
+    Button { text: String, id: String },
+    TextInput { placeholder: String, id: String },
+}
+
+/// Builds an overlay element by element. Text is only ever set with
+/// `set_text_content`, so it can never be interpreted as HTML.
+#[derive(Default)]
+pub struct UiBuilder {
+    nodes: Vec<UiNode>,
+    css_classes: Vec<String>,
+}
+
+impl UiBuilder {
+    pub fn new() -> Self {
+        UiBuilder::default()
+    }
+
+    pub fn heading(mut self, text: &str) -> Self {
+        self.nodes.push(UiNode::Heading(text.into()));
+        self
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.nodes.push(UiNode::Text(text.into()));
+        self
+    }
+
+    pub fn score_line(mut self, score: u32) -> Self {
+        self.nodes.push(UiNode::ScoreLine(score));
+        self
+    }
+
+    pub fn button(mut self, text: &str, id: &str) -> Self {
+        self.nodes.push(UiNode::Button {
+            text: text.into(),
+            id: id.into(),
+        });
+        self
+    }
+
+    pub fn text_input(mut self, placeholder: &str, id: &str) -> Self {
+        self.nodes.push(UiNode::TextInput {
+            placeholder: placeholder.into(),
+            id: id.into(),
+        });
+        self
+    }
+
+    /// Whether an element of the overlay will get `id`.
+    pub fn contains_id(&self, id: &str) -> bool {
+        self.nodes.iter().any(|node| match node {
+            UiNode::Button { id: node_id, .. } | UiNode::TextInput { id: node_id, .. } => {
+                node_id == id
+            }
+            UiNode::Heading(_) | UiNode::Text(_) | UiNode::ScoreLine(_) => false,
+        })
+    }
+
+    pub fn with_css_class(mut self, class: &str) -> Self {
+        self.css_classes.push(class.into());
+        self
+    }
+
+    pub fn build(self) -> Result<HtmlElement> {
+        let container = create_html_element("div")?;
+        container.set_class_name(&self.css_classes.join(" "));
+        for node in self.nodes {
+            let element = match node {
+                UiNode::Heading(text) => {
+                    let heading = create_html_element("h1")?;
+                    heading.set_text_content(Some(&text));
+                    heading
+                }
+                UiNode::Text(text) => {
+                    let paragraph = create_html_element("p")?;
+                    paragraph.set_text_content(Some(&text));
+                    paragraph
+                }
+                UiNode::ScoreLine(score) => {
+                    let score_line = create_html_element("p")?;
+                    score_line.set_text_content(Some(&format!("Score: {}", score)));
+                    score_line
+                }
+                UiNode::Button { text, id } => {
+                    let button = create_html_element("button")?;
+                    button.set_text_content(Some(&text));
+                    button.set_id(&id);
+                    button
+                }
+                UiNode::TextInput { placeholder, id } => {
+                    let input = create_html_element("input")?;
+                    input
+                        .set_attribute("placeholder", &placeholder)
+                        .map_err(|err| anyhow!("Could not set placeholder {:#?}", err))?;
+                    input.set_id(&id);
+                    input
+                }
+            };
+            container
+                .append_child(&element)
+                .map_err(|err| anyhow!("Could not append ui element {:#?}", err))?;
+        }
+        Ok(container)
+    }
+}
+
+fn create_html_element(tag: &str) -> Result<HtmlElement> {
+    document()?
+        .create_element(tag)
+        .map_err(|err| anyhow!("Could not create element {} {:#?}", tag, err))?
+        .dyn_into::<HtmlElement>()
+        .map_err(|err| anyhow!("Could not cast into HtmlElement {:#?}", err))
 }
 
 pub fn hide_ui() -> Result<()> {
@@ -170,15 +479,226 @@ fn find_ui() -> Result<Element> {
     })
 }
 
+/// Fails with the id in the error when there is no such element.
 pub fn find_html_element_by_id(id: &str) -> Result<HtmlElement> {
-    document()
-        .and_then(|doc| {
-            doc.get_element_by_id(id)
-                .ok_or_else(|| anyhow!("Element with id {} not found", id))
-        })
-        .and_then(|element| {
-            element
-                .dyn_into::<HtmlElement>()
-                .map_err(|err| anyhow!("Could not cast into HtmlElement {:#?}", err))
-        })
+    document()?
+        .get_element_by_id(id)
+        .ok_or_else(|| anyhow!("Element with id {} not found", id))?
+        .dyn_into::<HtmlElement>()
+        .map_err(|err| anyhow!("Element with id {} is no HtmlElement {:#?}", id, err))
+}
+
+/// For elements the game put there itself, missing one is a bug.
+pub fn expect_html_element_by_id(id: &str) -> Result<HtmlElement> {
+    find_html_element_by_id(id).with_context(|| format!("The game drew no element with id {}", id))
+}
+
+fn local_storage() -> Result<Storage> {
+    window()?
+        .local_storage()
+        .map_err(|err| anyhow!("Could not access localStorage {:#?}", err))?
+        .ok_or_else(|| anyhow!("No localStorage found"))
+}
+
+pub fn load_from_storage(key: &str) -> Result<Option<String>> {
+    local_storage()?
+        .get_item(key)
+        .map_err(|err| anyhow!("Could not read {} from localStorage {:#?}", key, err))
+}
+
+pub fn save_to_storage(key: &str, value: &str) -> Result<()> {
+    local_storage()?
+        .set_item(key, value)
+        .map_err(|err| anyhow!("Could not write {} to localStorage {:#?}", key, err))
+}
+
+pub fn parse_json(json: &str) -> Result<JsValue> {
+    js_sys::JSON::parse(json).map_err(|err| anyhow!("Could not parse JSON {:#?}", err))
+}
+
+pub fn stringify_json(value: &JsValue) -> Result<String> {
+    js_sys::JSON::stringify(value)
+        .map(String::from)
+        .map_err(|err| anyhow!("Could not stringify JSON {:#?}", err))
+}
+
+pub fn input_value(id: &str) -> Result<String> {
+    find_html_element_by_id(id)?
+        .dyn_into::<HtmlInputElement>()
+        .map(|input| input.value())
+        .map_err(|err| anyhow!("Could not cast into HtmlInputElement {:#?}", err))
+}
+
+const DATABASE_NAME: &str = "rusty-runner";
+// Bump this when a store is added, only an upgrade can create stores
+const DATABASE_VERSION: u32 = 1;
+
+/// Waits for an IndexedDB request to succeed and returns its result.
+async fn idb_request(request: &IdbRequest) -> Result<JsValue> {
+    let (complete_tx, complete_rx) = oneshot::channel::<Result<()>>();
+    let success_tx = Rc::new(RefCell::new(Some(complete_tx)));
+    let error_tx = Rc::clone(&success_tx);
+    let success_callback = closure_once(move || {
+        if let Some(success_tx) = success_tx.borrow_mut().take() {
+            let _ = success_tx.send(Ok(()));
+        }
+    });
+    let error_callback: Closure<dyn FnMut(JsValue)> = closure_once(move |err| {
+        if let Some(error_tx) = error_tx.borrow_mut().take() {
+            let _ = error_tx.send(Err(anyhow!("IndexedDB request failed {:#?}", err)));
+        }
+    });
+    request.set_onsuccess(Some(success_callback.as_ref().unchecked_ref()));
+    request.set_onerror(Some(error_callback.as_ref().unchecked_ref()));
+    complete_rx.await??;
+    request
+        .result()
+        .map_err(|err| anyhow!("Could not read IndexedDB result {:#?}", err))
+}
+
+/// Opens the game's database, creating `store` the first time.
+async fn open_database(store: &str) -> Result<IdbDatabase> {
+    let request = window()?
+        .indexed_db()
+        .map_err(|err| anyhow!("Could not access IndexedDB {:#?}", err))?
+        .ok_or_else(|| anyhow!("No IndexedDB found"))?
+        .open_with_u32(DATABASE_NAME, DATABASE_VERSION)
+        .map_err(|err| anyhow!("Could not open {} {:#?}", DATABASE_NAME, err))?;
+    let upgrade_request = request.clone();
+    let store_name = store.to_string();
+    let upgrade_callback: Closure<dyn FnMut(JsValue)> = closure_once(move |_event| {
+        let created = upgrade_request
+            .result()
+            .and_then(|database| database.dyn_into::<IdbDatabase>())
+            .and_then(|database| {
+                if database.object_store_names().contains(&store_name) {
+                    Ok(())
+                } else {
+                    database.create_object_store(&store_name).map(|_store| ())
+                }
+            });
+        if let Err(err) = created {
+            log!("Could not create store {} {:#?}", store_name, err);
+        }
+    });
+    request.set_onupgradeneeded(Some(upgrade_callback.as_ref().unchecked_ref()));
+    idb_request(&request)
+        .await?
+        .dyn_into::<IdbDatabase>()
+        .map_err(|err| anyhow!("Could not cast into IdbDatabase {:#?}", err))
+}
+
+async fn object_store(store: &str, mode: IdbTransactionMode) -> Result<IdbObjectStore> {
+    open_database(store)
+        .await?
+        .transaction_with_str_and_mode(store, mode)
+        .and_then(|transaction| transaction.object_store(store))
+        .map_err(|err| anyhow!("Could not open store {} {:#?}", store, err))
+}
+
+pub async fn idb_put(store: &str, key: &str, value: &JsValue) -> Result<()> {
+    let request = object_store(store, IdbTransactionMode::Readwrite)
+        .await?
+        .put_with_key(value, &JsValue::from_str(key))
+        .map_err(|err| anyhow!("Could not write {} to IndexedDB {:#?}", key, err))?;
+    idb_request(&request).await.map(|_result| ())
+}
+
+pub async fn idb_get(store: &str, key: &str) -> Result<Option<JsValue>> {
+    let request = object_store(store, IdbTransactionMode::Readonly)
+        .await?
+        .get(&JsValue::from_str(key))
+        .map_err(|err| anyhow!("Could not read {} from IndexedDB {:#?}", key, err))?;
+    let value = idb_request(&request).await?;
+    Ok(Some(value).filter(|value| !value.is_undefined()))
+}
+
+pub async fn idb_delete(store: &str, key: &str) -> Result<()> {
+    let request = object_store(store, IdbTransactionMode::Readwrite)
+        .await?
+        .delete(&JsValue::from_str(key))
+        .map_err(|err| anyhow!("Could not delete {} from IndexedDB {:#?}", key, err))?;
+    idb_request(&request).await.map(|_result| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn missing_element_is_an_error_naming_its_id() {
+        let err = expect_html_element_by_id("no_such_element").unwrap_err();
+        assert!(format!("{:#}", err).contains("no_such_element"));
+    }
+
+    #[wasm_bindgen_test]
+    fn errors_are_shown_as_text_with_a_working_retry_button() {
+        let document = document().unwrap();
+        let ui = document.create_element("div").unwrap();
+        ui.set_id("ui");
+        document.body().unwrap().append_child(&ui).unwrap();
+
+        draw_error_ui("<b>Failed to load</b>").unwrap();
+
+        let retry = find_html_element_by_id(RETRY_BUTTON_ID).unwrap();
+        assert_eq!(retry.tag_name(), "BUTTON");
+        assert!(retry.onclick().is_some());
+        // The markup in the message stays text
+        assert!(ui.query_selector("b").unwrap().is_none());
+        assert!(ui.text_content().unwrap().contains("<b>Failed to load</b>"));
+        ui.remove();
+    }
+
+    #[wasm_bindgen_test]
+    fn sets_the_document_title() {
+        set_document_title("Walk the Dog");
+        assert_eq!(document().unwrap().title(), "Walk the Dog");
+    }
+
+    #[wasm_bindgen_test]
+    fn keys_typed_into_a_text_field_are_typing() {
+        let document = document().unwrap();
+        let input = document.create_element("input").unwrap();
+        document.body().unwrap().append_child(&input).unwrap();
+        let event = KeyboardEvent::new_with_keyboard_event_init_dict(
+            "keydown",
+            web_sys::KeyboardEventInit::new().code("KeyR"),
+        )
+        .unwrap();
+        input.dispatch_event(&event).unwrap();
+        input.remove();
+        assert!(is_typing(&event));
+
+        let event = KeyboardEvent::new_with_keyboard_event_init_dict(
+            "keydown",
+            web_sys::KeyboardEventInit::new().code("KeyR"),
+        )
+        .unwrap();
+        document.body().unwrap().dispatch_event(&event).unwrap();
+        assert!(!is_typing(&event));
+    }
+
+    #[test]
+    fn prefers_the_physical_code() {
+        assert_eq!(normalize_key("KeyZ", "y"), "KeyZ");
+    }
+
+    #[test]
+    fn falls_back_to_the_key_without_a_code() {
+        assert_eq!(normalize_key("", "ArrowRight"), "ArrowRight");
+        assert_eq!(normalize_key("", "a"), "KeyA");
+        assert_eq!(normalize_key("", "7"), "Digit7");
+    }
+
+    #[test]
+    fn maps_common_synonyms() {
+        assert!(KEY_SYNONYMS.len() >= 10);
+        KEY_SYNONYMS.iter().for_each(|(synonym, code)| {
+            assert_eq!(normalize_key("", synonym), *code);
+            assert_eq!(normalize_key(synonym, ""), *code);
+        });
+        assert_eq!(normalize_key("", "Right"), "ArrowRight");
+        assert_eq!(normalize_key("", " "), "Space");
+    }
 }
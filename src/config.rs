@@ -0,0 +1,567 @@
+use crate::{browser, engine, game, segment};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+pub const STONE_AND_PLATFORM_SEGMENT: &str = "stone_and_platform";
+pub const OTHER_PLATFORM_SEGMENT: &str = "other_platform";
+pub const GLOWING_PLATFORM_SEGMENT: &str = "glowing_platform";
+pub const WINDY_PLATFORM_SEGMENT: &str = "windy_platform";
+pub const PIT_SEGMENT: &str = "pit";
+pub const HANGING_BARRIER_SEGMENT: &str = "hanging_barrier_run";
+pub const GAP_AND_PLATFORM_SEGMENT: &str = "gap_and_platform";
+pub const LONG_PLATFORM_SEGMENT: &str = "long_platform";
+/// Comes once per run at a set distance, never picked by weight.
+pub const BOSS_SEGMENT: &str = "boss";
+/// Nothing at all for `GameConfig::rest_duration`, it comes at set distances
+/// too.
+pub const REST_GAP_SEGMENT: &str = "rest_gap";
+/// The segments with a pit to fall down, the kids preset leaves them out.
+const PIT_SEGMENTS: [&str; 2] = [PIT_SEGMENT, GAP_AND_PLATFORM_SEGMENT];
+/// Longer rest gaps would run the timeline past what an `i16` holds.
+const MAX_REST_DURATION: i16 = 10000;
+
+const KIDS_SPEED_PERCENT: i16 = 75;
+const KIDS_GAP_PERCENT: i16 = 150;
+const KIDS_GRACE_PERCENT: i16 = 200;
+const KIDS_LIVES: u8 = 3;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_DEFS: &str = r#"
+/** Physical keys, as `KeyboardEvent.code`, for every action. */
+export interface KeyBindings {
+    run?: string;
+    jump?: string;
+    slide?: string;
+    dash?: string;
+    zoomIn?: string;
+    zoomOut?: string;
+    /** Abandons the run and goes back to the ready screen. */
+    quit?: string;
+    /** Starts a new game from the game over screen. */
+    restart?: string;
+    /** The keys from here on only work on the ready screen. */
+    hardMode?: string;
+    normalMode?: string;
+    reducedMotion?: string;
+    fullMotion?: string;
+    keyboardControls?: string;
+    touchControls?: string;
+    mouseControls?: string;
+    standardPreset?: string;
+    kidsPreset?: string;
+}
+
+/**
+ * How often a segment is picked, how hard it is from 0 up, and the range
+ * the gap before it is picked from.
+ */
+export interface SegmentEntry {
+    weight?: number;
+    difficulty?: number;
+    minBuffer?: number;
+    maxBuffer?: number;
+}
+
+/** Named bundles of tunings, laid over the rest of the config. */
+export type Preset = "standard" | "kids";
+
+export interface PhysicsConfig {
+    runningSpeed?: number;
+    jumpSpeed?: number;
+    maxVelocity?: number;
+    gravity?: number;
+    /** How much lower the top of the boy's hitbox is while sliding. */
+    slideBoxCrop?: number;
+}
+
+/** The shape of `config.json`. Every field is optional. */
+export interface GameConfig {
+    /** Changed in game or with `GameHandle.set_preset`, from the next run on. */
+    preset?: Preset;
+    startingLives?: number;
+    coinValue?: number;
+    speedRampDistance?: number;
+    timelineMinimum?: number;
+    /** No new segments while this many obstacles are around. */
+    maxObstacles?: number;
+    /** How far a rest gap without any obstacles stretches. */
+    restDuration?: number;
+    /** How far the boy runs before anything can hit him. */
+    graceDistance?: number;
+    segments?: Record<string, SegmentEntry>;
+    debugMode?: boolean;
+    renderScale?: number;
+    /** The most frames drawn a second, 0 draws as many as the display shows. */
+    targetFps?: number;
+    /** Smooths images the render scale stretches. Off keeps pixel art crisp. */
+    imageSmoothing?: boolean;
+    reducedMotion?: boolean;
+    /** The boy slows down to a stop unless run is held. */
+    holdToRun?: boolean;
+    /** How the boy is steered. Touch devices default to touch, others to keyboard. */
+    controlScheme?: "keyboard" | "touch" | "mouse";
+    dirtyRectRendering?: boolean;
+    /** Background images, in the order they take turns. */
+    backgroundThemes?: string[];
+    themeDistance?: number;
+    /** WebSocket URL of the leaderboard server. No leaderboard without it. */
+    leaderboardUrl?: string;
+    physics?: PhysicsConfig;
+    keyBindings?: KeyBindings;
+}
+"#;
+
+/// Everything that tunes a run. Every field is optional in `config.json`,
+/// missing fields fall back to their defaults.
+#[derive(Deserialize, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GameConfig {
+    /// Tuned into the rest of the fields by `Preset::apply`.
+    pub preset: Preset,
+    /// How often the boy gets up again before the game is over.
+    pub starting_lives: u8,
+    pub coin_value: u32,
+    pub speed_ramp_distance: u32,
+    pub timeline_minimum: i16,
+    /// Generation waits while this many obstacles are live, so a run can't
+    /// pile them up however long it goes.
+    pub max_obstacles: usize,
+    /// How far the boy runs through a rest gap before the next segment.
+    pub rest_duration: i16,
+    /// How far the boy runs at the start of a run, or after a continue,
+    /// before anything can hit him.
+    pub grace_distance: i16,
+    #[serde(alias = "segmentWeights", deserialize_with = "segments_or_weights")]
+    pub segments: HashMap<String, SegmentEntry>,
+    pub debug_mode: bool,
+    pub render_scale: f64,
+    /// The most frames drawn a second, lower saves power. 0 draws every
+    /// frame the display shows, the game speed is the same either way.
+    pub target_fps: u32,
+    /// Smooths images the render scale stretches, off keeps pixel art crisp.
+    pub image_smoothing: bool,
+    pub reduced_motion: bool,
+    /// Off, the boy runs on by himself once started. On, he slows down to a
+    /// stop whenever run isn't held.
+    pub hold_to_run: bool,
+    /// `None` picks touch on touch devices and the keyboard everywhere else.
+    pub control_scheme: Option<ControlScheme>,
+    /// Only redraws what changed on the ready and game over screens, for
+    /// slow devices.
+    pub dirty_rect_rendering: bool,
+    pub background_themes: Vec<String>,
+    /// How far the boy runs before the next background theme fades in.
+    pub theme_distance: u32,
+    pub leaderboard_url: Option<String>,
+    pub physics: PhysicsConfig,
+    pub key_bindings: KeyBindings,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            preset: Preset::default(),
+            starting_lives: 1,
+            coin_value: 1,
+            speed_ramp_distance: 1000,
+            timeline_minimum: 1000,
+            max_obstacles: 200,
+            rest_duration: 1500,
+            grace_distance: game::GRACE_DISTANCE,
+            segments: [
+                (STONE_AND_PLATFORM_SEGMENT, 2),
+                (OTHER_PLATFORM_SEGMENT, 1),
+                (GLOWING_PLATFORM_SEGMENT, 0),
+                (WINDY_PLATFORM_SEGMENT, 1),
+                (PIT_SEGMENT, 2),
+                (HANGING_BARRIER_SEGMENT, 2),
+                (GAP_AND_PLATFORM_SEGMENT, 2),
+                (LONG_PLATFORM_SEGMENT, 1),
+            ]
+            .iter()
+            .map(|(segment, difficulty)| {
+                (
+                    segment.to_string(),
+                    SegmentEntry {
+                        difficulty: *difficulty,
+                        ..SegmentEntry::default()
+                    },
+                )
+            })
+            .collect(),
+            debug_mode: false,
+            render_scale: 1.0,
+            target_fps: engine::DEFAULT_TARGET_FPS,
+            image_smoothing: false,
+            reduced_motion: false,
+            hold_to_run: false,
+            control_scheme: None,
+            dirty_rect_rendering: false,
+            background_themes: vec!["BG.png".into()],
+            theme_distance: 5000,
+            leaderboard_url: None,
+            physics: PhysicsConfig::default(),
+            key_bindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl GameConfig {
+    /// Loads the config from `path`, or the defaults if there is no such file.
+    /// Reduced motion is switched on when the player asks for it in their
+    /// system settings.
+    pub async fn load(path: &str) -> Result<Self> {
+        let response = browser::fetch_response(path).await?;
+        let mut config = if response.status() == 404 {
+            GameConfig::default()
+        } else {
+            browser::response_json(&response).await?.into_serde()?
+        };
+        config.reduced_motion |= browser::prefers_reduced_motion().unwrap_or(false);
+        config.validate();
+        Ok(config)
+    }
+
+    /// Warns about segments that leave too little room to land before them.
+    /// They are still generated as configured. A `rest_duration` out of range
+    /// is clamped into it, and `max_obstacles` raised to fit every segment.
+    fn validate(&mut self) {
+        let minimum_safe_buffer = self.physics.minimum_safe_buffer();
+        self.segments
+            .iter()
+            .filter(|(_segment, entry)| entry.min_buffer < minimum_safe_buffer)
+            .for_each(|(segment, entry)| {
+                log!(
+                    "Warning: the minBuffer {} of {} is below the minimum safe buffer {}",
+                    entry.min_buffer,
+                    segment,
+                    minimum_safe_buffer
+                )
+            });
+        let rest_duration = self.rest_duration.clamp(0, MAX_REST_DURATION);
+        if rest_duration != self.rest_duration {
+            log!(
+                "Warning: the restDuration {} is out of range, using {}",
+                self.rest_duration,
+                rest_duration
+            );
+            self.rest_duration = rest_duration;
+        }
+        if self.max_obstacles < segment::MAX_SEGMENT_OBSTACLES {
+            log!(
+                "Warning: the maxObstacles {} are too few for some segments, using {}",
+                self.max_obstacles,
+                segment::MAX_SEGMENT_OBSTACLES
+            );
+            self.max_obstacles = segment::MAX_SEGMENT_OBSTACLES;
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SegmentEntry {
+    pub weight: u32,
+    pub difficulty: u8,
+    /// The gap before the segment is picked at random between these two.
+    pub min_buffer: i16,
+    pub max_buffer: i16,
+}
+
+impl Default for SegmentEntry {
+    fn default() -> Self {
+        SegmentEntry {
+            weight: 1,
+            difficulty: 0,
+            min_buffer: 210,
+            max_buffer: 360,
+        }
+    }
+}
+
+/// Configs from before segments had a difficulty call them `segmentWeights`
+/// and only give each a weight, those still load with the default rest.
+fn segments_or_weights<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, SegmentEntry>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Segment {
+        Weight(u32),
+        Entry(SegmentEntry),
+    }
+
+    let segments = HashMap::<String, Segment>::deserialize(deserializer)?;
+    Ok(segments
+        .into_iter()
+        .map(|(name, segment)| match segment {
+            Segment::Weight(weight) => (
+                name,
+                SegmentEntry {
+                    weight,
+                    ..SegmentEntry::default()
+                },
+            ),
+            Segment::Entry(entry) => (name, entry),
+        })
+        .collect())
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PhysicsConfig {
+    pub running_speed: i16,
+    pub jump_speed: i16,
+    pub max_velocity: i16,
+    pub gravity: i16,
+    /// How much lower the top of the boy's hitbox is while sliding.
+    pub slide_box_crop: i16,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        PhysicsConfig {
+            running_speed: 4,
+            jump_speed: -25,
+            max_velocity: 20,
+            gravity: 1,
+            slide_box_crop: 30,
+        }
+    }
+}
+
+impl PhysicsConfig {
+    /// Sets the value called `name`, as in the config file, rounded and
+    /// clamped to what the game can cope with.
+    pub fn set(&mut self, name: &str, value: f64) -> Result<()> {
+        let (field, min, max) = match name {
+            "runningSpeed" => (&mut self.running_speed, 1, 20),
+            // Upwards, or the boy never leaves the ground
+            "jumpSpeed" => (&mut self.jump_speed, -60, -1),
+            "maxVelocity" => (&mut self.max_velocity, 1, 60),
+            "gravity" => (&mut self.gravity, 1, 10),
+            "slideBoxCrop" => (&mut self.slide_box_crop, 0, 60),
+            _ => return Err(anyhow!("There is no physics parameter {}", name)),
+        };
+        if !value.is_finite() {
+            return Err(anyhow!("{} can't be set to {}", name, value));
+        }
+        *field = value.round().clamp(min.into(), max.into()) as i16;
+        Ok(())
+    }
+
+    /// How far a running boy gets with one jump from the floor back down to
+    /// it, at `speed` pixels per update.
+    pub fn jump_reach(&self, speed: i16) -> i16 {
+        // Gives up on jumps that never come down
+        const MAX_AIRTIME: i32 = 1000;
+        let mut height = 0;
+        let mut velocity = i32::from(self.jump_speed);
+        let mut airtime = 0;
+        while airtime < MAX_AIRTIME {
+            height += velocity;
+            velocity = (velocity + i32::from(self.gravity)).min(self.max_velocity.into());
+            airtime += 1;
+            if height >= 0 {
+                break;
+            }
+        }
+        (airtime * i32::from(speed)).clamp(0, i16::MAX.into()) as i16
+    }
+
+    /// The gap a running boy needs to land from a jump over one segment
+    /// before the next one starts.
+    pub fn minimum_safe_buffer(&self) -> i16 {
+        self.jump_reach(self.running_speed)
+    }
+}
+
+/// A named bundle of tunings, laid over what `config.json` says. Changing
+/// it only ever changes the runs started afterwards.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Preset {
+    /// `config.json` as it is.
+    Standard,
+    /// Fewer obstacles for kids: a slower boy, wider gaps, extra lives, a
+    /// longer grace distance and no pits to fall down.
+    Kids,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset::Standard
+    }
+}
+
+impl Preset {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "standard" => Ok(Preset::Standard),
+            "kids" => Ok(Preset::Kids),
+            _ => Err(anyhow!("There is no preset {}", name)),
+        }
+    }
+
+    /// `config` tuned for the preset. The tunings are relative to it, so
+    /// they have to be applied to the config without any preset.
+    pub fn apply(self, config: &GameConfig) -> GameConfig {
+        let mut tuned = GameConfig {
+            preset: self,
+            ..config.clone()
+        };
+        if self == Preset::Kids {
+            tuned.physics.running_speed =
+                percent_of(config.physics.running_speed, KIDS_SPEED_PERCENT).max(1);
+            // The lightest gravity there is, the jumps last longest
+            tuned.physics.gravity = 1;
+            tuned.starting_lives = config.starting_lives.max(KIDS_LIVES);
+            tuned.grace_distance = percent_of(config.grace_distance, KIDS_GRACE_PERCENT);
+            tuned.segments.iter_mut().for_each(|(segment, entry)| {
+                entry.min_buffer = percent_of(entry.min_buffer, KIDS_GAP_PERCENT);
+                entry.max_buffer = percent_of(entry.max_buffer, KIDS_GAP_PERCENT);
+                if PIT_SEGMENTS.contains(&segment.as_str()) {
+                    entry.weight = 0;
+                }
+            });
+        }
+        tuned
+    }
+}
+
+fn percent_of(value: i16, percent: i16) -> i16 {
+    (i32::from(value) * i32::from(percent) / 100).clamp(i16::MIN.into(), i16::MAX.into()) as i16
+}
+
+/// How the player steers the boy.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ControlScheme {
+    Keyboard,
+    /// Virtual buttons drawn on the canvas.
+    Touch,
+    /// Moving the mouse right runs, up jumps and down slides.
+    Mouse,
+}
+
+/// Physical keys, as `KeyboardEvent.code`, for every action.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct KeyBindings {
+    pub run: String,
+    pub jump: String,
+    pub slide: String,
+    pub dash: String,
+    pub zoom_in: String,
+    pub zoom_out: String,
+    /// Abandons the run and goes back to the ready screen.
+    pub quit: String,
+    /// Starts a new game from the game over screen, like its button.
+    pub restart: String,
+    /// The keys from here on only work on the ready screen.
+    pub hard_mode: String,
+    pub normal_mode: String,
+    pub reduced_motion: String,
+    pub full_motion: String,
+    pub keyboard_controls: String,
+    pub touch_controls: String,
+    pub mouse_controls: String,
+    pub standard_preset: String,
+    pub kids_preset: String,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            run: "ArrowRight".into(),
+            jump: "Space".into(),
+            slide: "ArrowDown".into(),
+            dash: "ShiftLeft".into(),
+            zoom_in: "Equal".into(),
+            zoom_out: "Minus".into(),
+            quit: "Escape".into(),
+            restart: "KeyR".into(),
+            hard_mode: "KeyH".into(),
+            normal_mode: "KeyN".into(),
+            reduced_motion: "KeyM".into(),
+            full_motion: "KeyF".into(),
+            keyboard_controls: "Digit1".into(),
+            touch_controls: "Digit2".into(),
+            mouse_controls: "Digit3".into(),
+            standard_preset: "KeyS".into(),
+            kids_preset: "KeyK".into(),
+        }
+    }
+}
+
+const SETTINGS_KEY: &str = "walk_the_dog_settings";
+// Bump whenever the fields change, older saves are then ignored
+const SETTINGS_VERSION: u32 = 2;
+
+/// What the player chose in game, kept in localStorage across page loads.
+/// Saved settings win over `config.json`.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Settings {
+    version: u32,
+    pub hard_mode: bool,
+    // Saved before there were presets, those settings keep the standard one
+    #[serde(default)]
+    pub preset: Preset,
+    pub reduced_motion: bool,
+    pub render_scale: f64,
+    pub control_scheme: Option<ControlScheme>,
+    pub key_bindings: KeyBindings,
+}
+
+impl Settings {
+    pub fn new(config: &GameConfig, hard_mode: bool) -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            hard_mode,
+            preset: config.preset,
+            reduced_motion: config.reduced_motion,
+            render_scale: config.render_scale,
+            control_scheme: config.control_scheme,
+            key_bindings: config.key_bindings.clone(),
+        }
+    }
+
+    pub fn apply_to(&self, config: &mut GameConfig) {
+        config.preset = self.preset;
+        config.reduced_motion = self.reduced_motion;
+        config.render_scale = self.render_scale;
+        config.control_scheme = self.control_scheme.or(config.control_scheme);
+        config.key_bindings = self.key_bindings.clone();
+    }
+}
+
+/// The saved settings, or `None` when there are none or they can't be used,
+/// for example because another version of the game saved them. The
+/// defaults apply then.
+pub fn load_settings() -> Option<Settings> {
+    let saved = browser::load_from_storage(SETTINGS_KEY).and_then(|json| {
+        json.map(|json| {
+            browser::parse_json(&json)?
+                .into_serde::<Settings>()
+                .map_err(|err| anyhow!("Could not read settings {:#?}", err))
+        })
+        .transpose()
+    });
+    match saved {
+        Ok(Some(settings)) if settings.version == SETTINGS_VERSION => Some(settings),
+        Ok(_) => None,
+        Err(err) => {
+            log!("Ignoring saved settings {:#?}", err);
+            None
+        }
+    }
+}
+
+pub fn save_settings(settings: &Settings) -> Result<()> {
+    let value = JsValue::from_serde(settings)
+        .map_err(|err| anyhow!("Could not serialize settings {:#?}", err))?;
+    browser::save_to_storage(SETTINGS_KEY, &browser::stringify_json(&value)?)
+}
@@ -1,11 +1,16 @@
 use crate::{browser, sound};
 use async_trait::async_trait;
 use futures::channel::oneshot::channel;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::cell::{self, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::rc::Rc;
 use std::sync::Mutex;
-use web_sys::{AudioBuffer, AudioContext, CanvasRenderingContext2d, HtmlElement, HtmlImageElement};
+use web_sys::{
+    AudioBuffer, AudioBufferSourceNode, AudioContext, CanvasRenderingContext2d, GainNode,
+    HtmlCanvasElement, HtmlElement, HtmlImageElement,
+};
 
 use crate::browser::LoopClosure;
 use crate::game::{Cell, Point, Sheet};
@@ -26,43 +31,288 @@ pub fn add_click_handler(elem: HtmlElement) -> UnboundedReceiver<()> {
     click_receiver
 }
 
+pub trait AudioBackend {
+    fn play_sound(&self, sound: &WebAudioSound) -> Result<()>;
+    fn play_looping_sound(&self, sound: &WebAudioSound) -> Result<()>;
+    fn play_sound_at(&self, sound: &WebAudioSound, x: f64, screen_width: f64) -> Result<()>;
+    fn set_volume(&self, volume: f32);
+    fn suspend(&self) -> Result<()>;
+    fn resume(&self) -> Result<()>;
+    fn mute(&self) -> Result<()>;
+    fn unmute(&self) -> Result<()>;
+
+    /// Starts `sound` as the music, replacing any music already playing.
+    /// Without volume control, it can only start at full volume.
+    fn play_looping_sound_fade_in(&self, sound: &WebAudioSound, _duration_ms: f64) -> Result<()> {
+        self.play_looping_sound(sound)
+    }
+
+    /// Fades the music out and stops it. Without volume control, the music
+    /// keeps playing.
+    fn fade_out(&self, _duration_ms: f64) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn stereo_pan(x: f64, screen_width: f64) -> f32 {
+    ((2.0 * x / screen_width) - 1.0).clamp(-1.0, 1.0) as f32
+}
+
+async fn load_sound(context: &AudioContext, filename: &str) -> Result<WebAudioSound> {
+    let array_buffer = browser::fetch_array_buffer(filename).await?;
+    let audio_buffer = sound::decode_audio_data(context, &array_buffer).await?;
+    Ok(WebAudioSound::new(audio_buffer))
+}
+
+/// Plays every sound straight into the context destination, without any
+/// volume control.
 #[derive(Clone)]
 pub struct Audio {
-    context: AudioContext,
+    inner: AudioImpl,
+}
+
+#[derive(Clone)]
+enum AudioImpl {
+    Real(AudioContext),
+    /// Plays nothing, for tests and headless runs without an `AudioContext`.
+    #[cfg(any(test, feature = "noop-audio"))]
+    Noop,
 }
 
 impl Audio {
     pub fn new() -> Result<Self> {
         Ok(Audio {
-            context: sound::create_audio_context()?,
+            inner: AudioImpl::Real(sound::create_audio_context()?),
         })
     }
 
-    pub async fn load_sound(&self, filename: &str) -> Result<Sound> {
-        let array_buffer = browser::fetch_array_buffer(filename).await?;
-        let audio_buffer = sound::decode_audio_data(&self.context, &array_buffer).await?;
-        Ok(Sound {
-            buffer: audio_buffer,
+    /// An `Audio` whose every call does nothing and succeeds, the sounds it
+    /// loads are noops as well.
+    #[cfg(any(test, feature = "noop-audio"))]
+    pub fn new_noop() -> Self {
+        Audio {
+            inner: AudioImpl::Noop,
+        }
+    }
+
+    fn context(&self) -> Option<&AudioContext> {
+        match &self.inner {
+            AudioImpl::Real(context) => Some(context),
+            #[cfg(any(test, feature = "noop-audio"))]
+            AudioImpl::Noop => None,
+        }
+    }
+
+    pub async fn load_sound(&self, filename: &str) -> Result<WebAudioSound> {
+        match self.context() {
+            Some(context) => load_sound(context, filename).await,
+            None => Ok(WebAudioSound { buffer: None }),
+        }
+    }
+
+    fn play(&self, sound: &WebAudioSound, looping: sound::LOOPING) -> Result<()> {
+        match (self.context(), &sound.buffer) {
+            (Some(context), Some(buffer)) => {
+                sound::play_sound(context, buffer, &context.destination(), looping)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl AudioBackend for Audio {
+    fn play_sound(&self, sound: &WebAudioSound) -> Result<()> {
+        self.play(sound, sound::LOOPING::NO)
+    }
+
+    fn play_looping_sound(&self, sound: &WebAudioSound) -> Result<()> {
+        self.play(sound, sound::LOOPING::YES)
+    }
+
+    fn play_sound_at(&self, sound: &WebAudioSound, x: f64, screen_width: f64) -> Result<()> {
+        match (self.context(), &sound.buffer) {
+            (Some(context), Some(buffer)) => {
+                let panner = sound::create_stereo_panner(
+                    context,
+                    stereo_pan(x, screen_width),
+                    &context.destination(),
+                )?;
+                sound::play_sound(context, buffer, &panner, sound::LOOPING::NO)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn set_volume(&self, _volume: f32) {}
+
+    fn suspend(&self) -> Result<()> {
+        self.context().map_or(Ok(()), sound::suspend)
+    }
+
+    fn resume(&self) -> Result<()> {
+        self.context().map_or(Ok(()), sound::resume)
+    }
+
+    // Without a gain node, muting has to stop the whole context
+    fn mute(&self) -> Result<()> {
+        self.suspend()
+    }
+
+    fn unmute(&self) -> Result<()> {
+        self.resume()
+    }
+}
+
+/// Looping music with a gain node of its own, so it can fade in and out
+/// without touching the other sounds.
+struct Music {
+    source: AudioBufferSourceNode,
+    gain: GainNode,
+}
+
+/// Routes every sound through a master gain node, so the overall volume
+/// can be changed while sounds are playing.
+#[derive(Clone)]
+pub struct WebAudioEngine {
+    context: AudioContext,
+    master_gain: GainNode,
+    volume: Rc<cell::Cell<f32>>,
+    music: Rc<RefCell<Option<Music>>>,
+}
+
+impl WebAudioEngine {
+    pub fn new() -> Result<Self> {
+        let context = sound::create_audio_context()?;
+        let master_gain = sound::create_gain(&context)?;
+        Ok(WebAudioEngine {
+            context,
+            master_gain,
+            volume: Rc::new(cell::Cell::new(1.0)),
+            music: Rc::new(RefCell::new(None)),
         })
     }
 
-    pub fn play_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::NO)
+    pub async fn load_sound(&self, filename: &str) -> Result<WebAudioSound> {
+        load_sound(&self.context, filename).await
     }
 
-    pub fn play_looping_sound(&self, sound: &Sound) -> Result<()> {
-        sound::play_sound(&self.context, &sound.buffer, sound::LOOPING::YES)
+    fn play(&self, sound: &WebAudioSound, looping: sound::LOOPING) -> Result<()> {
+        match &sound.buffer {
+            Some(buffer) => sound::play_sound(&self.context, buffer, &self.master_gain, looping),
+            None => Ok(()),
+        }
     }
 }
 
+impl AudioBackend for WebAudioEngine {
+    fn play_sound(&self, sound: &WebAudioSound) -> Result<()> {
+        self.play(sound, sound::LOOPING::NO)
+    }
+
+    fn play_looping_sound(&self, sound: &WebAudioSound) -> Result<()> {
+        self.play(sound, sound::LOOPING::YES)
+    }
+
+    fn play_sound_at(&self, sound: &WebAudioSound, x: f64, screen_width: f64) -> Result<()> {
+        match &sound.buffer {
+            Some(buffer) => {
+                let panner = sound::create_stereo_panner(
+                    &self.context,
+                    stereo_pan(x, screen_width),
+                    &self.master_gain,
+                )?;
+                sound::play_sound(&self.context, buffer, &panner, sound::LOOPING::NO)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+        self.master_gain.gain().set_value(volume);
+    }
+
+    fn suspend(&self) -> Result<()> {
+        sound::suspend(&self.context)
+    }
+
+    fn resume(&self) -> Result<()> {
+        sound::resume(&self.context)
+    }
+
+    fn mute(&self) -> Result<()> {
+        self.master_gain.gain().set_value(0.0);
+        Ok(())
+    }
+
+    fn unmute(&self) -> Result<()> {
+        self.master_gain.gain().set_value(self.volume.get());
+        Ok(())
+    }
+
+    fn play_looping_sound_fade_in(&self, sound: &WebAudioSound, duration_ms: f64) -> Result<()> {
+        if let Some(music) = self.music.borrow_mut().take() {
+            sound::stop_sound(&self.context, &music.source, 0.0)?;
+        }
+        let buffer = match &sound.buffer {
+            Some(buffer) => buffer,
+            None => return Ok(()),
+        };
+        let gain = sound::create_gain_to(&self.context, &self.master_gain)?;
+        gain.gain().set_value(0.0);
+        let source = sound::start_sound(&self.context, buffer, &gain, sound::LOOPING::YES)?;
+        sound::ramp_gain(&self.context, &gain, 1.0, duration_ms / 1000.0)?;
+        self.music.replace(Some(Music { source, gain }));
+        Ok(())
+    }
+
+    fn fade_out(&self, duration_ms: f64) -> Result<()> {
+        match self.music.borrow_mut().take() {
+            Some(music) => {
+                let duration = duration_ms / 1000.0;
+                sound::ramp_gain(&self.context, &music.gain, 0.0, duration)?;
+                sound::stop_sound(&self.context, &music.source, duration)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Every backend plays a sound without a buffer as silence.
 #[derive(Clone)]
-pub struct Sound {
-    buffer: AudioBuffer,
+pub struct WebAudioSound {
+    buffer: Option<AudioBuffer>,
+}
+
+impl WebAudioSound {
+    pub fn new(buffer: AudioBuffer) -> Self {
+        WebAudioSound {
+            buffer: Some(buffer),
+        }
+    }
+
+    /// A sound with nothing to play, it needs no `AudioContext` to make.
+    #[cfg(any(test, feature = "noop-audio"))]
+    pub fn new_noop() -> Self {
+        WebAudioSound { buffer: None }
+    }
+}
+
+const TINT_ALPHA: f64 = 0.3;
+
+/// Anything that can put itself on the canvas.
+pub trait Drawable {
+    fn draw(&self, renderer: &Renderer);
 }
 
 pub struct Image {
     element: HtmlImageElement,
     bounding_box: Rect,
+    /// Drawn this much bigger or smaller around its center, the bounding
+    /// box stays as it is.
+    pub scale: f64,
+    /// A CSS color washed over the image.
+    pub tint: Option<String>,
 }
 
 impl Image {
@@ -71,6 +321,8 @@ impl Image {
         Self {
             element,
             bounding_box,
+            scale: 1.0,
+            tint: None,
         }
     }
 
@@ -78,8 +330,25 @@ impl Image {
         &self.bounding_box
     }
 
+    pub fn element(&self) -> &HtmlImageElement {
+        &self.element
+    }
+
     pub fn draw(&self, renderer: &Renderer) {
-        renderer.draw_entire_image(&self.element, &self.bounding_box.position)
+        renderer.draw_image_scaled(&self.element, &self.bounding_box.scaled(self.scale));
+        if let Some(tint) = &self.tint {
+            renderer.draw_alpha_rect(&self.bounding_box.scaled(self.scale), tint, TINT_ALPHA);
+        }
+    }
+
+    /// Draws `element` in place of this image, stretched to its size.
+    pub fn draw_in_place_of(&self, renderer: &Renderer, element: &HtmlImageElement, alpha: f64) {
+        renderer.draw_image_with_alpha(
+            element,
+            &Rect::new_from_x_y(0, 0, element.width() as i16, element.height() as i16),
+            &self.bounding_box,
+            alpha,
+        );
     }
 
     pub fn move_horizontally(&mut self, distance: i16) {
@@ -90,12 +359,85 @@ impl Image {
         self.bounding_box.set_x(x);
     }
 
+    pub fn set_position(&mut self, position: Point) {
+        self.bounding_box.position = position;
+    }
+
     pub fn right(&self) -> i16 {
         self.bounding_box.right()
     }
 }
 
-pub async fn load_image(source: &str) -> Result<HtmlImageElement> {
+impl<T: Drawable + ?Sized> Drawable for Box<T> {
+    fn draw(&self, renderer: &Renderer) {
+        (**self).draw(renderer);
+    }
+}
+
+impl Drawable for Image {
+    fn draw(&self, renderer: &Renderer) {
+        Image::draw(self, renderer);
+    }
+}
+
+const PREFERRED_IMAGE_FORMATS: [&str; 2] = ["avif", "webp"];
+
+thread_local! {
+    /// Every image loaded so far, by name. Clones are the same element,
+    /// already decoded.
+    static IMAGE_CACHE: RefCell<HashMap<String, HtmlImageElement>> = RefCell::new(HashMap::new());
+}
+
+/// The image named `name`, fetched only the first time it is asked for.
+/// Failed loads aren't kept, the next call tries again.
+pub async fn load_image(name: &str) -> Result<HtmlImageElement> {
+    if let Some(image) = IMAGE_CACHE.with(|cache| cache.borrow().get(name).cloned()) {
+        return Ok(image);
+    }
+    let image = first_loaded(image_sources(name), |source| async move {
+        fetch_image(&source).await
+    })
+    .await?;
+    IMAGE_CACHE.with(|cache| cache.borrow_mut().insert(name.to_string(), image.clone()));
+    Ok(image)
+}
+
+/// Where the image named `name` may be, best first. A base name or a PNG
+/// is tried in each of `PREFERRED_IMAGE_FORMATS` before the PNG, every
+/// other file only as it is.
+fn image_sources(name: &str) -> Vec<String> {
+    let file = name.rsplit('/').next().unwrap_or(name);
+    let base = match name.strip_suffix(".png") {
+        Some(base) => base,
+        None if !file.contains('.') => name,
+        None => return vec![name.to_string()],
+    };
+    PREFERRED_IMAGE_FORMATS
+        .iter()
+        .chain(["png"].iter())
+        .map(|extension| format!("{}.{}", base, extension))
+        .collect()
+}
+
+/// The first of `sources` that `fetch` loads. A missing format or one the
+/// browser can't decode just moves on to the next, only the last failure
+/// is reported.
+async fn first_loaded<T, F, Fut>(sources: Vec<String>, fetch: F) -> Result<T>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut last_error = anyhow!("There is no source to load from");
+    for source in sources {
+        match fetch(source).await {
+            Ok(loaded) => return Ok(loaded),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+async fn fetch_image(source: &str) -> Result<HtmlImageElement> {
     let image = browser::new_image()?;
     let (complete_tx, complete_rx) = channel::<Result<()>>();
     let success_tx = Rc::new(Mutex::new(Some(complete_tx)));
@@ -135,44 +477,301 @@ impl SpriteSheet {
         self.sheet.frames.get(name)
     }
 
+    pub fn cells_by_prefix<'a>(&'a self, prefix: &str) -> Vec<&'a Cell> {
+        self.sheet.cells_by_prefix(prefix)
+    }
+
+    pub fn frame_count_for(&self, animation: &str) -> u8 {
+        self.sheet.frame_count_for(animation)
+    }
+
     pub fn draw(&self, renderer: &Renderer, source: &Rect, destination: &Rect) {
         renderer.draw_image(&self.image, source, destination);
     }
 }
 
+#[cfg(feature = "pixel-perfect-collision")]
+impl SpriteSheet {
+    /// The mask of `sprite`, see `alpha_mask`.
+    pub fn alpha_mask(sprite: &Cell, image: &HtmlImageElement) -> Result<Rc<AlphaMask>> {
+        alpha_mask(image, &sprite.frame_rect())
+    }
+}
+
+#[cfg(feature = "pixel-perfect-collision")]
+thread_local! {
+    /// Every mask built so far, by image source and the part of the image
+    /// it covers.
+    static ALPHA_MASK_CACHE: RefCell<HashMap<(String, [i16; 4]), Rc<AlphaMask>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// The mask of `source` in `image`. Its pixels are only read back the
+/// first time, which is slow, later calls get the same mask.
+#[cfg(feature = "pixel-perfect-collision")]
+pub fn alpha_mask(image: &HtmlImageElement, source: &Rect) -> Result<Rc<AlphaMask>> {
+    let key = (
+        image.src(),
+        [source.x(), source.y(), source.width, source.height],
+    );
+    if let Some(mask) = ALPHA_MASK_CACHE.with(|cache| cache.borrow().get(&key).cloned()) {
+        return Ok(mask);
+    }
+    let pixels = get_pixel_data(image, source)?;
+    let mask = Rc::new(AlphaMask::from_rgba(source.width, source.height, &pixels));
+    ALPHA_MASK_CACHE.with(|cache| cache.borrow_mut().insert(key, Rc::clone(&mask)));
+    Ok(mask)
+}
+
+/// Reads the RGBA pixels of `source` by drawing it onto an offscreen canvas.
+#[cfg(feature = "pixel-perfect-collision")]
+fn get_pixel_data(image: &HtmlImageElement, source: &Rect) -> Result<Vec<u8>> {
+    let canvas = browser::create_canvas(source.width as u32, source.height as u32)?;
+    let renderer = Renderer::new(browser::context_for(&canvas)?);
+    renderer.draw_image(
+        image,
+        source,
+        &Rect::new_from_x_y(0, 0, source.width, source.height),
+    );
+    renderer
+        .context
+        .get_image_data(0.0, 0.0, source.width.into(), source.height.into())
+        .map(|image_data| image_data.data().0)
+        .map_err(|err| anyhow!("Could not get image data {:#?}", err))
+}
+
+/// The frame last drawn on the game canvas, HUD and all, as a PNG data URL.
+/// It's copied onto a canvas of its own first, if that fails the game canvas
+/// is read directly.
+pub fn capture_frame() -> Result<String> {
+    let canvas = browser::canvas()?;
+    copy_canvas(&canvas)
+        .and_then(|copy| browser::data_url(&copy))
+        .or_else(|err| {
+            log!("Could not copy the frame, reading the canvas {:#?}", err);
+            browser::data_url(&canvas)
+        })
+}
+
+fn copy_canvas(canvas: &HtmlCanvasElement) -> Result<HtmlCanvasElement> {
+    let copy = browser::create_canvas(canvas.width(), canvas.height())?;
+    let renderer = Renderer::new(browser::context_for(&copy)?);
+    renderer.draw_canvas(canvas, &Point::default());
+    Ok(copy)
+}
+
+/// One bit per pixel, set when the pixel is not transparent.
+#[cfg(feature = "pixel-perfect-collision")]
+pub struct AlphaMask {
+    width: i16,
+    height: i16,
+    bits: Vec<u64>,
+}
+
+#[cfg(feature = "pixel-perfect-collision")]
+impl AlphaMask {
+    pub fn from_rgba(width: i16, height: i16, rgba: &[u8]) -> Self {
+        let mut bits = vec![0; (width.max(0) as usize * height.max(0) as usize + 63) / 64];
+        rgba.chunks(4)
+            .enumerate()
+            .filter(|(_index, pixel)| pixel.get(3).map_or(false, |alpha| *alpha > 0))
+            .for_each(|(index, _pixel)| {
+                if let Some(word) = bits.get_mut(index / 64) {
+                    *word |= 1 << (index % 64);
+                }
+            });
+        AlphaMask {
+            width,
+            height,
+            bits,
+        }
+    }
+
+    pub fn is_opaque(&self, x: i16, y: i16) -> bool {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return false;
+        }
+        let index = y as usize * self.width as usize + x as usize;
+        self.bits
+            .get(index / 64)
+            .map_or(false, |word| word & (1 << (index % 64)) != 0)
+    }
+}
+
 #[async_trait(?Send)]
 pub trait Game {
     async fn initialize(&self) -> Result<Box<dyn Game>>;
-    fn update(&mut self, keystate: &KeyState);
-    fn draw(&self, renderer: &Renderer);
+    /// `delta` is the real time in seconds one update stands for.
+    fn update(&mut self, keystate: &mut KeyState, delta: f64);
+    /// `interpolation` is how far, from 0.0 to 1.0, this frame is between the
+    /// last update and the next one.
+    fn draw(&self, renderer: &Renderer, interpolation: f32);
+    fn pause(&mut self);
+    fn resume(&mut self);
+    /// Called as soon as the page is hidden, no more frames run after that
+    /// for a while, if ever.
+    fn save(&self) {}
+    fn debug_mode(&self) -> bool;
+    /// Whether scaled images are smoothed. Off keeps pixel art crisp.
+    fn image_smoothing(&self) -> bool {
+        false
+    }
+    /// The most frames a second the loop runs, see `GameLoop::set_target_fps`.
+    fn target_fps(&self) -> u32 {
+        DEFAULT_TARGET_FPS
+    }
+    /// On-screen buttons that press a key while they are touched.
+    fn virtual_buttons(&self) -> &[VirtualButton] {
+        &[]
+    }
+}
+
+thread_local! {
+    static PAUSED: Rc<cell::Cell<bool>> = Rc::new(cell::Cell::new(false));
+    /// Milliseconds between the frames the loop runs, 0 runs every one.
+    static MIN_FRAME_INTERVAL: cell::Cell<f64> = cell::Cell::new(0.0);
 }
 
 type SharedLoopClosure = Rc<RefCell<Option<LoopClosure>>>;
 impl GameLoop {
+    /// Stops updating the game until `resume` is called. The last frame keeps
+    /// being drawn. Pausing an already paused game does nothing.
+    pub fn pause() {
+        PAUSED.with(|paused| paused.set(true));
+    }
+
+    pub fn resume() {
+        PAUSED.with(|paused| paused.set(false));
+    }
+
+    /// Runs at most `fps` frames a second, skipping the animation frames in
+    /// between, to save power on high refresh displays. 0 runs every frame.
+    /// The game speed stays the same either way.
+    pub fn set_target_fps(fps: u32) {
+        let interval = if fps == 0 {
+            0.0
+        } else {
+            1000.0 / f64::from(fps)
+        };
+        MIN_FRAME_INTERVAL.with(|min_interval| min_interval.set(interval));
+    }
+
+    /// Spins in the middle of the canvas until `loading` is false, so
+    /// there is something moving while the assets download.
+    fn spin_while_loading(loading: Rc<cell::Cell<bool>>) -> Result<()> {
+        let renderer = Renderer::new(browser::context()?);
+        let screen = Screen::new()?;
+        let f: SharedLoopClosure = Rc::new(RefCell::new(None));
+        let g = f.clone();
+        *g.borrow_mut() = Some(browser::create_raf_closure(move |perf: f64| {
+            // The game loop draws from here on
+            if !loading.get() {
+                return;
+            }
+            renderer.clear_with_color(&screen.game_rect(), LOADING_BACKGROUND);
+            renderer.draw_spinner(
+                &screen.game_rect().center(),
+                SPINNER_RADIUS,
+                perf * SPINNER_SPEED,
+            );
+            if let Err(err) = browser::request_animation_frame(f.borrow().as_ref().unwrap()) {
+                log!("Could not keep the spinner going {:#?}", err);
+            }
+        }));
+        browser::request_animation_frame(
+            g.borrow()
+                .as_ref()
+                .ok_or_else(|| anyhow!("GameLoop: Spinner is None"))?,
+        )?;
+        Ok(())
+    }
+
     pub async fn start(game: impl Game + 'static) -> Result<()> {
         let mut keyevent_receiver = prepare_input()?;
-        let mut game = game.initialize().await?;
+        let loading = Rc::new(cell::Cell::new(true));
+        GameLoop::spin_while_loading(loading.clone())?;
+        let game = game.initialize().await;
+        loading.set(false);
+        let game = Rc::new(RefCell::new(game?));
         let mut game_loop = GameLoop {
             last_frame: browser::now()?,
-            accumulated_delta: 0.0,
+            timestep: FixedTimestep::default(),
+            paused: PAUSED.with(Rc::clone),
+            hidden: Rc::new(cell::Cell::new(false)),
+            was_paused: false,
+            frame_times: VecDeque::with_capacity(FRAME_TIME_SAMPLES),
+            #[cfg(debug_assertions)]
+            single_step: SingleStep::default(),
         };
 
-        let renderer = Renderer {
-            context: browser::context()?,
+        let renderer = Renderer::new(browser::context()?);
+        renderer.set_image_smoothing(game.borrow().image_smoothing());
+        GameLoop::set_target_fps(game.borrow().target_fps());
+
+        let on_visibility_change: Box<dyn FnMut()> = {
+            let hidden = Rc::clone(&game_loop.hidden);
+            let game = Rc::clone(&game);
+            Box::new(move || match browser::is_document_hidden() {
+                Ok(is_hidden) => {
+                    hidden.set(is_hidden);
+                    if is_hidden {
+                        game.borrow().save();
+                    }
+                }
+                Err(err) => log!("Could not read visibility state {:#?}", err),
+            })
         };
+        browser::add_visibility_change_handler(browser::closure_wrap(on_visibility_change))?;
 
         let f: SharedLoopClosure = Rc::new(RefCell::new(None));
         let g = f.clone();
         let mut keystate = KeyState::new();
         *g.borrow_mut() = Some(browser::create_raf_closure(move |perf: f64| {
-            process_input(&mut keystate, &mut keyevent_receiver);
-            game_loop.accumulated_delta += (perf - game_loop.last_frame) as f32;
-            while game_loop.accumulated_delta > FRAME_SIZE {
-                game.update(&keystate);
-                game_loop.accumulated_delta -= FRAME_SIZE;
+            let min_interval = MIN_FRAME_INTERVAL.with(cell::Cell::get);
+            if !frame_due(perf - game_loop.last_frame, min_interval) {
+                if let Err(err) = browser::request_animation_frame(f.borrow().as_ref().unwrap()) {
+                    log!("Could not request the next frame {:#?}", err);
+                }
+                return;
+            }
+            let mut game = game.borrow_mut();
+            process_input(
+                &mut keystate,
+                &mut keyevent_receiver,
+                game.virtual_buttons(),
+            );
+            let paused = game_loop.paused.get() || game_loop.hidden.get();
+            if paused != game_loop.was_paused {
+                if paused {
+                    game.pause();
+                } else {
+                    game.resume();
+                }
+                game_loop.was_paused = paused;
             }
+            if !paused {
+                let steps = game_loop
+                    .timestep
+                    .advance((perf - game_loop.last_frame) as f32);
+                #[cfg(debug_assertions)]
+                let steps = if game.debug_mode() {
+                    game_loop.single_step.gate(&keystate, steps)
+                } else {
+                    steps
+                };
+                (0..steps).for_each(|_step| {
+                    game.update(&mut keystate, STEP_SECONDS);
+                    keystate.remember_pressed_keys();
+                });
+            }
+            game_loop.record_frame_time(perf - game_loop.last_frame);
             game_loop.last_frame = perf;
-            game.draw(&renderer);
+            game.draw(&renderer, game_loop.timestep.interpolation());
+            if game.debug_mode() {
+                game_loop.draw_frame_stats(&renderer);
+                #[cfg(debug_assertions)]
+                game_loop.single_step.draw(&renderer);
+            }
             browser::request_animation_frame(f.borrow().as_ref().unwrap());
         }));
         browser::request_animation_frame(
@@ -184,17 +783,260 @@ impl GameLoop {
     }
 }
 
+/// What has to be redrawn this frame.
+pub enum Redraw {
+    Full,
+    /// Only these regions changed, the rest of the canvas still shows the
+    /// last frame.
+    Regions(Vec<Rect>),
+}
+
+const MAX_DIRTY_FRACTION: f32 = 0.5;
+
+impl Redraw {
+    /// Compares where every tracked thing was drawn last frame with where it
+    /// is drawn now. When more than half of the screen changed a full redraw
+    /// is cheaper than clipping.
+    pub fn between(previous: &[Rect], current: &[Rect], screen: &Rect) -> Self {
+        if previous.len() != current.len() {
+            return Redraw::Full;
+        }
+        let regions: Vec<Rect> = previous
+            .iter()
+            .zip(current)
+            .filter(|(before, now)| before != now)
+            .flat_map(|(before, now)| [before.clone(), now.clone()])
+            .collect();
+        let area = |rect: &Rect| i32::from(rect.width) * i32::from(rect.height);
+        let dirty_area: i32 = regions.iter().map(area).sum();
+        if dirty_area as f32 > area(screen) as f32 * MAX_DIRTY_FRACTION {
+            Redraw::Full
+        } else {
+            Redraw::Regions(regions)
+        }
+    }
+
+    pub fn needs(&self, area: &Rect) -> bool {
+        match self {
+            Redraw::Full => true,
+            Redraw::Regions(regions) => regions.iter().any(|region| region.intersects(area)),
+        }
+    }
+}
+
 const FRAME_SIZE: f32 = 1.0 / 60.0 * 1000.0;
+/// Every update is one `FRAME_SIZE` of real time, however many a frame has.
+pub const STEP_SECONDS: f64 = FRAME_SIZE as f64 / 1000.0;
+const FRAME_TIME_SAMPLES: usize = 60;
+const FRAME_GRAPH_TOP: i16 = 70;
+const FRAME_GRAPH_HEIGHT: i16 = 40;
+const FRAME_GRAPH_ALPHA: f64 = 0.7;
+const SLOW_FRAME_MS: f64 = 16.0;
+pub const DEFAULT_TARGET_FPS: u32 = 60;
+const FRAME_CAP_TOLERANCE_MS: f64 = 2.0;
+
+/// Whether a frame `elapsed` milliseconds after the last one runs. Frames
+/// never come exactly on time, one a little early still counts, or a cap
+/// at the display's own rate would skip every other frame.
+fn frame_due(elapsed: f64, min_interval: f64) -> bool {
+    elapsed >= min_interval - FRAME_CAP_TOLERANCE_MS
+}
+
+/// Turns the time between animation frames into whole updates of
+/// `FRAME_SIZE`, so the simulation runs at the same rate whatever the
+/// display's refresh rate is. The time left over carries into the next frame.
+#[derive(Default)]
+struct FixedTimestep {
+    accumulated_delta: f32,
+}
+
+impl FixedTimestep {
+    fn advance(&mut self, delta: f32) -> u32 {
+        self.accumulated_delta += delta;
+        let mut steps = 0;
+        while self.accumulated_delta >= FRAME_SIZE {
+            self.accumulated_delta -= FRAME_SIZE;
+            steps += 1;
+        }
+        steps
+    }
+
+    fn interpolation(&self) -> f32 {
+        self.accumulated_delta / FRAME_SIZE
+    }
+}
+
 pub struct GameLoop {
     last_frame: f64,
-    accumulated_delta: f32,
+    timestep: FixedTimestep,
+    paused: Rc<cell::Cell<bool>>,
+    /// Whether the page is hidden. Kept apart from `paused`, so showing the
+    /// page again doesn't resume a game the embedding page paused.
+    hidden: Rc<cell::Cell<bool>>,
+    was_paused: bool,
+    frame_times: VecDeque<f64>,
+    #[cfg(debug_assertions)]
+    single_step: SingleStep,
+}
+
+#[cfg(debug_assertions)]
+const SINGLE_STEP_TOGGLE_KEY: &str = "Backslash";
+#[cfg(debug_assertions)]
+const SINGLE_STEP_KEY: &str = "Period";
+
+/// Holds the updates back and runs them one key press at a time, to look at
+/// collisions frame by frame. Only debug builds of a game in debug mode have
+/// it, the frame is still drawn while it waits.
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct SingleStep {
+    enabled: bool,
+    toggle_was_pressed: bool,
+    step_was_pressed: bool,
+}
+
+#[cfg(debug_assertions)]
+impl SingleStep {
+    /// How many of the `steps` updates that are due may run this frame.
+    /// Holding a key down only counts once.
+    fn gate(&mut self, keystate: &KeyState, steps: u32) -> u32 {
+        let toggle_pressed = keystate.is_pressed(SINGLE_STEP_TOGGLE_KEY);
+        if toggle_pressed && !self.toggle_was_pressed {
+            self.enabled = !self.enabled;
+        }
+        self.toggle_was_pressed = toggle_pressed;
+
+        let step_pressed = keystate.is_pressed(SINGLE_STEP_KEY);
+        let stepped = step_pressed && !self.step_was_pressed;
+        self.step_was_pressed = step_pressed;
+
+        match (self.enabled, stepped) {
+            (false, _) => steps,
+            (true, true) => 1,
+            (true, false) => 0,
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer) {
+        if self.enabled {
+            renderer.draw_text_centered("STEP", &Point { x: 40, y: 30 }, 16);
+        }
+    }
+}
+
+impl GameLoop {
+    fn record_frame_time(&mut self, delta_ms: f64) {
+        if self.frame_times.len() == FRAME_TIME_SAMPLES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(delta_ms);
+    }
+
+    /// Draws the FPS of the last frame and a bar per recent frame time in the
+    /// top right corner. Red bars took longer than a 60 FPS frame.
+    fn draw_frame_stats(&self, renderer: &Renderer) {
+        let (width, _height) = renderer.canvas_size();
+        let right = width as i16 - 10;
+        let left = right - FRAME_TIME_SAMPLES as i16;
+        if let Some(delta_ms) = self.frame_times.back().filter(|delta_ms| **delta_ms > 0.0) {
+            renderer.draw_text_centered(
+                &format!("{:.0} FPS", 1000.0 / delta_ms),
+                &Point {
+                    x: left + FRAME_TIME_SAMPLES as i16 / 2,
+                    y: FRAME_GRAPH_TOP - 15,
+                },
+                16,
+            );
+        }
+        let bottom = FRAME_GRAPH_TOP + FRAME_GRAPH_HEIGHT;
+        self.frame_times
+            .iter()
+            .enumerate()
+            .for_each(|(index, delta_ms)| {
+                let height = (*delta_ms as i16).clamp(1, FRAME_GRAPH_HEIGHT);
+                let color = if *delta_ms > SLOW_FRAME_MS {
+                    "#FF0000"
+                } else {
+                    "#00FF00"
+                };
+                renderer.draw_alpha_rect(
+                    &Rect::new_from_x_y(left + index as i16, bottom - height, 1, height),
+                    color,
+                    FRAME_GRAPH_ALPHA,
+                );
+            });
+    }
 }
 
 pub struct Renderer {
     context: CanvasRenderingContext2d,
 }
 
-#[derive(Default)]
+const TEXT_LINE_HEIGHT: f64 = 1.2;
+const TEXT_COLOR: &str = "#FFFFFF";
+
+/// The CSS font for game text of `size` pixels.
+pub fn game_font(size: u16) -> String {
+    format!("{}px 'Ken Future'", size)
+}
+
+/// Splits `start..start + length` into a corner, the stretch in between and
+/// the other corner. Corners shrink to fit, they never overlap.
+fn slice_thirds(start: i16, length: i16, corner_size: i16) -> [(i16, i16); 3] {
+    let corner = corner_size.clamp(0, length / 2);
+    [
+        (start, corner),
+        (start + corner, length - corner * 2),
+        (start + length - corner, corner),
+    ]
+}
+
+/// Pairs each of the nine pieces of `source` with where it goes in
+/// `destination`, row by row.
+fn nine_slice(source: &Rect, destination: &Rect, corner_size: i16) -> Vec<(Rect, Rect)> {
+    let source_columns = slice_thirds(source.x(), source.width, corner_size);
+    let source_rows = slice_thirds(source.y(), source.height, corner_size);
+    let columns = slice_thirds(destination.x(), destination.width, corner_size);
+    let rows = slice_thirds(destination.y(), destination.height, corner_size);
+    source_rows
+        .into_iter()
+        .zip(rows)
+        .flat_map(|((source_y, source_height), (y, height))| {
+            source_columns.into_iter().zip(columns).map(
+                move |((source_x, source_width), (x, width))| {
+                    (
+                        Rect::new_from_x_y(source_x, source_y, source_width, source_height),
+                        Rect::new_from_x_y(x, y, width, height),
+                    )
+                },
+            )
+        })
+        .collect()
+}
+
+/// The size of the canvas the game is drawn on, so nothing has to assume
+/// a resolution.
+#[derive(Clone, Copy)]
+pub struct Screen {
+    pub width: i16,
+    pub height: i16,
+}
+
+impl Screen {
+    pub fn new() -> Result<Self> {
+        let canvas = browser::canvas()?;
+        Ok(Screen {
+            width: canvas.width() as i16,
+            height: canvas.height() as i16,
+        })
+    }
+
+    pub fn game_rect(&self) -> Rect {
+        Rect::new_from_x_y(0, 0, self.width, self.height)
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Rect {
     pub position: Point,
     pub width: i16,
@@ -214,6 +1056,14 @@ impl Rect {
         Rect::new(Point { x, y }, width, height)
     }
 
+    /// Points on the right and bottom edge are outside, like for `intersects`.
+    pub fn contains(&self, point: &Point) -> bool {
+        point.x >= self.x()
+            && point.x < self.right()
+            && point.y >= self.y()
+            && point.y < self.bottom()
+    }
+
     pub fn intersects(&self, rect: &Rect) -> bool {
         self.x() < (rect.x() + rect.width)
             && self.x() + self.width > rect.x()
@@ -225,6 +1075,14 @@ impl Rect {
         self.x() + self.width
     }
 
+    /// The center of the rect, rounded towards the top left for odd sizes.
+    pub fn center(&self) -> Point {
+        Point {
+            x: self.x() + self.width / 2,
+            y: self.y() + self.height / 2,
+        }
+    }
+
     pub fn bottom(&self) -> i16 {
         self.y() + self.height
     }
@@ -240,9 +1098,36 @@ impl Rect {
     pub fn set_x(&mut self, x: i16) {
         self.position.x = x
     }
+
+    /// The rect grown or shrunk by `scale`, keeping its center.
+    pub fn scaled(&self, scale: f64) -> Rect {
+        let width = (f64::from(self.width) * scale).round() as i16;
+        let height = (f64::from(self.height) * scale).round() as i16;
+        let center = self.center();
+        Rect::new_from_x_y(center.x - width / 2, center.y - height / 2, width, height)
+    }
 }
 
 impl Renderer {
+    /// Smoothing starts out off, for crisp pixel art.
+    pub fn new(context: CanvasRenderingContext2d) -> Self {
+        let renderer = Renderer { context };
+        renderer.set_image_smoothing(false);
+        renderer
+    }
+
+    /// Resizing the canvas resets the context, call this again after.
+    pub fn set_image_smoothing(&self, enabled: bool) {
+        self.context.set_image_smoothing_enabled(enabled);
+    }
+
+    pub fn canvas_size(&self) -> (f64, f64) {
+        self.context
+            .canvas()
+            .map(|canvas| (canvas.width().into(), canvas.height().into()))
+            .unwrap_or_default()
+    }
+
     pub fn clear(&self, rect: &Rect) {
         self.context.clear_rect(
             rect.x().into(),
@@ -252,6 +1137,119 @@ impl Renderer {
         );
     }
 
+    /// Scales everything drawn afterwards, keeping the bottom left corner of
+    /// the canvas in place so the floor stays in view. The scale is in canvas
+    /// pixels, devicePixelRatio is not taken into account.
+    pub fn set_scale(&self, scale: f64) {
+        let (_width, height) = self.canvas_size();
+        self.reset_scale();
+        self.context
+            .translate(0.0, height * (1.0 - scale))
+            .and_then(|_unit| self.context.scale(scale, scale))
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    }
+
+    pub fn translate(&self, x: f64, y: f64) {
+        self.context
+            .translate(x, y)
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    }
+
+    /// Restricts drawing to `regions` until `end_clip` is called.
+    pub fn clip_to(&self, regions: &[Rect]) {
+        self.context.save();
+        self.context.begin_path();
+        regions.iter().for_each(|region| {
+            self.context.rect(
+                region.x().into(),
+                region.y().into(),
+                region.width.into(),
+                region.height.into(),
+            )
+        });
+        self.context.clip();
+    }
+
+    pub fn end_clip(&self) {
+        self.context.restore();
+    }
+
+    pub fn reset_scale(&self) {
+        self.context
+            .set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    }
+
+    pub fn clear_with_color(&self, rect: &Rect, color: &str) {
+        self.clear(rect);
+        self.fill_rect(rect, color);
+    }
+
+    pub fn draw_alpha_rect(&self, rect: &Rect, color: &str, alpha: f64) {
+        self.context.set_global_alpha(alpha);
+        self.fill_rect(rect, color);
+        self.context.set_global_alpha(1.0);
+    }
+
+    pub fn fill_rect(&self, rect: &Rect, color: &str) {
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context.fill_rect(
+            rect.x().into(),
+            rect.y().into(),
+            rect.width.into(),
+            rect.height.into(),
+        );
+    }
+
+    pub fn fill_circle(&self, center: &Point, radius: i16, color: &str) {
+        self.context.begin_path();
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context
+            .arc(
+                center.x.into(),
+                center.y.into(),
+                radius.into(),
+                0.0,
+                std::f64::consts::TAU,
+            )
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+        self.context.fill();
+    }
+
+    /// A quarter circle of `radius` around `center`, starting at `angle`.
+    /// Turning `angle` a bit every frame makes it spin.
+    pub fn draw_spinner(&self, center: &Point, radius: f64, angle: f64) {
+        self.context.save();
+        self.context
+            .set_stroke_style(&JsValue::from_str(SPINNER_COLOR));
+        self.context.set_line_width(SPINNER_LINE_WIDTH);
+        self.context.set_line_cap("round");
+        self.context.begin_path();
+        self.context
+            .arc(
+                center.x.into(),
+                center.y.into(),
+                radius,
+                angle,
+                angle + SPINNER_SWEEP,
+            )
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+        self.context.stroke();
+        self.context.restore();
+    }
+
+    /// Fills the whole canvas with a vertical gradient from `top_color` to `bottom_color`.
+    pub fn draw_gradient_background(&self, top_color: &str, bottom_color: &str) {
+        let (width, height) = self.canvas_size();
+        let gradient = self.context.create_linear_gradient(0.0, 0.0, 0.0, height);
+        gradient
+            .add_color_stop(0.0, top_color)
+            .and_then(|_unit| gradient.add_color_stop(1.0, bottom_color))
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+        self.context.set_fill_style(&gradient);
+        self.context.fill_rect(0.0, 0.0, width, height);
+    }
+
     pub fn draw_image(&self, image: &HtmlImageElement, frame: &Rect, destination: &Rect) {
         self.context
             .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
@@ -268,14 +1266,159 @@ impl Renderer {
             .expect("Drawing is throwing exceptions! Unrecoverable error.");
     }
 
-    pub fn draw_entire_image(&self, image: &HtmlImageElement, position: &Point) {
+    /// Draws the cell over `destination` without stretching its corners,
+    /// only the edges and the middle stretch to fit. Does nothing if the
+    /// sheet has no such cell.
+    pub fn draw_nine_slice(
+        &self,
+        sheet: &SpriteSheet,
+        cell_name: &str,
+        destination: &Rect,
+        corner_size: i16,
+    ) {
+        self.draw_nine_slice_with_alpha(sheet, cell_name, destination, corner_size, 1.0);
+    }
+
+    pub fn draw_nine_slice_with_alpha(
+        &self,
+        sheet: &SpriteSheet,
+        cell_name: &str,
+        destination: &Rect,
+        corner_size: i16,
+        alpha: f64,
+    ) {
+        if let Some(cell) = sheet.cell(cell_name) {
+            self.context.set_global_alpha(alpha);
+            nine_slice(&cell.frame_rect(), destination, corner_size)
+                .iter()
+                // Canvases throw on empty sources
+                .filter(|(source, piece)| source.width > 0 && source.height > 0 && piece.width > 0)
+                .for_each(|(source, piece)| self.draw_image(&sheet.image, source, piece));
+            self.context.set_global_alpha(1.0);
+        }
+    }
+
+    pub fn draw_image_with_alpha(
+        &self,
+        image: &HtmlImageElement,
+        frame: &Rect,
+        destination: &Rect,
+        alpha: f64,
+    ) {
+        self.context.set_global_alpha(alpha);
+        self.draw_image(image, frame, destination);
+        self.context.set_global_alpha(1.0);
+    }
+
+    /// Draws the frame upside down, mirrored about the horizontal middle of
+    /// `destination` so it still covers the same area.
+    pub fn draw_image_flipped_vertical(
+        &self,
+        image: &HtmlImageElement,
+        frame: &Rect,
+        destination: &Rect,
+        alpha: f64,
+    ) {
+        let mirror_y = f64::from(destination.y()) * 2.0 + f64::from(destination.height);
+        self.context.save();
+        self.context
+            .translate(0.0, mirror_y)
+            .and_then(|_unit| self.context.scale(1.0, -1.0))
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+        self.draw_image_with_alpha(image, frame, destination, alpha);
+        self.context.restore();
+    }
+
+    /// Stretches all of `image` over `destination`, whatever size the image
+    /// itself has.
+    pub fn draw_image_scaled(&self, image: &HtmlImageElement, destination: &Rect) {
+        self.context
+            .draw_image_with_html_image_element_and_dw_and_dh(
+                image,
+                destination.x().into(),
+                destination.y().into(),
+                destination.width.into(),
+                destination.height.into(),
+            )
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    }
+
+    pub fn draw_canvas(&self, canvas: &HtmlCanvasElement, position: &Point) {
+        self.context
+            .draw_image_with_html_canvas_element(canvas, position.x.into(), position.y.into())
+            .expect("Drawing is throwing exceptions! Unrecoverable error.");
+    }
+
+    /// How wide `text` is in `font`, a CSS font like `game_font` returns.
+    pub fn measure_text(&self, text: &str, font: &str) -> f64 {
+        self.context.set_font(font);
         self.context
-            .draw_image_with_html_image_element(image, position.x.into(), position.y.into())
+            .measure_text(text)
+            .expect("Measuring text is throwing exceptions! Unrecoverable error.")
+            .width()
+    }
+
+    /// About how tall a line of text is, the canvas can't measure it in
+    /// every browser.
+    pub fn text_height(font_size_px: u32) -> f64 {
+        f64::from(font_size_px) * TEXT_LINE_HEIGHT
+    }
+
+    /// Draws `text` in the game font with its middle on `center`, whatever
+    /// the text says.
+    pub fn draw_text_centered(&self, text: &str, center: &Point, size: u16) {
+        self.draw_text_centered_with_alpha(text, center, size, 1.0);
+    }
+
+    pub fn draw_text_centered_with_alpha(&self, text: &str, center: &Point, size: u16, alpha: f64) {
+        self.fill_text_centered(text, center, size, TEXT_COLOR, alpha);
+    }
+
+    pub fn draw_text_centered_with_color(
+        &self,
+        text: &str,
+        center: &Point,
+        size: u16,
+        color: &str,
+    ) {
+        self.fill_text_centered(text, center, size, color, 1.0);
+    }
+
+    fn fill_text_centered(&self, text: &str, center: &Point, size: u16, color: &str, alpha: f64) {
+        let font = game_font(size);
+        let width = self.measure_text(text, &font);
+        let height = Renderer::text_height(size.into());
+        self.context.set_global_alpha(alpha);
+        self.context.set_text_align("left");
+        self.context.set_text_baseline("top");
+        self.context.set_fill_style(&JsValue::from_str(color));
+        self.context
+            .fill_text(
+                text,
+                f64::from(center.x) - width / 2.0,
+                f64::from(center.y) - height / 2.0,
+            )
             .expect("Drawing is throwing exceptions! Unrecoverable error.");
+        self.context.set_global_alpha(1.0);
+    }
+
+    pub fn draw_line(&self, x1: f64, y1: f64, x2: f64, y2: f64, color: &str, width: f64) {
+        self.context.set_stroke_style(&JsValue::from_str(color));
+        self.context.set_line_width(width);
+        self.context.begin_path();
+        self.context.move_to(x1, y1);
+        self.context.line_to(x2, y2);
+        self.context.stroke();
+        self.context.set_line_width(1.0);
     }
 
     pub fn draw_rect(&self, bounding_box: &Rect) {
-        self.context.set_stroke_style(&JsValue::from_str("#FF0000"));
+        self.draw_rect_with_color(bounding_box, "#FF0000");
+    }
+
+    /// The outline of `bounding_box`, in `color`.
+    pub fn draw_rect_with_color(&self, bounding_box: &Rect, color: &str) {
+        self.context.set_stroke_style(&JsValue::from_str(color));
         self.context.begin_path();
         self.context.rect(
             bounding_box.x().into(),
@@ -287,17 +1430,71 @@ impl Renderer {
     }
 }
 
+const SPINNER_COLOR: &str = "#FFFFFF";
+const SPINNER_LINE_WIDTH: f64 = 6.0;
+const SPINNER_SWEEP: f64 = std::f64::consts::FRAC_PI_2;
+const SPINNER_RADIUS: f64 = 30.0;
+/// Radians per millisecond, a turn every second.
+const SPINNER_SPEED: f64 = std::f64::consts::TAU / 1000.0;
+const LOADING_BACKGROUND: &str = "#000000";
+const VIRTUAL_BUTTON_COLOR: &str = "#000000";
+const VIRTUAL_BUTTON_ALPHA: f64 = 0.3;
+const VIRTUAL_BUTTON_TEXT_SIZE: u16 = 20;
+
+/// A button drawn on the canvas, for devices without a keyboard. It presses
+/// `code` in the `KeyState` for as long as it is touched.
+pub struct VirtualButton {
+    pub area: Rect,
+    pub code: String,
+    pub label: String,
+}
+
+impl VirtualButton {
+    pub fn draw(&self, renderer: &Renderer) {
+        renderer.draw_alpha_rect(&self.area, VIRTUAL_BUTTON_COLOR, VIRTUAL_BUTTON_ALPHA);
+        renderer.draw_text_centered(&self.label, &self.area.center(), VIRTUAL_BUTTON_TEXT_SIZE);
+    }
+}
+
 enum KeyPress {
     KeyUp(web_sys::KeyboardEvent),
     KeyDown(web_sys::KeyboardEvent),
+    PointerDown { pointer_id: i32, position: Point },
+    PointerUp { pointer_id: i32 },
+    MouseMove { movement: Point },
+}
+
+/// Pointer events are in CSS pixels, but the canvas can be displayed at
+/// another size than it is drawn at.
+fn canvas_position(canvas: &HtmlCanvasElement, event: &web_sys::PointerEvent) -> Point {
+    let scale = |size: u32, displayed: i32| {
+        if displayed > 0 {
+            size as f32 / displayed as f32
+        } else {
+            1.0
+        }
+    };
+    Point {
+        x: (event.offset_x() as f32 * scale(canvas.width(), canvas.client_width())) as i16,
+        y: (event.offset_y() as f32 * scale(canvas.height(), canvas.client_height())) as i16,
+    }
 }
 
 fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
     let (keydown_sender, keyevent_receiver) = unbounded();
     let keydown_sender = Rc::new(RefCell::new(keydown_sender));
     let keyup_sender = Rc::clone(&keydown_sender);
+    let pointerdown_sender = Rc::clone(&keydown_sender);
+    let pointerup_sender = Rc::clone(&keydown_sender);
+    let mousemove_sender = Rc::clone(&keydown_sender);
+    let canvas = browser::canvas()?;
+    let pointer_canvas = canvas.clone();
 
     let onkeydown = browser::closure_wrap(Box::new(move |keycode: web_sys::KeyboardEvent| {
+        // Typing a name must not restart the game, or jump
+        if browser::is_typing(&keycode) {
+            return;
+        }
         keydown_sender
             .borrow_mut()
             .start_send(KeyPress::KeyDown(keycode));
@@ -308,29 +1505,100 @@ fn prepare_input() -> Result<UnboundedReceiver<KeyPress>> {
             .start_send(KeyPress::KeyUp(keycode));
     }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
 
+    let onpointerdown = browser::closure_wrap(Box::new(move |event: web_sys::PointerEvent| {
+        pointerdown_sender
+            .borrow_mut()
+            .start_send(KeyPress::PointerDown {
+                pointer_id: event.pointer_id(),
+                position: canvas_position(&pointer_canvas, &event),
+            });
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+    let onpointerup = browser::closure_wrap(Box::new(move |event: web_sys::PointerEvent| {
+        pointerup_sender
+            .borrow_mut()
+            .start_send(KeyPress::PointerUp {
+                pointer_id: event.pointer_id(),
+            });
+    }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+    let onmousemove = browser::closure_wrap(Box::new(move |event: web_sys::MouseEvent| {
+        let clamp = |movement: i32| movement.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+        mousemove_sender
+            .borrow_mut()
+            .start_send(KeyPress::MouseMove {
+                movement: Point {
+                    x: clamp(event.movement_x()),
+                    y: clamp(event.movement_y()),
+                },
+            });
+    }) as Box<dyn FnMut(web_sys::MouseEvent)>);
+
     browser::window()?.set_onkeydown(Some(onkeydown.as_ref().unchecked_ref()));
     browser::window()?.set_onkeyup(Some(onkeyup.as_ref().unchecked_ref()));
+    canvas.set_onpointerdown(Some(onpointerdown.as_ref().unchecked_ref()));
+    // A cancelled touch never sends pointerup, but must still let go
+    canvas.set_onpointerup(Some(onpointerup.as_ref().unchecked_ref()));
+    canvas.set_onpointercancel(Some(onpointerup.as_ref().unchecked_ref()));
     onkeydown.forget();
     onkeyup.forget();
+    onpointerdown.forget();
+    onpointerup.forget();
+    browser::add_mouse_move_handler(onmousemove)?;
+    // The browser only tells whether locking the mouse worked later on
+    browser::add_pointer_lock_error_handler(browser::closure_wrap(Box::new(|| {
+        log!("Could not lock the mouse");
+    }) as Box<dyn FnMut()>))?;
 
     Ok(keyevent_receiver)
 }
 
-fn process_input(state: &mut KeyState, keyevent_receiver: &mut UnboundedReceiver<KeyPress>) {
+fn process_input(
+    state: &mut KeyState,
+    keyevent_receiver: &mut UnboundedReceiver<KeyPress>,
+    buttons: &[VirtualButton],
+) {
+    state.mouse_movement = Point::default();
     loop {
         match keyevent_receiver.try_next() {
             Ok(None) => break,
             Err(_err) => break,
             Ok(Some(event)) => match event {
-                KeyPress::KeyUp(event) => state.set_released(&event.code()),
-                KeyPress::KeyDown(event) => state.set_pressed(&event.code(), event),
+                KeyPress::KeyUp(event) => state.set_released(&browser::normalize_key_code(&event)),
+                KeyPress::KeyDown(event) => {
+                    state.set_pressed(&browser::normalize_key_code(&event), Some(event))
+                }
+                KeyPress::PointerDown {
+                    pointer_id,
+                    position,
+                } => {
+                    if let Some(button) = buttons
+                        .iter()
+                        .find(|button| button.area.contains(&position))
+                    {
+                        state.set_pointer_pressed(pointer_id, &button.code)
+                    }
+                }
+                KeyPress::PointerUp { pointer_id } => state.set_pointer_released(pointer_id),
+                KeyPress::MouseMove { movement } => {
+                    state.mouse_movement = Point {
+                        x: state.mouse_movement.x.saturating_add(movement.x),
+                        y: state.mouse_movement.y.saturating_add(movement.y),
+                    }
+                }
             },
         }
     }
 }
 
 pub struct KeyState {
-    pressed_keys: HashMap<String, web_sys::KeyboardEvent>,
+    /// Keys pressed on a virtual button have no `KeyboardEvent`.
+    pressed_keys: HashMap<String, Option<web_sys::KeyboardEvent>>,
+    /// Pressed for the next update only, by controls that aren't keys.
+    injected_keys: HashSet<String>,
+    pointer_keys: HashMap<i32, String>,
+    /// The keys that were down during the last update.
+    previous_keys: HashSet<String>,
+    /// How far the mouse moved since the last frame.
+    mouse_movement: Point,
 }
 
 /// Just a wrapper that stores a lookup of KeyboardEvent.code
@@ -338,21 +1606,528 @@ pub struct KeyState {
 /// It represents the a physical key pressed. You can find other keys
 /// at https://mzl.la/3ar9krK
 impl KeyState {
-    fn new() -> Self {
+    pub fn new() -> Self {
         KeyState {
             pressed_keys: HashMap::new(),
+            injected_keys: HashSet::new(),
+            pointer_keys: HashMap::new(),
+            previous_keys: HashSet::new(),
+            mouse_movement: Point::default(),
         }
     }
 
+    pub fn mouse_movement(&self) -> Point {
+        self.mouse_movement
+    }
+
+    /// Presses `code` without a key, for controls that aren't keys at all.
+    /// It is released again after the next update.
+    pub fn inject_press(&mut self, code: &str) {
+        self.injected_keys.insert(code.into());
+    }
+
     pub fn is_pressed(&self, code: &str) -> bool {
-        self.pressed_keys.contains_key(code)
+        self.pressed_keys.contains_key(code) || self.injected_keys.contains(code)
+    }
+
+    /// Only true for the first update a key is down, holding it doesn't
+    /// count again.
+    pub fn is_just_pressed(&self, code: &str) -> bool {
+        self.is_pressed(code) && !self.previous_keys.contains(code)
+    }
+
+    pub fn is_just_released(&self, code: &str) -> bool {
+        !self.is_pressed(code) && self.previous_keys.contains(code)
     }
 
-    fn set_pressed(&mut self, code: &str, event: web_sys::KeyboardEvent) {
+    /// Called after every update, so the next one can tell which keys went
+    /// down or up since. Frames without an update keep their key changes
+    /// for the next one that has one.
+    fn remember_pressed_keys(&mut self) {
+        self.previous_keys = self.pressed_keys.keys().cloned().collect();
+        self.previous_keys.extend(self.injected_keys.drain());
+    }
+
+    fn set_pressed(&mut self, code: &str, event: Option<web_sys::KeyboardEvent>) {
         self.pressed_keys.insert(code.into(), event);
     }
 
     fn set_released(&mut self, code: &str) {
         self.pressed_keys.remove(code.into());
     }
+
+    /// Remembers which key each pointer pressed, so lifting a finger releases
+    /// it even when the finger moved off the button.
+    fn set_pointer_pressed(&mut self, pointer_id: i32, code: &str) {
+        self.pointer_keys.insert(pointer_id, code.into());
+        self.set_pressed(code, None);
+    }
+
+    fn set_pointer_released(&mut self, pointer_id: i32) {
+        if let Some(code) = self.pointer_keys.remove(&pointer_id) {
+            self.set_released(&code);
+        }
+    }
+}
+
+pub mod leaderboard {
+    use crate::browser;
+    use anyhow::{anyhow, Result};
+    use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+    use futures::StreamExt;
+    use serde::Serialize;
+    use wasm_bindgen::{JsCast, JsValue};
+    use web_sys::WebSocket;
+
+    #[derive(Serialize)]
+    struct ScoreMessage<'a> {
+        name: &'a str,
+        score: u32,
+    }
+
+    #[derive(Clone, Copy)]
+    enum SocketEvent {
+        Open,
+        Message,
+        Closed,
+    }
+
+    /// Sends `score` to the leaderboard server at `url` and waits for it to
+    /// answer with any message before closing the connection.
+    pub async fn submit_score(url: &str, name: &str, score: u32) -> Result<()> {
+        let message = JsValue::from_serde(&ScoreMessage { name, score })
+            .map_err(|err| anyhow!("Could not serialize score {:#?}", err))
+            .and_then(|value| browser::stringify_json(&value))?;
+        let socket = WebSocket::new(url)
+            .map_err(|err| anyhow!("Could not open WebSocket to {} {:#?}", url, err))?;
+
+        let (sender, mut events) = unbounded();
+        let event_handler = |event: SocketEvent| {
+            let mut sender = sender.clone();
+            browser::closure_wrap(Box::new(move |_event: JsValue| {
+                sender.start_send(event);
+            }) as Box<dyn FnMut(JsValue)>)
+        };
+        let on_open = event_handler(SocketEvent::Open);
+        let on_message = event_handler(SocketEvent::Message);
+        // An error is always followed by close, both end the exchange
+        let on_close = event_handler(SocketEvent::Closed);
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onerror(Some(on_close.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        let result = exchange(&socket, &mut events, &message).await;
+
+        // The closures are dropped on return, JavaScript must not call them after
+        socket.set_onopen(None);
+        socket.set_onmessage(None);
+        socket.set_onerror(None);
+        socket.set_onclose(None);
+        if let Err(err) = socket.close() {
+            log!("Could not close leaderboard connection {:#?}", err);
+        }
+        result
+    }
+
+    async fn exchange(
+        socket: &WebSocket,
+        events: &mut UnboundedReceiver<SocketEvent>,
+        message: &str,
+    ) -> Result<()> {
+        match events.next().await {
+            Some(SocketEvent::Open) => {}
+            _ => return Err(anyhow!("Could not connect to the leaderboard")),
+        }
+        socket
+            .send_with_str(message)
+            .map_err(|err| anyhow!("Could not send score {:#?}", err))?;
+        match events.next().await {
+            Some(SocketEvent::Message) => Ok(()),
+            _ => Err(anyhow!("Leaderboard closed before acknowledging the score")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps_for_cadence(frame_delta: f32, elapsed: f32) -> u32 {
+        let mut timestep = FixedTimestep::default();
+        let mut remaining = elapsed;
+        let mut steps = 0;
+        while remaining > 0.0 {
+            let delta = frame_delta.min(remaining);
+            steps += timestep.advance(delta);
+            remaining -= delta;
+        }
+        steps
+    }
+
+    #[test]
+    fn images_are_tried_in_smaller_formats_first() {
+        assert_eq!(
+            image_sources("rhb"),
+            vec!["rhb.avif", "rhb.webp", "rhb.png"]
+        );
+        assert_eq!(
+            image_sources("themes/BG.png"),
+            vec!["themes/BG.avif", "themes/BG.webp", "themes/BG.png"]
+        );
+        assert_eq!(image_sources("v1.2/Stone")[2], "v1.2/Stone.png");
+        assert_eq!(image_sources("photo.jpg"), vec!["photo.jpg"]);
+    }
+
+    /// Fetches only the sources with one of `extensions`, remembering what
+    /// was asked for.
+    fn fetch_only<'a>(
+        extensions: &'a [&'a str],
+        tried: &'a RefCell<Vec<String>>,
+    ) -> impl Fn(String) -> futures::future::Ready<Result<String>> + 'a {
+        move |source| {
+            tried.borrow_mut().push(source.clone());
+            futures::future::ready(
+                if extensions
+                    .iter()
+                    .any(|extension| source.ends_with(extension))
+                {
+                    Ok(source)
+                } else {
+                    Err(anyhow!("404 Not Found: {}", source))
+                },
+            )
+        }
+    }
+
+    #[test]
+    fn images_fall_back_to_the_png_when_only_it_exists() {
+        let tried = RefCell::new(vec![]);
+        let loaded = futures::executor::block_on(first_loaded(
+            image_sources("rhb"),
+            fetch_only(&[".png"], &tried),
+        ));
+        assert_eq!(loaded.unwrap(), "rhb.png");
+        assert_eq!(*tried.borrow(), vec!["rhb.avif", "rhb.webp", "rhb.png"]);
+    }
+
+    #[test]
+    fn images_stop_at_the_best_format_there_is() {
+        let tried = RefCell::new(vec![]);
+        let loaded = futures::executor::block_on(first_loaded(
+            image_sources("rhb"),
+            fetch_only(&[".webp", ".png"], &tried),
+        ));
+        assert_eq!(loaded.unwrap(), "rhb.webp");
+        assert_eq!(*tried.borrow(), vec!["rhb.avif", "rhb.webp"]);
+    }
+
+    #[test]
+    fn images_fail_with_the_last_error_when_no_format_exists() {
+        let tried = RefCell::new(vec![]);
+        let loaded = futures::executor::block_on(first_loaded(
+            image_sources("rhb"),
+            fetch_only(&[], &tried),
+        ));
+        assert_eq!(loaded.unwrap_err().to_string(), "404 Not Found: rhb.png");
+    }
+
+    #[test]
+    fn capped_frames_wait_for_their_interval() {
+        let interval = 1000.0 / 30.0;
+        assert!(!frame_due(1000.0 / 120.0, interval));
+        assert!(!frame_due(1000.0 / 60.0, interval));
+        assert!(frame_due(interval - 1.0, interval));
+        assert!(frame_due(1000.0 / 120.0, 0.0));
+    }
+
+    #[test]
+    fn nine_slice_keeps_the_corners_and_stretches_the_rest() {
+        let source = Rect::new_from_x_y(0, 0, 30, 30);
+        let destination = Rect::new_from_x_y(100, 100, 50, 40);
+        let pieces: Vec<((i16, i16, i16, i16), (i16, i16, i16, i16))> =
+            nine_slice(&source, &destination, 10)
+                .iter()
+                .map(|(source, piece)| {
+                    (
+                        (source.x(), source.y(), source.width, source.height),
+                        (piece.x(), piece.y(), piece.width, piece.height),
+                    )
+                })
+                .collect();
+
+        assert_eq!(pieces.len(), 9);
+        assert_eq!(pieces[0], ((0, 0, 10, 10), (100, 100, 10, 10)));
+        assert_eq!(pieces[4], ((10, 10, 10, 10), (110, 110, 30, 20)));
+        assert_eq!(pieces[8], ((20, 20, 10, 10), (140, 130, 10, 10)));
+    }
+
+    #[test]
+    fn nine_slice_corners_shrink_to_fit() {
+        assert_eq!(slice_thirds(0, 12, 10), [(0, 6), (6, 0), (6, 6)]);
+    }
+
+    #[test]
+    fn unchanged_frame_redraws_nothing() {
+        let screen = Rect::new_from_x_y(0, 0, 600, 600);
+        let boy = [Rect::new_from_x_y(0, 479, 100, 120)];
+        let redraw = Redraw::between(&boy, &boy, &screen);
+        assert!(matches!(&redraw, Redraw::Regions(regions) if regions.is_empty()));
+    }
+
+    #[test]
+    fn large_changes_fall_back_to_a_full_redraw() {
+        let screen = Rect::new_from_x_y(0, 0, 600, 600);
+        let before = [Rect::new_from_x_y(0, 0, 400, 400)];
+        let after = [Rect::new_from_x_y(200, 200, 400, 400)];
+        assert!(matches!(
+            Redraw::between(&before, &after, &screen),
+            Redraw::Full
+        ));
+    }
+
+    #[test]
+    fn only_what_a_change_touches_is_redrawn() {
+        let screen = Rect::new_from_x_y(0, 0, 600, 600);
+        let before = Rect::new_from_x_y(250, 100, 100, 120);
+        let after = Rect::new_from_x_y(250, 100, 101, 120);
+        let redraw = Redraw::between(&[before.clone()], &[after.clone()], &screen);
+
+        assert!(redraw.needs(&before));
+        assert!(redraw.needs(&after));
+        assert!(redraw.needs(&Rect::new_from_x_y(340, 200, 50, 50)));
+        (0..6).for_each(|index| {
+            assert!(!redraw.needs(&Rect::new_from_x_y(index * 100, 400, 90, 90)));
+        });
+    }
+
+    #[test]
+    fn update_count_follows_elapsed_time_not_frame_rate() {
+        // Half a step past a boundary, so rounding can't tip the count either way
+        let elapsed = 120.5 * FRAME_SIZE;
+        [1000.0 / 144.0, 1000.0 / 60.0, 1000.0 / 30.0, 50.0, 7.0]
+            .iter()
+            .for_each(|frame_delta| assert_eq!(steps_for_cadence(*frame_delta, elapsed), 120));
+    }
+
+    #[test]
+    fn update_count_with_irregular_frames() {
+        let mut timestep = FixedTimestep::default();
+        let steps: u32 = [5.0, 40.0, 3.0, 16.0, 100.0, 2.5]
+            .iter()
+            .map(|delta| timestep.advance(*delta))
+            .sum();
+        assert_eq!(steps, (166.5 / FRAME_SIZE) as u32);
+    }
+
+    #[test]
+    fn interpolation_is_the_leftover_fraction_of_a_step() {
+        let mut timestep = FixedTimestep::default();
+        timestep.advance(FRAME_SIZE * 2.25);
+        assert!((timestep.interpolation() - 0.25).abs() < 0.001);
+    }
+
+    #[test]
+    fn rect_contains_points_inside_but_not_on_the_far_edges() {
+        let rect = Rect::new_from_x_y(10, 20, 100, 50);
+        assert!(rect.contains(&Point { x: 10, y: 20 }));
+        assert!(rect.contains(&Point { x: 109, y: 69 }));
+        assert!(!rect.contains(&Point { x: 110, y: 40 }));
+        assert!(!rect.contains(&Point { x: 50, y: 70 }));
+        assert!(!rect.contains(&Point { x: 9, y: 40 }));
+    }
+
+    #[test]
+    fn virtual_button_is_pressed_until_its_pointer_lifts() {
+        let buttons = [VirtualButton {
+            area: Rect::new_from_x_y(0, 500, 100, 100),
+            code: "Space".into(),
+            label: "JUMP".into(),
+        }];
+        let (mut sender, mut receiver) = unbounded();
+        let mut state = KeyState::new();
+
+        sender
+            .start_send(KeyPress::PointerDown {
+                pointer_id: 1,
+                position: Point { x: 300, y: 550 },
+            })
+            .unwrap();
+        process_input(&mut state, &mut receiver, &buttons);
+        assert!(!state.is_pressed("Space"));
+
+        sender
+            .start_send(KeyPress::PointerDown {
+                pointer_id: 2,
+                position: Point { x: 50, y: 550 },
+            })
+            .unwrap();
+        process_input(&mut state, &mut receiver, &buttons);
+        assert!(state.is_pressed("Space"));
+
+        sender
+            .start_send(KeyPress::PointerUp { pointer_id: 2 })
+            .unwrap();
+        process_input(&mut state, &mut receiver, &buttons);
+        assert!(!state.is_pressed("Space"));
+    }
+
+    #[test]
+    fn noop_audio_succeeds_without_an_audio_context() {
+        let audio = Audio::new_noop();
+        let sound = WebAudioSound::new_noop();
+        assert!(audio.play_sound(&sound).is_ok());
+        assert!(audio.play_looping_sound(&sound).is_ok());
+        assert!(audio.play_sound_at(&sound, 300.0, 600.0).is_ok());
+        assert!(audio.mute().is_ok());
+        assert!(audio.unmute().is_ok());
+        assert!(futures::executor::block_on(audio.load_sound("jump.mp3")).is_ok());
+    }
+
+    #[test]
+    fn held_key_is_just_pressed_for_one_update_only() {
+        let (mut sender, mut receiver) = unbounded();
+        let mut state = KeyState::new();
+        let press = KeyPress::PointerDown {
+            pointer_id: 1,
+            position: Point { x: 50, y: 50 },
+        };
+        let buttons = [VirtualButton {
+            area: Rect::new_from_x_y(0, 0, 100, 100),
+            code: "Space".into(),
+            label: "JUMP".into(),
+        }];
+
+        sender.start_send(press).unwrap();
+        process_input(&mut state, &mut receiver, &buttons);
+        assert!(state.is_just_pressed("Space"));
+        state.remember_pressed_keys();
+
+        (0..3).for_each(|_update| {
+            process_input(&mut state, &mut receiver, &buttons);
+            assert!(state.is_pressed("Space"));
+            assert!(!state.is_just_pressed("Space"));
+            state.remember_pressed_keys();
+        });
+
+        sender
+            .start_send(KeyPress::PointerUp { pointer_id: 1 })
+            .unwrap();
+        process_input(&mut state, &mut receiver, &buttons);
+        assert!(state.is_just_released("Space"));
+        state.remember_pressed_keys();
+        assert!(!state.is_just_released("Space"));
+    }
+
+    #[test]
+    fn mouse_movement_adds_up_over_a_frame_only() {
+        let (mut sender, mut receiver) = unbounded();
+        let mut state = KeyState::new();
+        [(3, -4), (5, -6)].iter().for_each(|(x, y)| {
+            sender
+                .start_send(KeyPress::MouseMove {
+                    movement: Point { x: *x, y: *y },
+                })
+                .unwrap();
+        });
+        process_input(&mut state, &mut receiver, &[]);
+        let movement = state.mouse_movement();
+        assert_eq!((movement.x, movement.y), (8, -10));
+
+        process_input(&mut state, &mut receiver, &[]);
+        assert_eq!((state.mouse_movement().x, state.mouse_movement().y), (0, 0));
+    }
+
+    #[test]
+    fn injected_presses_last_one_update() {
+        let mut state = KeyState::new();
+        state.inject_press("ArrowRight");
+        assert!(state.is_just_pressed("ArrowRight"));
+        state.remember_pressed_keys();
+        assert!(!state.is_pressed("ArrowRight"));
+        assert!(state.is_just_released("ArrowRight"));
+
+        state.inject_press("ArrowRight");
+        state.remember_pressed_keys();
+        state.inject_press("ArrowRight");
+        assert!(state.is_pressed("ArrowRight"));
+        assert!(!state.is_just_pressed("ArrowRight"));
+    }
+
+    #[test]
+    fn center_of_even_sized_rect() {
+        let center = Rect::new_from_x_y(10, 20, 100, 50).center();
+        assert_eq!((center.x, center.y), (60, 45));
+    }
+
+    #[test]
+    fn center_of_odd_sized_rect() {
+        let center = Rect::new_from_x_y(10, 20, 101, 51).center();
+        assert_eq!((center.x, center.y), (60, 45));
+    }
+
+    #[test]
+    fn scaled_rect_keeps_its_center() {
+        let rect = Rect::new_from_x_y(10, 20, 100, 50);
+        let bigger = rect.scaled(1.1);
+        assert_eq!((bigger.width, bigger.height), (110, 55));
+        assert_eq!((bigger.center().x, bigger.center().y), (60, 45));
+        assert!(rect.scaled(1.0) == rect);
+    }
+
+    #[test]
+    fn distance_between_axis_aligned_points() {
+        let origin = Point { x: 0, y: 0 };
+        assert_eq!(origin.distance_to(&Point { x: 30, y: 0 }), 30.0);
+        assert_eq!(origin.distance_to(&Point { x: 0, y: -40 }), 40.0);
+    }
+
+    #[test]
+    fn distance_between_diagonal_points() {
+        let a = Point { x: -3, y: 4 };
+        let b = Point { x: 3, y: -4 };
+        assert!((a.distance_to(&b) - 10.0).abs() < f32::EPSILON);
+        assert!((b.distance_to(&a) - 10.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn distance_does_not_overflow_at_the_edges() {
+        let a = Point {
+            x: i16::MIN,
+            y: i16::MIN,
+        };
+        let b = Point {
+            x: i16::MAX,
+            y: i16::MAX,
+        };
+        assert!((a.distance_to(&b) - 65535.0 * 2f32.sqrt()).abs() < 1.0);
+    }
+
+    #[test]
+    fn text_height_leaves_room_between_lines() {
+        assert_eq!(Renderer::text_height(20), 24.0);
+        assert_eq!(Renderer::text_height(0), 0.0);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn single_step_runs_one_update_per_key_press() {
+        let mut single_step = SingleStep::default();
+        let mut keystate = KeyState::new();
+        assert_eq!(single_step.gate(&keystate, 2), 2);
+
+        keystate.set_pressed(SINGLE_STEP_TOGGLE_KEY, None);
+        assert_eq!(single_step.gate(&keystate, 2), 0);
+        keystate.set_released(SINGLE_STEP_TOGGLE_KEY);
+
+        keystate.set_pressed(SINGLE_STEP_KEY, None);
+        assert_eq!(single_step.gate(&keystate, 2), 1);
+        // Still held down, that is no new step
+        assert_eq!(single_step.gate(&keystate, 2), 0);
+        keystate.set_released(SINGLE_STEP_KEY);
+        assert_eq!(single_step.gate(&keystate, 2), 0);
+
+        keystate.set_pressed(SINGLE_STEP_TOGGLE_KEY, None);
+        assert_eq!(single_step.gate(&keystate, 2), 2);
+    }
 }
@@ -1,25 +1,186 @@
-use crate::engine::{Audio, Game, Image, KeyState, Rect, Renderer, Sound, SpriteSheet};
+use crate::browser::UiBuilder;
+use crate::config::{
+    self, ControlScheme, GameConfig, KeyBindings, PhysicsConfig, Preset, SegmentEntry, Settings,
+    BOSS_SEGMENT, GAP_AND_PLATFORM_SEGMENT, GLOWING_PLATFORM_SEGMENT, HANGING_BARRIER_SEGMENT,
+    LONG_PLATFORM_SEGMENT, OTHER_PLATFORM_SEGMENT, PIT_SEGMENT, REST_GAP_SEGMENT,
+    STONE_AND_PLATFORM_SEGMENT, WINDY_PLATFORM_SEGMENT,
+};
+use crate::engine::{
+    AudioBackend, Drawable, Game, Image, KeyState, Rect, Redraw, Renderer, Screen, SpriteSheet,
+    VirtualButton, WebAudioEngine, WebAudioSound,
+};
 use crate::{browser, engine};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use futures::channel::mpsc::UnboundedReceiver;
-use rand::{thread_rng, Rng};
+use rand::distributions::WeightedIndex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::borrow::Cow;
+use std::cell::{self, RefCell};
 use std::collections::HashMap;
+use std::ops::{Add, Sub};
 use std::rc::Rc;
-use web_sys::HtmlImageElement;
+use web_sys::{HtmlElement, HtmlImageElement};
 
 use crate::game::red_hat_boy_states::{
     Falling, FallingState, Idle, Jumping, JumpingEndState, KnockedOut, RedHatBoyContext,
-    RedHatBoyState, Running, Sliding, SlidingEndState,
+    RedHatBoyState, Running, Sliding, SlidingEndState, DASH_SPEED_MULTIPLIER, FRAMES_PER_CELL,
+    PLAYER_HEIGHT,
 };
-use crate::segment::{other_platform, stone_and_platform};
-use serde::Deserialize;
-
-const HEIGHT: i16 = 600;
-const TIMELINE_MINIMUM: i16 = 1000;
-const OBSTACLE_BUFFER: i16 = 20;
-
-#[derive(Deserialize, Clone)]
+use crate::segment::{
+    boss_segment, floating_platform_bounding_boxes, gap_and_platform, glowing_platform,
+    hanging_barrier_run, long_platform, other_platform, pit, stone_and_platform, windy_platform,
+    FLOATING_PLATFORM_SPRITES,
+};
+use serde::{Deserialize, Serialize, Serializer};
+use wasm_bindgen::JsValue;
+
+const HARD_MODE_SPEED_BONUS: i16 = 2;
+/// After this long the boy runs `SPEED_UP_BONUS` faster for the rest of the
+/// run.
+const SPEED_UP_SECONDS: f64 = 60.0;
+const SPEED_UP_BONUS: i16 = 1;
+const CONTINUE_COST: u32 = 10;
+const DECELERATION: i16 = 1;
+const MUSIC_FADE_IN_MS: f64 = 2000.0;
+const MUSIC_FADE_OUT_MS: f64 = 1000.0;
+// A screen width to find the keys before the first obstacle
+pub const GRACE_DISTANCE: i16 = 600;
+const REDUCED_MOTION_SPEED_PERCENT: i16 = 75;
+const MIN_RENDER_SCALE: f64 = 1.0;
+const MAX_RENDER_SCALE: f64 = 2.0;
+const RENDER_SCALE_STEP: f64 = 0.01;
+const HUD_FONT_SIZE: u16 = 24;
+const HUD_MARGIN: i16 = 16;
+const HARD_MODE_LABEL: &str = "HARD";
+const CLEAR_COLOR: &str = "#000000";
+const SKY_TOP_COLOR: &str = "#1E3A5F";
+const SKY_BOTTOM_COLOR: &str = "#F4B6C2";
+const PROGRESS_BAR_HEIGHT: i16 = 6;
+const PROGRESS_BAR_BACKGROUND_COLOR: &str = "rgba(0, 0, 0, 0.4)";
+const PROGRESS_BAR_COLOR: &str = "#FFD700";
+const POWER_UP_TICKS: u16 = 300;
+const ROCKET_TICKS: u16 = 180;
+// Ten seconds upside down
+const GRAVITY_FLIP_TICKS: u16 = 600;
+const POWER_UP_FLICKER_TICKS: u16 = 60;
+const POWER_UP_FLICKER_PERIOD: u16 = 8;
+const GHOST_ALPHA: f64 = 0.5;
+const MAGNET_RADIUS: i16 = 150;
+const MAGNET_SPEED: f32 = 6.0;
+const COIN_RADIUS: i16 = 12;
+// Coins bob between 90% and 110% of their size
+const COIN_BOB_AMPLITUDE: f64 = 0.1;
+const COIN_BOB_SPEED: f64 = 0.1;
+const COIN_FLOAT_HEIGHT: f64 = 5.0;
+const BARRIER_WARNING_DISTANCE: i16 = 100;
+const BARRIER_WARNING_COLOR: &str = "#FF0000";
+// Only listened to in debug mode, to try a power-up without finding a pickup
+const POWER_UP_CHEAT_KEYS: [(&str, PowerUp); 5] = [
+    ("KeyG", PowerUp::Ghost),
+    ("KeyC", PowerUp::Magnet),
+    ("KeyT", PowerUp::SlowMotion),
+    ("KeyR", PowerUp::Rocket),
+    ("KeyV", PowerUp::GravityFlip),
+];
+// One segment in this many has a power-up to pick up in front of it
+const POWER_UP_PICKUP_ODDS: u32 = 8;
+// High enough that the boy has to jump for it
+const POWER_UP_PICKUP_Y: i16 = GROUND - 200;
+const POWER_UP_PICKUP_SCALE: f64 = 0.5;
+const SLOW_MOTION_FACTOR: i16 = 2;
+const SLOW_MOTION_TINT_COLOR: &str = "#0000ff";
+const SLOW_MOTION_TINT_ALPHA: f64 = 0.1;
+const POWER_UP_BAR_HEIGHT: i16 = 4;
+const POWER_UP_BAR_COLOR: &str = "#7FDBFF";
+const ROCKET_FLAME_PUFFS: i16 = 3;
+const ROCKET_FLAME_COLORS: [&str; 3] = ["#FFF3B0", "#FFA500", "#FF4500"];
+const THEME_FADE_DISTANCE: u32 = 400;
+const DIFFICULTY_FLOOR_DISTANCE: u32 = 2000;
+const MILESTONE_DISTANCE: u32 = 1000;
+const MILESTONE_BANNER_FRAMES: u8 = 30;
+const MILESTONE_FONT_SIZE: u16 = 48;
+const REST_EVERY_DISTANCE: u32 = 4000;
+const SAFE_ZONE_BANNER_FRAMES: u8 = 60;
+const SAFE_ZONE_LABEL: &str = "Safe Zone";
+/// A plain jump off the ground lasts about 50 frames, only longer ones earn
+/// air time points.
+const AIR_TIME_THRESHOLD_FRAMES: u16 = 60;
+const AIR_FRAMES_PER_POINT: u16 = 5;
+const AIR_POPUP_FRAMES: u8 = 40;
+const AIR_POPUP_FONT_SIZE: u16 = 20;
+const AIR_POPUP_RISE: i16 = 30;
+/// How far past the right edge of the view segments are generated at least.
+const TIMELINE_LEAD: i16 = 400;
+const SAFE_GAP_STEP: i16 = 20;
+/// The difficulty floor never leaves fewer segments than this to pick from.
+const MIN_SEGMENT_VARIETY: usize = 3;
+const SEGMENTS: [&str; 8] = [
+    STONE_AND_PLATFORM_SEGMENT,
+    OTHER_PLATFORM_SEGMENT,
+    GLOWING_PLATFORM_SEGMENT,
+    WINDY_PLATFORM_SEGMENT,
+    PIT_SEGMENT,
+    HANGING_BARRIER_SEGMENT,
+    GAP_AND_PLATFORM_SEGMENT,
+    LONG_PLATFORM_SEGMENT,
+];
+const DEBUG_BOX_COLORS: [&str; 3] = ["#FF0000", "#00FF00", "#0000FF"];
+const WIND_FORCE: i16 = 2;
+const WIND_LINES: i16 = 6;
+const WIND_LINE_LENGTH: i16 = 40;
+const WIND_LINE_SPEED: u16 = 7;
+const WIND_LINE_COLOR: &str = "#FFFFFF";
+const WIND_LINE_ALPHA: f64 = 0.5;
+const RAIN_START_SECONDS: f64 = 30.0;
+const RAIN_DROPS: usize = 80;
+const RAIN_COLOR: &str = "#A0B8D8";
+const RAIN_LINE_WIDTH: f64 = 1.0;
+/// How far a drop drifts to the left for every pixel it falls.
+const RAIN_SLANT: f64 = 0.2;
+const RAIN_TINT_COLOR: &str = "#0A1A40";
+const RAIN_TINT_ALPHA: f64 = 0.25;
+const BOSS_DISTANCE: u32 = 10000;
+const BOSS_HEALTH: u8 = 3;
+const BOSS_SCORE: u32 = 500;
+const BOSS_WIDTH: i16 = 160;
+pub const BOSS_HEIGHT: i16 = 120;
+const BOSS_PATROL_RANGE: i16 = 200;
+const BOSS_SPEED: i16 = 1;
+const BOSS_DYING_FRAMES: u16 = 60;
+const BOSS_DYING_FLICKER: u16 = 4;
+const BOSS_HEALTH_PIP_RADIUS: i16 = 6;
+const BOSS_HEALTH_PIP_SPACING: i16 = 20;
+const BOSS_HEALTH_PIP_HEIGHT: i16 = 15;
+const BOSS_HEALTH_COLOR: &str = "#FF4136";
+/// Where the feet are on flat ground, the bottom of the world.
+pub const GROUND: i16 = 600;
+/// The top of a `HangingBarrier`, its stone reaches down past a running
+/// boy's head but not to a sliding one's, see `PhysicsConfig::slide_box_crop`.
+pub const DUCK_HEIGHT: i16 = 460;
+/// The stone's sprite has transparent margins, running into those is fair.
+pub const STONE_HITBOX_INSET: i16 = 8;
+const PIT_EDGE_HEIGHT: i16 = 24;
+const PIT_COLOR: &str = "#000000";
+const RAISED_GROUND_COLOR: &str = "#6B4F2A";
+const SAVE_STORE: &str = "saved_games";
+const SAVE_KEY: &str = "walk";
+const BEST_RUN_KEY: &str = "best_run";
+// Where only the best score was kept before there was a best run
+const LEGACY_BEST_SCORE_KEY: &str = "best_score";
+const TITLE: &str = "Walk the Dog";
+const TITLE_SCORE_STEP: u32 = 100;
+const DASH_TRAIL_LENGTH: i16 = 3;
+const DASH_TRAIL_SPACING: i16 = 20;
+const DASH_TRAIL_ALPHA: f64 = 0.4;
+const VIRTUAL_BUTTON_WIDTH: i16 = 120;
+const VIRTUAL_BUTTON_HEIGHT: i16 = 80;
+const VIRTUAL_BUTTON_MARGIN: i16 = 20;
+// Smaller mouse movements are just a shaky hand
+const MOUSE_DEADZONE: i16 = 4;
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct SheetRect {
     x: i16,
     y: i16,
@@ -27,11 +188,34 @@ pub struct SheetRect {
     h: i16,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Cell {
     frame: SheetRect,
     pub sprite_source_size: SheetRect,
+    /// How long the cell is shown in milliseconds, if the exporter wrote it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+}
+
+/// The game loop runs a fixed number of updates per second whatever the
+/// refresh rate, so counting updates is counting time.
+const MILLISECONDS_PER_UPDATE: f32 = (engine::STEP_SECONDS * 1000.0) as f32;
+
+impl Cell {
+    pub fn frame_rect(&self) -> Rect {
+        Rect::new_from_x_y(self.frame.x, self.frame.y, self.frame.w, self.frame.h)
+    }
+
+    /// The number of updates the cell is shown for, at least one.
+    /// `FRAMES_PER_CELL` without a duration.
+    fn updates(&self) -> u8 {
+        self.duration.map_or(FRAMES_PER_CELL, |duration| {
+            (duration as f32 / MILLISECONDS_PER_UPDATE)
+                .round()
+                .clamp(1.0, u8::MAX.into()) as u8
+        })
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -39,18 +223,190 @@ pub struct Sheet {
     pub(crate) frames: HashMap<String, Cell>,
 }
 
-#[derive(Clone, Copy, Default)]
+impl Sheet {
+    /// All cells whose name starts with `prefix`, ordered by the index in
+    /// their name, e.g. "Run (2).png" comes before "Run (10).png".
+    pub fn cells_by_prefix<'a>(&'a self, prefix: &str) -> Vec<&'a Cell> {
+        let mut cells: Vec<(u32, &Cell)> = self
+            .frames
+            .iter()
+            .filter(|(name, _cell)| name.starts_with(prefix))
+            .map(|(name, cell)| (frame_index(name), cell))
+            .collect();
+        cells.sort_by_key(|(index, _cell)| *index);
+        cells.into_iter().map(|(_index, cell)| cell).collect()
+    }
+
+    pub fn frame_count_for(&self, animation: &str) -> u8 {
+        self.cells_by_prefix(&format!("{} (", animation)).len() as u8
+    }
+
+    /// The update at which every cell of `animation` ends, counted from the
+    /// start of the animation.
+    pub fn cell_ends(&self, animation: &str) -> Vec<u8> {
+        self.cells_by_prefix(&format!("{} (", animation))
+            .iter()
+            .scan(0u8, |end, cell| {
+                *end = end.saturating_add(cell.updates());
+                Some(*end)
+            })
+            .collect()
+    }
+}
+
+fn frame_index(name: &str) -> u32 {
+    name.rsplit('(')
+        .next()
+        .and_then(|index| index.split(')').next())
+        .and_then(|index| index.parse().ok())
+        .unwrap_or(0)
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 pub struct Point {
     pub x: i16,
     pub y: i16,
 }
 
+impl Point {
+    pub fn distance_to(&self, other: &Point) -> f32 {
+        // Subtract as f32, the difference of two i16 can overflow
+        let dx = f32::from(other.x) - f32::from(self.x);
+        let dy = f32::from(other.y) - f32::from(self.y);
+        dx.hypot(dy)
+    }
+
+    /// Both coordinates times `factor`, rounded to the nearest pixel and
+    /// saturating at the ends of `i16`.
+    pub fn scale(self, factor: f32) -> Point {
+        Point {
+            x: (f32::from(self.x) * factor).round() as i16,
+            y: (f32::from(self.y) * factor).round() as i16,
+        }
+    }
+}
+
+/// Saturates instead of overflowing, a point far off in the world sticks to
+/// the edge of `i16` instead of wrapping around to the other side.
+impl Add for Point {
+    type Output = Point;
+
+    fn add(self, other: Point) -> Point {
+        Point {
+            x: self.x.saturating_add(other.x),
+            y: self.y.saturating_add(other.y),
+        }
+    }
+}
+
+/// Saturates like `add`.
+impl Sub for Point {
+    type Output = Point;
+
+    fn sub(self, other: Point) -> Point {
+        Point {
+            x: self.x.saturating_sub(other.x),
+            y: self.y.saturating_sub(other.y),
+        }
+    }
+}
+
 pub struct WalkTheDog {
     machine: Option<WalkTheDogStateMachine>,
+    status: Rc<GameStatus>,
+    last_frame: RefCell<Option<DrawnFrame>>,
+    virtual_buttons: Vec<VirtualButton>,
+}
+
+/// The part of the game the embedding page can see and poke at. It is shared
+/// between the running `WalkTheDog` and the `GameHandle` handed to JavaScript.
+pub struct GameStatus {
+    score: cell::Cell<u32>,
+    state: cell::Cell<&'static str>,
+    difficulty: cell::Cell<&'static str>,
+    reset_requested: cell::Cell<bool>,
+    physics: cell::Cell<PhysicsConfig>,
+    physics_request: cell::Cell<Option<PhysicsConfig>>,
+    preset_request: cell::Cell<Option<Preset>>,
+}
+
+impl GameStatus {
+    fn new() -> Self {
+        GameStatus {
+            score: cell::Cell::new(0),
+            state: cell::Cell::new("Loading"),
+            difficulty: cell::Cell::new("Normal"),
+            reset_requested: cell::Cell::new(false),
+            physics: cell::Cell::new(PhysicsConfig::default()),
+            physics_request: cell::Cell::new(None),
+            preset_request: cell::Cell::new(None),
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score.get()
+    }
+
+    pub fn state(&self) -> &'static str {
+        self.state.get()
+    }
+
+    pub fn difficulty(&self) -> &'static str {
+        self.difficulty.get()
+    }
+
+    /// The reset happens on the next update, so a paused game resets once it
+    /// is resumed.
+    pub fn request_reset(&self) {
+        self.reset_requested.set(true);
+    }
+
+    /// Changes one value of the physics from the next update on, see
+    /// `PhysicsConfig::set`. Several changes before that update all apply.
+    pub fn request_physics_param(&self, name: &str, value: f64) -> Result<()> {
+        let mut physics = self.physics_request.get().unwrap_or(self.physics.get());
+        physics.set(name, value)?;
+        self.physics_request.set(Some(physics));
+        Ok(())
+    }
+
+    /// Picks the preset called `name` for the runs started afterwards, a run
+    /// under way keeps its own.
+    pub fn request_preset(&self, name: &str) -> Result<()> {
+        self.preset_request.set(Some(Preset::from_name(name)?));
+        Ok(())
+    }
+
+    fn publish(&self, machine: &WalkTheDogStateMachine) {
+        self.update_title(machine.state_name(), machine.walk().score());
+        self.score.set(machine.walk().score());
+        self.state.set(machine.state_name());
+        self.difficulty.set(machine.walk().difficulty.level_name());
+        self.physics.set(machine.walk().config.physics);
+    }
+
+    /// Shows the score in the tab title every `TITLE_SCORE_STEP` points, and
+    /// the best score once the game is over. Compares against what was
+    /// published last, so it has to run before the new values are set.
+    fn update_title(&self, state: &'static str, score: u32) {
+        let previous_state = self.state.get();
+        match state {
+            "Ready" if previous_state != "Ready" => browser::set_document_title(TITLE),
+            "Walking" if score / TITLE_SCORE_STEP > self.score.get() / TITLE_SCORE_STEP => {
+                browser::set_document_title(&format!("{} \u{2014} Score: {}", TITLE, score))
+            }
+            "GameOver" if previous_state != "GameOver" => {
+                let best = load_best_run().map_or(0, |best| best.score).max(score);
+                browser::set_document_title(&format!("GAME OVER \u{2014} Best: {}", best));
+            }
+            _ => {}
+        }
+    }
 }
 
 enum WalkTheDogStateMachine {
     Ready(WalkTheDogState<Ready>),
+    Countdown(WalkTheDogState<Countdown>),
     Walking(WalkTheDogState<Walking>),
     GameOver(WalkTheDogState<GameOver>),
 }
@@ -60,71 +416,622 @@ impl WalkTheDogStateMachine {
         WalkTheDogStateMachine::Ready(WalkTheDogState::new(walk))
     }
 
-    fn update(self, keystate: &KeyState) -> Self {
+    /// The ready screen, offering to continue `saved_walk`.
+    fn with_saved_walk(walk: Walk, saved_walk: WalkSnapshot) -> Self {
+        WalkTheDogStateMachine::Ready(WalkTheDogState::with_saved_walk(walk, saved_walk))
+    }
+
+    fn update(self, keystate: &KeyState, delta: f64) -> Self {
         match self {
             WalkTheDogStateMachine::Ready(state) => state.update(keystate).into(),
-            WalkTheDogStateMachine::Walking(state) => state.update(keystate).into(),
-            WalkTheDogStateMachine::GameOver(state) => state.update().into(),
+            WalkTheDogStateMachine::Countdown(state) => state.update(keystate).into(),
+            WalkTheDogStateMachine::Walking(state) => state.update(keystate, delta).into(),
+            WalkTheDogStateMachine::GameOver(state) => state.update(keystate).into(),
         }
     }
 
-    fn draw(&self, renderer: &Renderer) {
+    fn is_game_over(&self) -> bool {
+        matches!(self, WalkTheDogStateMachine::GameOver(_))
+    }
+
+    fn is_walking(&self) -> bool {
+        matches!(self, WalkTheDogStateMachine::Walking(_))
+    }
+
+    fn state_name(&self) -> &'static str {
+        match self {
+            WalkTheDogStateMachine::Ready(_) => "Ready",
+            WalkTheDogStateMachine::Countdown(_) => "Countdown",
+            WalkTheDogStateMachine::Walking(_) => "Walking",
+            WalkTheDogStateMachine::GameOver(_) => "GameOver",
+        }
+    }
+
+    fn reset(self) -> Self {
+        if self.is_game_over() || self.has_saved_walk() {
+            if let Err(err) = browser::hide_ui() {
+                log!("Could not hide the UI {:#?}", err);
+            }
+        }
+        if self.is_game_over() {
+            self.walk().fade_in_music();
+        }
+        let walk = match self {
+            WalkTheDogStateMachine::Ready(state) => state.walk,
+            WalkTheDogStateMachine::Countdown(state) => state.walk,
+            WalkTheDogStateMachine::Walking(state) => state.walk,
+            WalkTheDogStateMachine::GameOver(state) => state.walk,
+        };
+        WalkTheDogStateMachine::new(Walk::reset(walk))
+    }
+
+    fn has_saved_walk(&self) -> bool {
+        matches!(self, WalkTheDogStateMachine::Ready(state) if state._state.saved_walk.is_some())
+    }
+
+    fn walk(&self) -> &Walk {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => &state.walk,
+            WalkTheDogStateMachine::Countdown(state) => &state.walk,
+            WalkTheDogStateMachine::Walking(state) => &state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &state.walk,
+        }
+    }
+
+    fn walk_mut(&mut self) -> &mut Walk {
+        match self {
+            WalkTheDogStateMachine::Ready(state) => &mut state.walk,
+            WalkTheDogStateMachine::Countdown(state) => &mut state.walk,
+            WalkTheDogStateMachine::Walking(state) => &mut state.walk,
+            WalkTheDogStateMachine::GameOver(state) => &mut state.walk,
+        }
+    }
+
+    /// Only the ready and game over screens can be partially redrawn, the
+    /// others change all over the screen every frame. The game over summary
+    /// only once it stopped counting.
+    fn is_static(&self) -> bool {
+        match self {
+            WalkTheDogStateMachine::Ready(_) => true,
+            WalkTheDogStateMachine::GameOver(state) => state._state.tallied(),
+            _ => false,
+        }
+    }
+
+    fn stats_tallied(&self) -> bool {
+        matches!(self, WalkTheDogStateMachine::GameOver(state) if state._state.tallied())
+    }
+
+    fn draw(&self, renderer: &Renderer, interpolation: f32, redraw: &Redraw) {
+        // Only a walking world moves between updates, everything else is
+        // drawn exactly where the last update left it
         match self {
-            WalkTheDogStateMachine::Ready(state) => state.draw(renderer),
-            WalkTheDogStateMachine::Walking(state) => state.draw(renderer),
-            WalkTheDogStateMachine::GameOver(state) => state.draw(renderer),
+            WalkTheDogStateMachine::Ready(state) => state.draw(renderer, redraw),
+            WalkTheDogStateMachine::Countdown(state) => {
+                state.draw(renderer, &Redraw::Full);
+                state.draw_countdown(renderer);
+            }
+            WalkTheDogStateMachine::Walking(state) => {
+                state
+                    .walk
+                    .draw_interpolated(renderer, interpolation, &Redraw::Full);
+                state.draw_progress(renderer);
+                state.draw_go(renderer);
+            }
+            WalkTheDogStateMachine::GameOver(state) => {
+                state.draw(renderer, redraw);
+                state.draw_summary(renderer);
+                state.draw_comparison(renderer);
+            }
         }
     }
 }
 
+/// Everything that changes the whole picture at once. A frame can only be
+/// redrawn partially if this stayed the same since the last one.
+#[derive(PartialEq)]
+struct Scene {
+    state: &'static str,
+    render_scale_bits: u64,
+    hard_mode: bool,
+    stats_tallied: bool,
+    control_scheme: ControlScheme,
+}
+
+struct DrawnFrame {
+    scene: Scene,
+    regions: Vec<Rect>,
+}
+
 struct WalkTheDogState<T> {
     _state: T,
     walk: Walk,
 }
 
 impl<T> WalkTheDogState<T> {
-    fn draw(&self, renderer: &Renderer) {
-        self.walk.draw(renderer)
+    fn draw(&self, renderer: &Renderer, redraw: &Redraw) {
+        self.walk.draw_interpolated(renderer, 1.0, redraw)
+    }
+}
+
+struct Ready {
+    saved_walk: Option<SavedWalk>,
+}
+/// A run saved when the player left the page, offered on the ready screen.
+struct SavedWalk {
+    snapshot: WalkSnapshot,
+    continue_event: UnboundedReceiver<()>,
+}
+struct Countdown {
+    frame: u16,
+}
+struct Walking {
+    go_frames: u16,
+    /// Updates left before the game over UI shows up, counting down once the
+    /// boy is knocked out so his fall reads before the menu covers it.
+    knocked_out_frames: Option<u16>,
+    /// The best run from before this one started. A continued run is still
+    /// compared against it, not against where it ended the first time.
+    previous_best: Option<BestRun>,
+}
+/// What the boy did on a run, counted as it goes.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RunTally {
+    jumps: u32,
+    slides: u32,
+    knockouts: u32,
+    updates: u32,
+    coins: u32,
+    /// How many coins of each `CoinKind::ALL` were collected.
+    #[serde(default)]
+    coins_by_kind: [u32; 3],
+    /// Coins collected since the last one that was missed.
+    combo: u32,
+    combo_max: u32,
+}
+
+impl RunTally {
+    fn count_move(&mut self, before: BoyMove, after: BoyMove) {
+        if before == after {
+            return;
+        }
+        match after {
+            BoyMove::Jumping => self.jumps += 1,
+            BoyMove::Sliding => self.slides += 1,
+            BoyMove::Running => {}
+        }
+    }
+
+    fn collect_coin(&mut self, kind: CoinKind) {
+        self.coins += 1;
+        self.coins_by_kind[kind as usize] += 1;
+        self.extend_combo();
+    }
+
+    fn extend_combo(&mut self) {
+        self.combo += 1;
+        self.combo_max = self.combo_max.max(self.combo);
+    }
+}
+
+/// The summary of a finished run.
+struct RunStats {
+    score: u32,
+    distance: u32,
+    coins_by_kind: [u32; 3],
+    /// What all the coins were worth together.
+    coin_score: u32,
+    jumps: u32,
+    slides: u32,
+    knockouts: u32,
+    time_seconds: u32,
+    combo_max: u32,
+}
+
+impl RunStats {
+    fn from_walk(walk: &Walk) -> Self {
+        let tally = &walk.tally;
+        RunStats {
+            score: walk.score(),
+            distance: walk.distance,
+            coins_by_kind: tally.coins_by_kind,
+            coin_score: CoinKind::ALL
+                .iter()
+                .zip(tally.coins_by_kind)
+                .map(|(kind, count)| kind.value() * count * walk.config.coin_value)
+                .sum(),
+            jumps: tally.jumps,
+            slides: tally.slides,
+            knockouts: tally.knockouts,
+            time_seconds: walk.elapsed_seconds as u32,
+            combo_max: tally.combo_max,
+        }
+    }
+
+    /// "S" to "C", the score and distance earn points and every knockout
+    /// takes some away.
+    fn grade(&self) -> &'static str {
+        let earned = u64::from(self.score) * GRADE_POINTS_PER_SCORE
+            + u64::from(self.distance) / GRADE_DISTANCE_PER_POINT;
+        let points = earned.saturating_sub(u64::from(self.knockouts) * GRADE_POINTS_PER_KNOCKOUT);
+        GRADES
+            .iter()
+            .find(|(threshold, _grade)| points >= *threshold)
+            .map_or(LOWEST_GRADE, |(_threshold, grade)| *grade)
+    }
+
+    fn lines(&self) -> [StatLine; SUMMARY_LINES] {
+        [
+            StatLine::new(StatIcon::Tile("14.png"), self.score),
+            StatLine::new(StatIcon::Boy("Run (1).png"), self.distance),
+            StatLine::new(StatIcon::Coin(CoinKind::Bronze), self.coins_by_kind[0]),
+            StatLine::new(StatIcon::Coin(CoinKind::Silver), self.coins_by_kind[1]),
+            StatLine::new(StatIcon::Coin(CoinKind::Gold), self.coins_by_kind[2]),
+            StatLine::new(StatIcon::CoinScore, self.coin_score),
+            StatLine::new(StatIcon::Boy("Jump (1).png"), self.jumps),
+            StatLine::new(StatIcon::Boy("Slide (1).png"), self.slides),
+            StatLine::new(StatIcon::Boy("Dead (10).png"), self.knockouts),
+            StatLine::new(StatIcon::Boy("Idle (1).png"), self.time_seconds),
+            StatLine::new(StatIcon::Tile("13.png"), self.combo_max),
+        ]
+    }
+}
+
+/// The stats compared between runs, each the best any run did so far.
+/// Kept in localStorage under `BEST_RUN_KEY`.
+#[derive(Clone, Copy, Default, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct BestRun {
+    score: u32,
+    distance: u32,
+    coins: u32,
+    jumps: u32,
+}
+
+impl BestRun {
+    fn from_stats(stats: &RunStats) -> Self {
+        BestRun {
+            score: stats.score,
+            distance: stats.distance,
+            coins: stats.coins_by_kind.iter().sum(),
+            jumps: stats.jumps,
+        }
+    }
+
+    /// The better of the two for every stat, they can come from different
+    /// runs.
+    fn max(self, other: BestRun) -> Self {
+        BestRun {
+            score: self.score.max(other.score),
+            distance: self.distance.max(other.distance),
+            coins: self.coins.max(other.coins),
+            jumps: self.jumps.max(other.jumps),
+        }
+    }
+
+    /// Every stat of this run next to the same stat of `best`.
+    fn compare(&self, best: &BestRun) -> [(StatIcon, u32, u32); COMPARISON_ROWS] {
+        [
+            (StatIcon::Tile("14.png"), self.score, best.score),
+            (StatIcon::Boy("Run (1).png"), self.distance, best.distance),
+            (StatIcon::Coins, self.coins, best.coins),
+            (StatIcon::Boy("Jump (1).png"), self.jumps, best.jumps),
+        ]
+    }
+}
+
+fn load_best_run() -> Option<BestRun> {
+    let saved = browser::load_from_storage(BEST_RUN_KEY).and_then(|json| {
+        json.map(|json| {
+            browser::parse_json(&json)?
+                .into_serde::<BestRun>()
+                .map_err(|err| anyhow!("Could not read the best run {:#?}", err))
+        })
+        .transpose()
+    });
+    match saved {
+        Ok(Some(best)) => Some(best),
+        Ok(None) => load_legacy_best_score(),
+        Err(err) => {
+            log!("Ignoring the saved best run {:#?}", err);
+            None
+        }
+    }
+}
+
+/// The best score of a player from before there was a best run, as one.
+fn load_legacy_best_score() -> Option<BestRun> {
+    match browser::load_from_storage(LEGACY_BEST_SCORE_KEY) {
+        Ok(best) => best
+            .and_then(|best| best.parse().ok())
+            .map(|score| BestRun {
+                score,
+                ..BestRun::default()
+            }),
+        Err(err) => {
+            log!("Could not load the best score {:#?}", err);
+            None
+        }
+    }
+}
+
+fn save_best_run(best: &BestRun) {
+    let saved = JsValue::from_serde(best)
+        .map_err(|err| anyhow!("Could not serialize the best run {:#?}", err))
+        .and_then(|value| browser::stringify_json(&value))
+        .and_then(|json| browser::save_to_storage(BEST_RUN_KEY, &json));
+    if let Err(err) = saved {
+        log!("Could not save the best run {:#?}", err);
+    }
+}
+
+/// A sprite standing in for the name of a stat.
+#[derive(Clone, Copy)]
+enum StatIcon {
+    Boy(&'static str),
+    Tile(&'static str),
+    Coin(CoinKind),
+    /// How many coins of any kind.
+    Coins,
+    CoinScore,
+}
+
+/// A stat of the summary, counting up from 0 to its value.
+struct StatLine {
+    icon: StatIcon,
+    value: u32,
+    display_value: u32,
+}
+
+impl StatLine {
+    fn new(icon: StatIcon, value: u32) -> Self {
+        StatLine {
+            icon,
+            value,
+            display_value: 0,
+        }
+    }
+
+    /// Big and small values alike get there in `SUMMARY_TALLY_FRAMES`.
+    fn count_up(&mut self) {
+        let frames = u32::from(SUMMARY_TALLY_FRAMES);
+        let step = (self.value.saturating_add(frames - 1) / frames).max(1);
+        self.display_value = self.display_value.saturating_add(step).min(self.value);
     }
 }
 
-struct Ready;
-struct Walking;
 struct GameOver {
+    /// Disabled until the stats are tallied, so a click meant for the
+    /// summary can't start a new game by accident.
+    new_game_button: HtmlElement,
     new_game_event: UnboundedReceiver<()>,
+    continue_event: Option<UnboundedReceiver<()>>,
+    submit_score_event: Option<UnboundedReceiver<()>>,
+    stats: RunStats,
+    summary: [StatLine; SUMMARY_LINES],
+    tally_frame: u16,
+    /// The best before this run, `None` on the first one.
+    previous_best: Option<BestRun>,
 }
 
 impl GameOver {
+    fn count_up(&mut self) {
+        let was_tallied = self.tallied();
+        self.tally_frame = (self.tally_frame + 1).min(SUMMARY_TALLY_FRAMES);
+        self.summary.iter_mut().for_each(StatLine::count_up);
+        if self.tallied() && !was_tallied {
+            if let Err(err) = self.new_game_button.remove_attribute("disabled") {
+                log!("Could not enable the new game button {:#?}", err);
+            }
+        }
+    }
+
+    /// The stats stopped counting and the grade is up.
+    fn tallied(&self) -> bool {
+        self.tally_frame >= SUMMARY_TALLY_FRAMES
+    }
+
     fn new_game_pressed(&mut self) -> bool {
         matches!(self.new_game_event.try_next(), Ok(Some(())))
     }
+
+    fn continue_pressed(&mut self) -> bool {
+        self.continue_event
+            .as_mut()
+            .map_or(false, |event| matches!(event.try_next(), Ok(Some(()))))
+    }
+
+    fn submit_score_pressed(&mut self) -> bool {
+        self.submit_score_event
+            .as_mut()
+            .map_or(false, |event| matches!(event.try_next(), Ok(Some(()))))
+    }
 }
 
+const COUNTDOWN_FRAMES_PER_STEP: u16 = 60;
+const COUNTDOWN_STEPS: [&str; 3] = ["3", "2", "1"];
+const COUNTDOWN_GO: &str = "GO!";
+const COUNTDOWN_FONT_SIZE: u16 = 96;
+const KNOCKED_OUT_HOLD_FRAMES: u16 = 45;
+const SUMMARY_LINES: usize = 11;
+const SUMMARY_TALLY_FRAMES: u16 = 90;
+const NEW_GAME_BUTTON_ID: &str = "new_game";
+const CONTINUE_BUTTON_ID: &str = "continue";
+const LEADERBOARD_NAME_ID: &str = "leaderboard_name";
+const SUBMIT_SCORE_BUTTON_ID: &str = "submit_score";
+const CONTINUE_SAVED_BUTTON_ID: &str = "continue_saved";
+const SUMMARY_MARGIN: i16 = 20;
+const SUMMARY_PADDING: i16 = 12;
+const SUMMARY_WIDTH: i16 = 220;
+const SUMMARY_ROW_HEIGHT: i16 = 32;
+const SUMMARY_ICON_SIZE: i16 = 24;
+const SUMMARY_FONT_SIZE: u16 = 20;
+const SUMMARY_GRADE_FONT_SIZE: u16 = 48;
+const PANEL_COLOR: &str = "#000000";
+const PANEL_ALPHA: f64 = 0.6;
+const PANEL_CELL: &str = "2.png";
+const PANEL_CORNER: i16 = 32;
+const PANEL_PADDING: i16 = 12;
+const COMPARISON_ROWS: usize = 4;
+const COMPARISON_WIDTH: i16 = 300;
+const COMPARISON_COLOR: &str = "#FFFFFF";
+const COMPARISON_BETTER_COLOR: &str = "#2ECC40";
+const FIRST_RUN_TEXT: &str = "First run \u{2014} set the bar!";
+// Weights of the grade, a knockout costs as much as a long stretch of running
+const GRADE_POINTS_PER_SCORE: u64 = 10;
+const GRADE_DISTANCE_PER_POINT: u64 = 100;
+const GRADE_POINTS_PER_KNOCKOUT: u64 = 250;
+const GRADES: [(u64, &str); 3] = [(2000, "S"), (1000, "A"), (400, "B")];
+const LOWEST_GRADE: &str = "C";
+
 enum ReadyEndState {
-    Complete(WalkTheDogState<Walking>),
+    Complete(WalkTheDogState<Countdown>),
     Continue(WalkTheDogState<Ready>),
 }
 
 impl WalkTheDogState<Ready> {
     fn new(walk: Walk) -> WalkTheDogState<Ready> {
         WalkTheDogState {
-            _state: Ready,
+            _state: Ready { saved_walk: None },
+            walk,
+        }
+    }
+
+    fn with_saved_walk(walk: Walk, snapshot: WalkSnapshot) -> WalkTheDogState<Ready> {
+        let continue_event =
+            draw_continue_saved_ui().expect("could not build continue saved receiver!");
+        WalkTheDogState {
+            _state: Ready {
+                saved_walk: Some(SavedWalk {
+                    snapshot,
+                    continue_event,
+                }),
+            },
             walk,
         }
     }
+
     fn update(mut self, keystate: &KeyState) -> ReadyEndState {
+        if let Some(mut saved_walk) = self._state.saved_walk.take() {
+            if matches!(saved_walk.continue_event.try_next(), Ok(Some(()))) {
+                if let Err(err) = browser::hide_ui() {
+                    log!("Could not hide the UI {:#?}", err);
+                }
+                self.walk = Walk::restore(self.walk, saved_walk.snapshot);
+                return ReadyEndState::Complete(self.start_countdown());
+            }
+            self._state.saved_walk = Some(saved_walk);
+        }
         self.walk.boy.update();
-        if keystate.is_pressed("ArrowRight") {
-            ReadyEndState::Complete(self.start_running())
+        // The difficulty can only change before a run, never during one
+        let key_bindings = &self.walk.config.key_bindings;
+        let hard_mode =
+            toggle_pressed(keystate, &key_bindings.hard_mode, &key_bindings.normal_mode);
+        let reduced_motion = toggle_pressed(
+            keystate,
+            &key_bindings.reduced_motion,
+            &key_bindings.full_motion,
+        );
+        let control_scheme = [
+            (&key_bindings.keyboard_controls, ControlScheme::Keyboard),
+            (&key_bindings.touch_controls, ControlScheme::Touch),
+            (&key_bindings.mouse_controls, ControlScheme::Mouse),
+        ]
+        .iter()
+        .find(|(code, _scheme)| keystate.is_pressed(code))
+        .map(|(_code, scheme)| *scheme);
+        let preset = [
+            (&key_bindings.standard_preset, Preset::Standard),
+            (&key_bindings.kids_preset, Preset::Kids),
+        ]
+        .iter()
+        .find(|(code, _preset)| keystate.is_pressed(code))
+        .map(|(_code, preset)| *preset);
+        if let Some(hard_mode) = hard_mode {
+            self.walk.difficulty.hard_mode = hard_mode;
+        }
+        if let Some(reduced_motion) = reduced_motion {
+            self.walk.config.reduced_motion = reduced_motion;
+        }
+        if control_scheme.is_some() {
+            self.walk.config.control_scheme = control_scheme;
+        }
+        if let Some(preset) = preset {
+            self.walk.select_preset(preset);
+        }
+        self.walk.zoom(keystate);
+        // Still holding run from the last one doesn't start another run
+        if keystate.is_just_pressed(&self.walk.config.key_bindings.run) {
+            // A new run replaces the saved one
+            if self._state.saved_walk.take().is_some() {
+                if let Err(err) = browser::hide_ui() {
+                    log!("Could not hide the UI {:#?}", err);
+                }
+                delete_saved_walk();
+            }
+            self.walk = Walk::apply_preset(self.walk);
+            ReadyEndState::Complete(self.start_countdown())
         } else {
             ReadyEndState::Continue(self)
         }
     }
 
+    /// Whatever the player changed on the ready screen is kept for the next
+    /// visit.
+    fn start_countdown(self) -> WalkTheDogState<Countdown> {
+        let settings = Settings::new(&self.walk.config, self.walk.difficulty.hard_mode);
+        if let Err(err) = config::save_settings(&settings) {
+            log!("Could not save settings {:#?}", err);
+        }
+        if self.walk.control_scheme() == ControlScheme::Mouse {
+            if let Err(err) = browser::request_pointer_lock() {
+                log!("Could not lock the mouse {:#?}", err);
+            }
+        }
+        WalkTheDogState {
+            _state: Countdown { frame: 0 },
+            walk: self.walk,
+        }
+    }
+}
+
+enum CountdownEndState {
+    Complete(WalkTheDogState<Walking>),
+    Continue(WalkTheDogState<Countdown>),
+}
+
+impl WalkTheDogState<Countdown> {
+    /// Keys don't cut the countdown short, it takes them like every state.
+    fn update(mut self, _keystate: &KeyState) -> CountdownEndState {
+        self.walk.boy.update();
+        self._state.frame += 1;
+        if self._state.frame >= COUNTDOWN_FRAMES_PER_STEP * COUNTDOWN_STEPS.len() as u16 {
+            CountdownEndState::Complete(self.start_running())
+        } else {
+            CountdownEndState::Continue(self)
+        }
+    }
+
+    fn draw_countdown(&self, renderer: &Renderer) {
+        let step = (self._state.frame / COUNTDOWN_FRAMES_PER_STEP) as usize;
+        if let Some(text) = COUNTDOWN_STEPS.get(step) {
+            renderer.draw_text_centered(
+                text,
+                &self.walk.screen.game_rect().center(),
+                COUNTDOWN_FONT_SIZE,
+            );
+        }
+    }
+
     fn start_running(mut self) -> WalkTheDogState<Walking> {
         self.run_right();
         WalkTheDogState {
-            _state: Walking,
+            _state: Walking {
+                go_frames: COUNTDOWN_FRAMES_PER_STEP,
+                knocked_out_frames: None,
+                previous_best: load_best_run(),
+            },
             walk: self.walk,
         }
     }
@@ -136,88 +1043,348 @@ impl WalkTheDogState<Ready> {
 
 enum WalkingEndState {
     Complete(WalkTheDogState<GameOver>),
+    Quit(WalkTheDogState<Ready>),
     Continue(WalkTheDogState<Walking>),
 }
 
 impl WalkTheDogState<Walking> {
-    fn update(mut self, keystate: &KeyState) -> WalkingEndState {
-        let mut velocity = Point { x: 0, y: 0 };
-        if keystate.is_pressed("ArrowDown") {
-            self.walk.boy.slide();
-        }
-        if keystate.is_pressed("ArrowRight") {
-            velocity.x += 3;
-            self.walk.boy.run_right();
-        }
-        if keystate.is_pressed("Space") {
-            self.walk.boy.jump();
-        }
-        self.walk.boy.update();
-
-        let velocity = self.walk.velocity();
-        let [first_background, second_background] = &mut self.walk.backgrounds;
-        first_background.move_horizontally(velocity);
-        second_background.move_horizontally(velocity);
-        if first_background.right() < 0 {
-            first_background.set_x(second_background.right());
+    fn update(mut self, keystate: &KeyState, delta: f64) -> WalkingEndState {
+        if keystate.is_just_pressed(&self.walk.config.key_bindings.quit) {
+            return WalkingEndState::Quit(self.quit());
         }
-        if second_background.right() < 0 {
-            second_background.set_x(first_background.right());
+        self._state.go_frames = self._state.go_frames.saturating_sub(1);
+        self.walk.elapsed_seconds += delta;
+        self.walk.update(keystate);
+
+        match self._state.knocked_out_frames {
+            Some(0) if self.walk.lives > 1 => {
+                self._state.knocked_out_frames = None;
+                self.walk = Walk::lose_life(self.walk);
+                WalkingEndState::Continue(self)
+            }
+            Some(0) => WalkingEndState::Complete(self.end_game()),
+            Some(frames) => {
+                self._state.knocked_out_frames = Some(frames - 1);
+                WalkingEndState::Continue(self)
+            }
+            None if self.walk.knocked_out() => {
+                self.walk.tally.knockouts += 1;
+                self._state.knocked_out_frames = Some(KNOCKED_OUT_HOLD_FRAMES);
+                WalkingEndState::Continue(self)
+            }
+            None => WalkingEndState::Continue(self),
         }
+    }
 
-        self.walk.obstacles.retain(|obstacle| obstacle.right() > 0);
-        self.walk.obstacles.iter_mut().for_each(|obstacle| {
-            obstacle.move_horizontally(velocity);
-            obstacle.check_intersection(&mut self.walk.boy)
-        });
+    fn draw_progress(&self, renderer: &Renderer) {
+        let progress = self.walk.segment_progress();
+        let width = self.walk.screen.width;
+        renderer.fill_rect(
+            &Rect::new_from_x_y(0, 0, width, PROGRESS_BAR_HEIGHT),
+            PROGRESS_BAR_BACKGROUND_COLOR,
+        );
+        renderer.fill_rect(
+            &Rect::new_from_x_y(0, 0, (width as f32 * progress) as i16, PROGRESS_BAR_HEIGHT),
+            PROGRESS_BAR_COLOR,
+        );
+    }
 
-        // Generate new obstacles
-        if self.walk.timeline < TIMELINE_MINIMUM {
-            self.walk.generate_next_segment();
-        } else {
-            self.walk.timeline += velocity;
+    fn draw_go(&self, renderer: &Renderer) {
+        if self._state.go_frames > 0 {
+            renderer.draw_text_centered(
+                COUNTDOWN_GO,
+                &self.walk.screen.game_rect().center(),
+                COUNTDOWN_FONT_SIZE,
+            );
         }
+    }
 
-        if self.walk.knocked_out() {
-            WalkingEndState::Complete(self.end_game())
-        } else {
-            WalkingEndState::Continue(self)
+    /// Abandons the run for a new one. The music plays on, and there is no
+    /// UI to hide while walking.
+    fn quit(self) -> WalkTheDogState<Ready> {
+        delete_saved_walk();
+        if self.walk.control_scheme() == ControlScheme::Mouse {
+            if let Err(err) = browser::exit_pointer_lock() {
+                log!("Could not unlock the mouse {:#?}", err);
+            }
+        }
+        WalkTheDogState {
+            _state: Ready { saved_walk: None },
+            walk: Walk::reset(self.walk),
         }
     }
 
     fn end_game(self) -> WalkTheDogState<GameOver> {
-        let receiver = browser::draw_ui("<button id='new_game'>New Game</button>")
-            .and_then(|_unit| browser::find_html_element_by_id("new_game"))
-            .map(|element| engine::add_click_handler(element))
-            .expect("could not build receiver!");
+        delete_saved_walk();
+        self.walk.fade_out_music();
+        // The game over buttons need the mouse back
+        if self.walk.control_scheme() == ControlScheme::Mouse {
+            if let Err(err) = browser::exit_pointer_lock() {
+                log!("Could not unlock the mouse {:#?}", err);
+            }
+        }
+        let can_continue = self.walk.can_continue();
+        let has_leaderboard = self.walk.config.leaderboard_url.is_some();
+        let buttons = draw_game_over_ui(can_continue, has_leaderboard)
+            .expect("could not build the game over receivers!");
+        if let Err(err) = buttons.new_game_button.set_attribute("disabled", "") {
+            log!("Could not disable the new game button {:#?}", err);
+        }
 
+        let stats = RunStats::from_walk(&self.walk);
+        let this_run = BestRun::from_stats(&stats);
+        let previous_best = self._state.previous_best;
+        save_best_run(&previous_best.map_or(this_run, |best| best.max(this_run)));
         WalkTheDogState {
             _state: GameOver {
-                new_game_event: receiver,
+                new_game_button: buttons.new_game_button,
+                new_game_event: buttons.new_game_event,
+                continue_event: buttons.continue_event,
+                submit_score_event: buttons.submit_score_event,
+                summary: stats.lines(),
+                stats,
+                tally_frame: 0,
+                previous_best,
             },
             walk: self.walk,
         }
     }
 }
 
-enum GameOverEndState {
-    Complete(WalkTheDogState<Ready>),
-    Continue(WalkTheDogState<GameOver>),
+/// A tile stretched over `panel` without stretching its corners, darkened
+/// since text is hard to read on the bare tile.
+fn draw_panel(renderer: &Renderer, sheet: &SpriteSheet, panel: &Rect) {
+    renderer.draw_nine_slice(sheet, PANEL_CELL, panel, PANEL_CORNER);
+    renderer.draw_alpha_rect(panel, PANEL_COLOR, PANEL_ALPHA);
 }
 
 impl WalkTheDogState<GameOver> {
-    fn update(mut self) -> GameOverEndState {
-        if self._state.new_game_pressed() {
+    /// The stats of the run, each next to an icon, with the grade below them
+    /// once they are done counting up.
+    fn draw_summary(&self, renderer: &Renderer) {
+        let grade_height = SUMMARY_ROW_HEIGHT * 2;
+        let panel = Rect::new_from_x_y(
+            SUMMARY_MARGIN,
+            SUMMARY_MARGIN,
+            SUMMARY_WIDTH,
+            SUMMARY_ROW_HEIGHT * SUMMARY_LINES as i16 + grade_height + SUMMARY_PADDING * 2,
+        );
+        draw_panel(renderer, &self.walk.obstacle_sheet, &panel);
+        let left = panel.x() + SUMMARY_PADDING;
+        self._state
+            .summary
+            .iter()
+            .enumerate()
+            .for_each(|(row, line)| {
+                let top = panel.y() + SUMMARY_PADDING + row as i16 * SUMMARY_ROW_HEIGHT;
+                let icon = Rect::new_from_x_y(
+                    left,
+                    top + (SUMMARY_ROW_HEIGHT - SUMMARY_ICON_SIZE) / 2,
+                    SUMMARY_ICON_SIZE,
+                    SUMMARY_ICON_SIZE,
+                );
+                self.draw_icon(renderer, line.icon, &icon);
+                renderer.draw_text_centered(
+                    &line.display_value.to_string(),
+                    &Point {
+                        x: (left + SUMMARY_ICON_SIZE + panel.right()) / 2,
+                        y: top + SUMMARY_ROW_HEIGHT / 2,
+                    },
+                    SUMMARY_FONT_SIZE,
+                );
+            });
+        if self._state.tallied() {
+            renderer.draw_text_centered(
+                self._state.stats.grade(),
+                &Point {
+                    x: panel.center().x,
+                    y: panel.bottom() - SUMMARY_PADDING - grade_height / 2,
+                },
+                SUMMARY_GRADE_FONT_SIZE,
+            );
+        }
+    }
+
+    /// This run next to the best one before it, once the stats are
+    /// tallied. Where this run did better the value is highlighted.
+    fn draw_comparison(&self, renderer: &Renderer) {
+        if !self._state.tallied() {
+            return;
+        }
+        let panel = Rect::new_from_x_y(
+            SUMMARY_MARGIN * 2 + SUMMARY_WIDTH,
+            SUMMARY_MARGIN,
+            COMPARISON_WIDTH,
+            SUMMARY_ROW_HEIGHT * (COMPARISON_ROWS as i16 + 1) + SUMMARY_PADDING * 2,
+        );
+        draw_panel(renderer, &self.walk.obstacle_sheet, &panel);
+        let best = match self._state.previous_best {
+            Some(best) => best,
+            None => {
+                renderer.draw_text_centered(FIRST_RUN_TEXT, &panel.center(), SUMMARY_FONT_SIZE);
+                return;
+            }
+        };
+        let left = panel.x() + SUMMARY_PADDING;
+        let columns_width = panel.right() - left - SUMMARY_ICON_SIZE;
+        let cell = |column: i16, row: i16| Point {
+            x: left + SUMMARY_ICON_SIZE + columns_width * (column * 2 + 1) / 4,
+            y: panel.y() + SUMMARY_PADDING + row * SUMMARY_ROW_HEIGHT + SUMMARY_ROW_HEIGHT / 2,
+        };
+        renderer.draw_text_centered("This run", &cell(0, 0), SUMMARY_FONT_SIZE);
+        renderer.draw_text_centered("Best run", &cell(1, 0), SUMMARY_FONT_SIZE);
+        let this_run = BestRun::from_stats(&self._state.stats);
+        this_run
+            .compare(&best)
+            .iter()
+            .zip(1..)
+            .for_each(|((icon, value, best_value), row)| {
+                let icon_center = cell(0, row).y;
+                let destination = Rect::new_from_x_y(
+                    left,
+                    icon_center - SUMMARY_ICON_SIZE / 2,
+                    SUMMARY_ICON_SIZE,
+                    SUMMARY_ICON_SIZE,
+                );
+                self.draw_icon(renderer, *icon, &destination);
+                let color = if value > best_value {
+                    COMPARISON_BETTER_COLOR
+                } else {
+                    COMPARISON_COLOR
+                };
+                renderer.draw_text_centered_with_color(
+                    &value.to_string(),
+                    &cell(0, row),
+                    SUMMARY_FONT_SIZE,
+                    color,
+                );
+                renderer.draw_text_centered(
+                    &best_value.to_string(),
+                    &cell(1, row),
+                    SUMMARY_FONT_SIZE,
+                );
+            });
+    }
+
+    fn draw_icon(&self, renderer: &Renderer, icon: StatIcon, destination: &Rect) {
+        match icon {
+            StatIcon::Boy(name) => {
+                if let Some(cell) = self.walk.boy.sprite_sheet.frames.get(name) {
+                    renderer.draw_image(&self.walk.boy.image, &cell.frame_rect(), destination);
+                }
+            }
+            StatIcon::Tile(name) => {
+                if let Some(cell) = self.walk.obstacle_sheet.cell(name) {
+                    self.walk
+                        .obstacle_sheet
+                        .draw(renderer, &cell.frame_rect(), destination);
+                }
+            }
+            StatIcon::Coin(kind) => {
+                renderer.fill_circle(&destination.center(), destination.width / 2, kind.color())
+            }
+            // A stack, the kind doesn't matter
+            StatIcon::Coins => {
+                let radius = destination.width / 4;
+                (0..3).for_each(|index| {
+                    let center = Point {
+                        x: destination.center().x,
+                        y: destination.bottom() - radius - index * radius / 2,
+                    };
+                    renderer.fill_circle(&center, radius, CoinKind::Bronze.color());
+                });
+            }
+            // A small pile, one coin of every kind
+            StatIcon::CoinScore => {
+                let radius = destination.width / 4;
+                CoinKind::ALL.iter().enumerate().for_each(|(index, kind)| {
+                    let center = Point {
+                        x: destination.x() + radius + index as i16 * radius,
+                        y: destination.bottom() - radius - (index as i16 % 2) * radius,
+                    };
+                    renderer.fill_circle(&center, radius, kind.color());
+                });
+            }
+        }
+    }
+}
+
+enum GameOverEndState {
+    Complete(WalkTheDogState<Ready>),
+    Revived(WalkTheDogState<Walking>),
+    Continue(WalkTheDogState<GameOver>),
+}
+
+impl WalkTheDogState<GameOver> {
+    fn update(mut self, keystate: &KeyState) -> GameOverEndState {
+        self._state.count_up();
+        if self._state.submit_score_pressed() {
+            self.submit_score();
+        }
+        // Like the button, the key waits for the stats to be tallied
+        let restart_pressed = self._state.tallied()
+            && keystate.is_just_pressed(&self.walk.config.key_bindings.restart);
+        if self._state.new_game_pressed() || restart_pressed {
             GameOverEndState::Complete(self.new_game())
+        } else if self._state.continue_pressed() {
+            if self.walk.can_continue() {
+                GameOverEndState::Revived(self.revive())
+            } else {
+                GameOverEndState::Complete(self.new_game())
+            }
         } else {
             GameOverEndState::Continue(self)
         }
     }
 
+    /// Sends the score in the background. A failed submission is only
+    /// logged, the game goes on either way.
+    fn submit_score(&mut self) {
+        let url = match &self.walk.config.leaderboard_url {
+            Some(url) => url.clone(),
+            None => return,
+        };
+        let name = match browser::input_value(LEADERBOARD_NAME_ID) {
+            Ok(name) if !name.trim().is_empty() => name.trim().to_string(),
+            Ok(_empty) => {
+                log!("Enter a name to submit your score");
+                return;
+            }
+            Err(err) => {
+                log!("Could not read the name {:#?}", err);
+                return;
+            }
+        };
+        // Every score is only submitted once
+        self._state.submit_score_event = None;
+        let score = self.walk.score();
+        browser::spawn_local(async move {
+            if let Err(err) = engine::leaderboard::submit_score(&url, &name, score).await {
+                log!("Could not submit score {:#?}", err);
+            }
+        });
+    }
+
+    fn revive(self) -> WalkTheDogState<Walking> {
+        if let Err(err) = browser::hide_ui() {
+            log!("Could not hide the UI {:#?}", err);
+        }
+        self.walk.fade_in_music();
+        WalkTheDogState {
+            _state: Walking {
+                go_frames: 0,
+                knocked_out_frames: None,
+                previous_best: self._state.previous_best,
+            },
+            walk: Walk::revive(self.walk),
+        }
+    }
+
     fn new_game(self) -> WalkTheDogState<Ready> {
         browser::hide_ui();
+        self.walk.fade_in_music();
         WalkTheDogState {
-            _state: Ready,
+            _state: Ready { saved_walk: None },
             walk: Walk::reset(self.walk),
         }
     }
@@ -229,6 +1396,12 @@ impl From<WalkTheDogState<Ready>> for WalkTheDogStateMachine {
     }
 }
 
+impl From<WalkTheDogState<Countdown>> for WalkTheDogStateMachine {
+    fn from(state: WalkTheDogState<Countdown>) -> Self {
+        WalkTheDogStateMachine::Countdown(state)
+    }
+}
+
 impl From<WalkTheDogState<Walking>> for WalkTheDogStateMachine {
     fn from(state: WalkTheDogState<Walking>) -> Self {
         WalkTheDogStateMachine::Walking(state)
@@ -244,16 +1417,26 @@ impl From<WalkTheDogState<GameOver>> for WalkTheDogStateMachine {
 impl From<ReadyEndState> for WalkTheDogStateMachine {
     fn from(state: ReadyEndState) -> Self {
         match state {
-            ReadyEndState::Complete(walking) => walking.into(),
+            ReadyEndState::Complete(countdown) => countdown.into(),
             ReadyEndState::Continue(ready) => ready.into(),
         }
     }
 }
 
+impl From<CountdownEndState> for WalkTheDogStateMachine {
+    fn from(state: CountdownEndState) -> Self {
+        match state {
+            CountdownEndState::Complete(walking) => walking.into(),
+            CountdownEndState::Continue(countdown) => countdown.into(),
+        }
+    }
+}
+
 impl From<WalkingEndState> for WalkTheDogStateMachine {
     fn from(state: WalkingEndState) -> Self {
         match state {
             WalkingEndState::Complete(game_over_state) => game_over_state.into(),
+            WalkingEndState::Quit(ready_state) => ready_state.into(),
             WalkingEndState::Continue(walking_state) => walking_state.into(),
         }
     }
@@ -263,216 +1446,2443 @@ impl From<GameOverEndState> for WalkTheDogStateMachine {
     fn from(s: GameOverEndState) -> Self {
         match s {
             GameOverEndState::Complete(new_game_state) => new_game_state.into(),
+            GameOverEndState::Revived(walking_state) => walking_state.into(),
             GameOverEndState::Continue(game_over_state) => game_over_state.into(),
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PowerUp {
+    Ghost,
+    Magnet,
+    SlowMotion,
+    Rocket,
+    /// The boy runs upside down on the ceiling.
+    GravityFlip,
+}
+
+impl PowerUp {
+    const ALL: [PowerUp; 5] = [
+        PowerUp::Ghost,
+        PowerUp::Magnet,
+        PowerUp::SlowMotion,
+        PowerUp::Rocket,
+        PowerUp::GravityFlip,
+    ];
+
+    /// Tints the pickup, so each power-up can be told apart before it's
+    /// picked up.
+    fn color(self) -> &'static str {
+        match self {
+            PowerUp::Ghost => "#FFFFFF",
+            PowerUp::Magnet => "#FF0000",
+            PowerUp::SlowMotion => "#0000FF",
+            PowerUp::Rocket => "#FFA500",
+            PowerUp::GravityFlip => "#800080",
+        }
+    }
+
+    fn duration(&self) -> u16 {
+        match self {
+            PowerUp::Rocket => ROCKET_TICKS,
+            PowerUp::GravityFlip => GRAVITY_FLIP_TICKS,
+            _ => POWER_UP_TICKS,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ActivePowerUp {
+    power_up: PowerUp,
+    ticks_left: u16,
+}
+
+impl ActivePowerUp {
+    fn new(power_up: PowerUp) -> Self {
+        ActivePowerUp {
+            power_up,
+            ticks_left: power_up.duration(),
+        }
+    }
+
+    /// The power-up flickers in its last second to warn the player.
+    fn flickering_off(&self) -> bool {
+        self.ticks_left <= POWER_UP_FLICKER_TICKS
+            && (self.ticks_left / POWER_UP_FLICKER_PERIOD) % 2 == 0
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Difficulty {
+    pub hard_mode: bool,
+}
+
+impl Difficulty {
+    fn level_name(&self) -> &'static str {
+        if self.hard_mode {
+            "Hard"
+        } else {
+            "Normal"
+        }
+    }
+}
+
 pub struct Walk {
     boy: RedHatBoy,
     backgrounds: [Image; 2],
+    /// Drawn in place of the backgrounds, one after the other. Never empty.
+    themes: Vec<HtmlImageElement>,
+    /// How far the boy ran, in world pixels.
+    distance: u32,
+    /// The real time the boy has been walking, without the countdown, pauses
+    /// or the time on the game over screen.
+    elapsed_seconds: f64,
+    /// Segments easier than this are no longer generated. Rises with the
+    /// distance.
+    difficulty_floor: u8,
+    /// The widest gap between two segments. Shrinks as the difficulty floor
+    /// rises, but never below the `min_buffer` of a segment.
+    max_safe_gap: i16,
     obstacle_sheet: Rc<SpriteSheet>,
-    obstacles: Vec<Box<dyn Obstacle>>,
+    obstacles: Vec<ObstacleKind>,
+    obstacle_pool: ObstaclePool,
     stone: HtmlImageElement,
+    music: WebAudioSound,
     timeline: i16,
+    difficulty: Difficulty,
+    rng: StdRng,
+    coins: u32,
+    /// Points that aren't coins, like the ones for stomping the boss.
+    bonus_score: u32,
+    boss_encountered: bool,
+    continue_used: bool,
+    tally: RunTally,
+    /// How many `MILESTONE_DISTANCE`s the boy ran, each gets one banner.
+    milestones: u32,
+    /// Updates left until the last milestone banner faded out.
+    milestone_banner_frames: u8,
+    /// The points for the last long jump, shown above the boy while
+    /// `air_popup_frames` last.
+    air_bonus: u32,
+    air_popup_frames: u8,
+    /// How many `REST_EVERY_DISTANCE`s the boy ran, each gets one rest gap.
+    rests: u32,
+    /// The distance left in the current rest gap, nothing is generated
+    /// until it runs out.
+    rest_gap_countdown: Option<i16>,
+    safe_zone_banner_frames: u8,
+    rain: RainSystem,
+    /// The distance at which the obstacles start to count, after a new run
+    /// or a continue. Nothing can hit the boy before that.
+    grace_ends_at: Option<u32>,
+    /// Set while generation waits for obstacles to scroll off, see
+    /// `GameConfig::max_obstacles`.
+    obstacle_cap_reached: bool,
+    /// Lives left, counting the one the boy is on.
+    lives: u8,
+    /// What the current run is tuned with, `base_config` with its preset
+    /// applied.
+    config: GameConfig,
+    /// The config without any preset tunings, and the preset picked for the
+    /// next run.
+    base_config: GameConfig,
+    screen: Screen,
+    active_power_up: Option<ActivePowerUp>,
+    previous_boy_position: Point,
+    /// How fast the world scrolled by in the last update. It follows
+    /// `target_scroll_velocity`, but only slows down `DECELERATION` at a time.
+    current_scroll_velocity: i16,
 }
 
 impl Walk {
-    fn velocity(&self) -> i16 {
-        -self.boy.walking_speed()
-    }
-    fn generate_next_segment(&mut self) {
-        let mut rng = thread_rng();
-        let next_segment = rng.gen_range(0..2);
-        let mut next_obstacles = match next_segment {
-            0 => stone_and_platform(
-                self.stone.clone(),
-                self.obstacle_sheet.clone(),
-                self.timeline + OBSTACLE_BUFFER,
-            ),
-            1 => other_platform(self.obstacle_sheet.clone(), self.timeline + OBSTACLE_BUFFER),
-            _ => vec![],
+    /// A walk without a page to load from, the images are empty and the
+    /// audio plays nothing. `seed` decides the segments.
+    #[cfg(any(test, feature = "noop-audio"))]
+    fn headless(boy_sheet: Sheet, seed: u64) -> Self {
+        let image = HtmlImageElement::new().expect("Could not create an image");
+        let boy = RedHatBoy::new(
+            boy_sheet,
+            image.clone(),
+            Rc::new(engine::Audio::new_noop()),
+            WebAudioSound::new_noop(),
+            PhysicsConfig::default(),
+        );
+        let obstacle_sheet = Rc::new(SpriteSheet::new(
+            Sheet {
+                frames: HashMap::new(),
+            },
+            image.clone(),
+        ));
+        let mut obstacle_pool = ObstaclePool::default();
+        let obstacles =
+            stone_and_platform(image.clone(), obstacle_sheet.clone(), 0, &mut obstacle_pool);
+        let screen = Screen {
+            width: 600,
+            height: 600,
         };
-        self.timeline = rightmost(&next_obstacles);
-        self.obstacles.append(&mut next_obstacles);
+        Walk {
+            previous_boy_position: boy.position(),
+            current_scroll_velocity: 0,
+            boy,
+            backgrounds: [
+                Image::new(image.clone(), Point::default()),
+                Image::new(image.clone(), Point::default()),
+            ],
+            themes: vec![image.clone()],
+            distance: 0,
+            elapsed_seconds: 0.0,
+            difficulty_floor: 0,
+            max_safe_gap: max_safe_gap(&GameConfig::default(), 0),
+            obstacle_sheet,
+            timeline: rightmost(&obstacles),
+            obstacles,
+            obstacle_pool,
+            stone: image,
+            music: WebAudioSound::new_noop(),
+            difficulty: Difficulty::default(),
+            rng: StdRng::seed_from_u64(seed),
+            coins: 0,
+            bonus_score: 0,
+            boss_encountered: false,
+            continue_used: false,
+            tally: RunTally::default(),
+            milestones: 0,
+            milestone_banner_frames: 0,
+            air_bonus: 0,
+            air_popup_frames: 0,
+            rests: 0,
+            rest_gap_countdown: None,
+            safe_zone_banner_frames: 0,
+            rain: RainSystem::new(&screen),
+            grace_ends_at: None,
+            obstacle_cap_reached: false,
+            lives: 1,
+            config: GameConfig::default(),
+            base_config: GameConfig::default(),
+            screen,
+            active_power_up: None,
+        }
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        self.backgrounds
-            .iter()
-            .for_each(|background| background.draw(renderer));
-        self.boy.draw(renderer);
-        self.obstacles.iter().for_each(|obj| obj.draw(renderer));
-    }
+    fn update(&mut self, keystate: &KeyState) {
+        self.previous_boy_position = self.boy.position();
+        self.tally.updates = self.tally.updates.saturating_add(1);
+        let move_before = self.boy.state_machine.boy_move();
+        let key_bindings = &self.config.key_bindings;
+        if keystate.is_pressed(&key_bindings.slide) {
+            self.boy.slide();
+        }
+        if keystate.is_pressed(&key_bindings.run) {
+            self.boy.run_right();
+        } else if !self.config.hold_to_run {
+            self.boy.keep_running();
+        }
+        // Holding jump or dash doesn't do it again, the key has to be pressed anew
+        if keystate.is_just_pressed(&key_bindings.jump) {
+            self.boy.jump();
+        }
+        if keystate.is_just_pressed(&key_bindings.dash) {
+            self.boy.dash();
+        }
+        if self.config.debug_mode {
+            if let Some((_key, power_up)) = POWER_UP_CHEAT_KEYS
+                .iter()
+                .find(|(key, _power_up)| keystate.is_just_pressed(key))
+            {
+                self.activate_power_up(*power_up);
+            }
+        }
+        if self.rocket_active() {
+            if keystate.is_pressed(&self.config.key_bindings.slide) {
+                self.active_power_up = None;
+            } else if !self.boy.is_jumping() {
+                // Flying uses the jumping animation, and only a running boy can jump
+                self.boy.jump();
+            }
+        }
+        self.tally
+            .count_move(move_before, self.boy.state_machine.boy_move());
+        // The slide jump takes timing, it counts towards the combo like a coin
+        if self.boy.take_slide_jump() {
+            self.tally.extend_combo();
+        }
+        self.boy
+            .set_slow_motion(self.power_up_active(PowerUp::SlowMotion));
+        self.boy.set_rocket(self.rocket_active());
+        self.boy
+            .set_gravity_flipped(self.power_up_active(PowerUp::GravityFlip));
+        self.boy.set_footing(self.footing_under_boy());
+        self.boy.update();
+        if self.boy.pos_y() > GROUND {
+            // Down a pit, not even a dash gets him out of that
+            self.boy.fall_off();
+        }
+        self.tick_power_up();
+
+        self.approach_target_scroll_velocity();
+        let velocity = self.velocity();
+        self.distance = self.distance.saturating_add(velocity.unsigned_abs().into());
+        self.raise_difficulty_floor();
+        self.pass_milestones();
+        self.end_grace_period();
+        // Rain is all motion, so it stays away with reduced motion
+        self.rain.active = self.elapsed_seconds > RAIN_START_SECONDS && !self.config.reduced_motion;
+        self.rain.update(&self.screen);
+        let [first_background, second_background] = &mut self.backgrounds;
+        first_background.move_horizontally(velocity);
+        second_background.move_horizontally(velocity);
+        if first_background.right() < 0 {
+            first_background.set_x(second_background.right());
+        }
+        if second_background.right() < 0 {
+            second_background.set_x(first_background.right());
+        }
 
-    fn knocked_out(&self) -> bool {
-        self.boy.knocked_out()
+        self.remove_off_screen_obstacles();
+        let animate = !self.config.reduced_motion && !self.skips_slow_motion_tick();
+        let boy_box = self.boy.bounding_box();
+        self.obstacles.iter_mut().for_each(|obstacle| {
+            if animate {
+                obstacle.update();
+            }
+            obstacle.move_horizontally(velocity);
+            obstacle.track_boy(&boy_box);
+        });
+        // Wind only pushes while the boy is inside it, the zones blow again below
+        self.boy.headwind = 0;
+        if self.collision_enabled() {
+            // Not obstacles_in_view, the boy has to be borrowed mutably alongside
+            let view_width = self.view_width();
+            let difficulty = self.difficulty;
+            self.obstacles
+                .iter()
+                .filter(|obstacle| in_view(*obstacle, view_width))
+                .for_each(|obstacle| obstacle.check_intersection(&mut self.boy, &difficulty));
+        }
+        if self.power_up_active(PowerUp::Magnet) {
+            self.attract_coins();
+        }
+        self.collect_coins();
+        self.collect_power_ups();
+        self.bonus_score += self
+            .obstacles
+            .iter_mut()
+            .map(|obstacle| obstacle.take_score())
+            .sum::<u32>();
+        self.score_air_time();
+
+        // Generate new obstacles
+        self.tick_rest_gap(velocity);
+        if self.timeline < self.timeline_minimum() && self.rest_gap_countdown.is_none() {
+            self.generate_next_segment();
+        } else {
+            self.timeline += velocity;
+        }
     }
 
-    fn reset(walk: Self) -> Self {
-        let start_obstacles =
-            stone_and_platform(walk.stone.clone(), walk.obstacle_sheet.clone(), 0);
-        let timeline = rightmost(&start_obstacles);
+    /// What is right under the boy, flat ground unless a segment says
+    /// otherwise.
+    fn footing_under_boy(&self) -> Footing {
+        let x = self.boy.bounding_box().center().x;
+        self.obstacles
+            .iter()
+            .find_map(|obstacle| obstacle.footing_at(x))
+            .unwrap_or(Footing::Ground(GROUND))
+    }
 
-        Walk {
-            boy: RedHatBoy::reset(walk.boy),
-            backgrounds: walk.backgrounds,
-            obstacles: start_obstacles,
-            obstacle_sheet: walk.obstacle_sheet,
-            stone: walk.stone,
-            timeline,
+    fn remove_off_screen_obstacles(&mut self) {
+        let missed_coin = self
+            .obstacles_of_type::<Coin>()
+            .any(|coin| coin.bounding_box().right() <= 0);
+        if missed_coin {
+            self.tally.combo = 0;
         }
+        self.obstacle_pool.reclaim_off_screen(&mut self.obstacles);
     }
-}
 
-impl WalkTheDog {
-    pub fn new() -> Self {
-        WalkTheDog { machine: None }
+    /// How much of the world fits on the screen at the current render scale.
+    fn view_width(&self) -> i16 {
+        (f64::from(self.screen.width) / self.config.render_scale).ceil() as i16
     }
-}
 
-pub struct Barrier {
-    image: Image,
-}
+    /// New segments are generated while the timeline is shorter than this.
+    /// It reaches `TIMELINE_LEAD` past the view, however wide the canvas, or
+    /// `GameConfig::timeline_minimum` if that is further.
+    fn timeline_minimum(&self) -> i16 {
+        self.config
+            .timeline_minimum
+            .max(self.view_width().saturating_add(TIMELINE_LEAD))
+    }
 
-impl Barrier {
-    pub fn new(image: Image) -> Self {
-        Barrier { image }
+    /// The obstacles that are at least partly on screen. The ones waiting to
+    /// scroll in can neither be seen nor touched.
+    fn obstacles_in_view(&self) -> impl Iterator<Item = &ObstacleKind> {
+        let view_width = self.view_width();
+        self.obstacles
+            .iter()
+            .filter(move |obstacle| in_view(*obstacle, view_width))
     }
-}
 
-impl Obstacle for Barrier {
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
-        if boy.bounding_box().intersects(self.image.bounding_box()) {
-            boy.knock_out()
-        }
+    /// The obstacles on `layer` a redraw touches, in the order they are
+    /// drawn.
+    fn drawables_in_view<'a>(
+        &'a self,
+        redraw: &'a Redraw,
+        layer: Layer,
+    ) -> impl Iterator<Item = &'a dyn Drawable> {
+        self.obstacles_in_view()
+            .filter(move |obj| obj.layer() == layer && redraw.needs(&obj.draw_area()))
+            .map(|obj| obj as &dyn Drawable)
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        self.image.draw(renderer);
+    fn activate_power_up(&mut self, power_up: PowerUp) {
+        self.active_power_up = Some(ActivePowerUp::new(power_up));
     }
 
-    fn move_horizontally(&mut self, x: i16) {
-        self.image.move_horizontally(x)
+    fn tick_power_up(&mut self) {
+        self.active_power_up = self
+            .active_power_up
+            .map(|active| ActivePowerUp {
+                ticks_left: active.ticks_left.saturating_sub(1),
+                ..active
+            })
+            .filter(|active| active.ticks_left > 0);
     }
 
-    fn right(&self) -> i16 {
-        self.image.right()
+    fn power_up_active(&self, power_up: PowerUp) -> bool {
+        self.active_power_up
+            .map_or(false, |active| active.power_up == power_up)
     }
-}
 
-pub struct RedHatBoy {
-    state_machine: RedHatBoyStateMachine,
-    sprite_sheet: Sheet,
-    image: HtmlImageElement,
-}
+    fn rocket_active(&self) -> bool {
+        self.power_up_active(PowerUp::Rocket)
+    }
 
-impl RedHatBoy {
-    fn new(sheet: Sheet, image: HtmlImageElement, audio: Audio, sound: Sound) -> Self {
-        RedHatBoy {
-            state_machine: RedHatBoyStateMachine::Idle(RedHatBoyState::new(audio, sound)),
-            sprite_sheet: sheet,
-            image,
+    /// Tuned physics outlast the run, the boy of the next one uses them too.
+    /// The next preset is applied over them.
+    fn set_physics(&mut self, physics: PhysicsConfig) {
+        // Only the values that changed go into the base, the rest of it has
+        // to stay free of what the preset tuned
+        let tuned = self.config.physics;
+        let base = &mut self.base_config.physics;
+        for (value, tuned, field) in [
+            (
+                physics.running_speed,
+                tuned.running_speed,
+                &mut base.running_speed,
+            ),
+            (physics.jump_speed, tuned.jump_speed, &mut base.jump_speed),
+            (
+                physics.max_velocity,
+                tuned.max_velocity,
+                &mut base.max_velocity,
+            ),
+            (physics.gravity, tuned.gravity, &mut base.gravity),
+            (
+                physics.slide_box_crop,
+                tuned.slide_box_crop,
+                &mut base.slide_box_crop,
+            ),
+        ] {
+            if value != tuned {
+                *field = value;
+            }
         }
+        self.config.physics = physics;
+        self.boy.set_physics(physics);
     }
 
-    fn walking_speed(&self) -> i16 {
-        self.state_machine.context().velocity.x
+    fn select_preset(&mut self, preset: Preset) {
+        self.base_config.preset = preset;
     }
 
-    fn frame_name(&self) -> String {
-        format!(
-            "{} ({}).png",
-            self.state_machine.frame_name(),
-            (self.state_machine.context().frame / 3) + 1
-        )
+    /// A walk tuned for the preset picked, if it isn't the one the walk is
+    /// tuned for yet. Only called before a run starts, a run keeps the
+    /// preset it started with. What the player changed on the ready screen
+    /// is kept.
+    fn apply_preset(mut walk: Self) -> Self {
+        let preset = walk.base_config.preset;
+        if preset == walk.config.preset {
+            return walk;
+        }
+        let mut config = walk.base_config.clone();
+        Settings::new(&walk.config, walk.difficulty.hard_mode).apply_to(&mut config);
+        walk.config = preset.apply(&config);
+        Walk::reset(walk)
     }
 
-    fn current_sprite(&self) -> Option<&Cell> {
-        self.sprite_sheet.frames.get(&self.frame_name())
+    /// A ghost passes through everything.
+    fn collision_enabled(&self) -> bool {
+        !self.power_up_active(PowerUp::Ghost) && self.grace_ends_at.is_none()
     }
 
-    fn destination_box(&self) -> Rect {
-        let sprite = self.current_sprite().expect("Cell not found");
-        Rect::new_from_x_y(
-            self.state_machine.context().position.x + sprite.sprite_source_size.x,
-            self.state_machine.context().position.y + sprite.sprite_source_size.y,
-            sprite.frame.w.into(),
-            sprite.frame.h.into(),
-        )
+    fn end_grace_period(&mut self) {
+        if self
+            .grace_ends_at
+            .map_or(false, |grace_ends_at| self.distance >= grace_ends_at)
+        {
+            self.grace_ends_at = None;
+        }
     }
 
-    fn bounding_box(&self) -> Rect {
-        const X_OFFSET: i16 = 18;
-        const Y_OFFSET: i16 = 14;
-        const WIDTH_OFFSET: i16 = 28;
-        let bounding_box = self.destination_box();
-        Rect::new_from_x_y(
-            bounding_box.x() + X_OFFSET,
-            bounding_box.y() + Y_OFFSET,
-            bounding_box.width - WIDTH_OFFSET,
-            bounding_box.height - Y_OFFSET,
-        )
+    /// The obstacles of one kind, without looking at the others.
+    fn obstacles_of_type<T: ObstacleType>(&self) -> impl Iterator<Item = &T> {
+        self.obstacles.iter().filter_map(T::of_kind)
     }
 
-    fn velocity_y(&self) -> i16 {
-        self.state_machine.context().velocity.y
+    fn obstacles_of_type_mut<T: ObstacleType>(&mut self) -> impl Iterator<Item = &mut T> {
+        self.obstacles.iter_mut().filter_map(T::of_kind_mut)
     }
 
-    fn pos_y(&self) -> i16 {
-        self.state_machine.context().position.y
+    /// Pulls every coin within `MAGNET_RADIUS` of the boy a step closer.
+    fn attract_coins(&mut self) {
+        let boy_center = self.boy.bounding_box().center();
+        self.obstacles_of_type_mut::<Coin>().for_each(|coin| {
+            let coin_center = coin.center();
+            let distance = coin_center.distance_to(&boy_center);
+            if distance > 0.0 && distance < f32::from(MAGNET_RADIUS) {
+                let step = MAGNET_SPEED.min(distance) / distance;
+                coin.move_by((boy_center - coin_center).scale(step));
+            }
+        });
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        let sprite = self.current_sprite().expect("Cell not found");
-        renderer.draw_image(
-            &self.image,
-            &Rect::new_from_x_y(
-                sprite.frame.x,
-                sprite.frame.y,
-                sprite.frame.w.into(),
-                sprite.frame.h.into(),
-            ),
-            &self.destination_box(),
-        );
-        renderer.draw_rect(&self.bounding_box())
+    fn collect_coins(&mut self) {
+        let boy_box = self.boy.bounding_box();
+        let mut index = 0;
+        while index < self.obstacles.len() {
+            let touched = Coin::of_kind(&self.obstacles[index])
+                .filter(|coin| coin.bounding_box().intersects(&boy_box))
+                .map(|coin| coin.kind());
+            if let Some(kind) = touched {
+                let coin = self.obstacles.remove(index);
+                self.obstacle_pool.reclaim(coin);
+                self.coins += kind.value() * self.config.coin_value;
+                self.tally.collect_coin(kind);
+            } else {
+                index += 1;
+            }
+        }
     }
 
-    fn update(&mut self) {
-        self.state_machine = self.state_machine.clone().update();
+    /// Touching a pickup sets its power-up off, in place of the one active.
+    fn collect_power_ups(&mut self) {
+        let boy_box = self.boy.bounding_box();
+        let touched = self.obstacles.iter().position(|obstacle| {
+            PowerUpPickup::of_kind(obstacle)
+                .map_or(false, |pickup| pickup.bounding_box().intersects(&boy_box))
+        });
+        if let Some(index) = touched {
+            if let ObstacleKind::PowerUpPickup(pickup) = self.obstacles.remove(index) {
+                self.activate_power_up(pickup.power_up);
+            }
+        }
     }
 
-    fn run_right(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Run);
+    /// In slow motion the obstacle animations only advance every
+    /// `SLOW_MOTION_FACTOR` ticks, just like the boy's.
+    fn skips_slow_motion_tick(&self) -> bool {
+        self.active_power_up.map_or(false, |active| {
+            active.power_up == PowerUp::SlowMotion
+                && active.ticks_left % SLOW_MOTION_FACTOR as u16 != 0
+        })
     }
-    fn knock_out(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::KnockOut);
-    }
-    fn slide(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Slide);
+
+    fn boy_alpha(&self) -> f64 {
+        match self.active_power_up {
+            Some(active) if active.power_up == PowerUp::Ghost && !active.flickering_off() => {
+                GHOST_ALPHA
+            }
+            _ => 1.0,
+        }
     }
 
-    fn jump(&mut self) {
-        self.state_machine = self.state_machine.clone().transition(Event::Jump);
+    fn velocity(&self) -> i16 {
+        self.current_scroll_velocity
     }
 
-    fn land_on(&mut self, y: i16) {
-        self.state_machine = self.state_machine.clone().transition(Event::Land(y));
+    /// Speeding up happens at once, the boy pushes off. Slowing down takes a
+    /// few updates, so stopping doesn't feel like hitting a wall.
+    fn approach_target_scroll_velocity(&mut self) {
+        let target = self.target_scroll_velocity();
+        self.current_scroll_velocity = if target <= self.current_scroll_velocity {
+            target
+        } else {
+            (self.current_scroll_velocity + DECELERATION).min(target)
+        };
     }
 
-    fn knocked_out(&self) -> bool {
-        self.state_machine.knocked_out()
+    /// How fast the world should scroll by for the way the boy moves now.
+    fn target_scroll_velocity(&self) -> i16 {
+        let mut walking_speed = self.boy.walking_speed();
+        if self.config.reduced_motion {
+            walking_speed = walking_speed * REDUCED_MOTION_SPEED_PERCENT / 100;
+        }
+        if self.difficulty.hard_mode && walking_speed > 0 {
+            walking_speed += HARD_MODE_SPEED_BONUS;
+        }
+        if self.elapsed_seconds >= SPEED_UP_SECONDS && walking_speed > 0 {
+            walking_speed += SPEED_UP_BONUS;
+        }
+        if self.boy.dashing() {
+            walking_speed *= DASH_SPEED_MULTIPLIER;
+        }
+        if walking_speed > 0 {
+            walking_speed = (walking_speed - self.boy.headwind).max(0);
+        }
+        if self.power_up_active(PowerUp::SlowMotion) {
+            walking_speed /= SLOW_MOTION_FACTOR;
+        }
+        -walking_speed
     }
 
-    fn reset(boy: Self) -> Self {
-        RedHatBoy::new(
-            boy.sprite_sheet,
-            boy.image,
-            boy.state_machine.context().audio.clone(),
-            boy.state_machine.context().jump_sound.clone(),
-        )
+    /// Every `DIFFICULTY_FLOOR_DISTANCE` the easiest segments drop out, and
+    /// the gaps between segments get shorter.
+    fn raise_difficulty_floor(&mut self) {
+        let floor = (self.distance / DIFFICULTY_FLOOR_DISTANCE).min(u8::MAX.into()) as u8;
+        if floor > self.difficulty_floor {
+            self.difficulty_floor = floor;
+            self.max_safe_gap = max_safe_gap(&self.config, floor);
+        }
     }
-}
+
+    /// Puts up a banner for every `MILESTONE_DISTANCE` the boy runs.
+    fn pass_milestones(&mut self) {
+        self.milestone_banner_frames = self.milestone_banner_frames.saturating_sub(1);
+        let milestones = self.distance / MILESTONE_DISTANCE;
+        if milestones > self.milestones {
+            self.milestones = milestones;
+            self.milestone_banner_frames = MILESTONE_BANNER_FRAMES;
+        }
+    }
+
+    /// Long jumps, say off a platform down to the ground, earn a point every
+    /// `AIR_FRAMES_PER_POINT` past the threshold.
+    fn score_air_time(&mut self) {
+        self.air_popup_frames = self.air_popup_frames.saturating_sub(1);
+        let bonus = self.boy.take_landed_air_frames().map_or(0, air_time_bonus);
+        if bonus > 0 {
+            self.bonus_score += bonus;
+            self.air_bonus = bonus;
+            self.air_popup_frames = AIR_POPUP_FRAMES;
+        }
+    }
+
+    fn generate_next_segment(&mut self) {
+        let weights = segment_weights(&self.config.segments, self.difficulty_floor);
+        let next_segment = if !self.boss_encountered && self.distance >= BOSS_DISTANCE {
+            self.boss_encountered = true;
+            Ok(BOSS_SEGMENT)
+        } else if self.distance / REST_EVERY_DISTANCE > self.rests {
+            Ok(REST_GAP_SEGMENT)
+        } else {
+            WeightedIndex::new(weights).map(|distribution| SEGMENTS[self.rng.sample(distribution)])
+        };
+        let entry = next_segment
+            .ok()
+            .and_then(|segment| self.config.segments.get(segment).copied())
+            .unwrap_or_default();
+        let offset_x = self.timeline + self.next_buffer(&entry);
+        let mut next_obstacles = match next_segment {
+            Ok(STONE_AND_PLATFORM_SEGMENT) => stone_and_platform(
+                self.stone.clone(),
+                self.obstacle_sheet.clone(),
+                offset_x,
+                &mut self.obstacle_pool,
+            ),
+            Ok(OTHER_PLATFORM_SEGMENT) => other_platform(
+                self.obstacle_sheet.clone(),
+                offset_x,
+                &mut self.obstacle_pool,
+            ),
+            Ok(GLOWING_PLATFORM_SEGMENT) => glowing_platform(
+                self.obstacle_sheet.clone(),
+                offset_x,
+                &mut self.obstacle_pool,
+            ),
+            Ok(WINDY_PLATFORM_SEGMENT) => windy_platform(
+                self.obstacle_sheet.clone(),
+                offset_x,
+                &mut self.obstacle_pool,
+            ),
+            Ok(PIT_SEGMENT) => pit(offset_x),
+            Ok(GAP_AND_PLATFORM_SEGMENT) => gap_and_platform(
+                self.obstacle_sheet.clone(),
+                offset_x,
+                &mut self.obstacle_pool,
+            ),
+            Ok(LONG_PLATFORM_SEGMENT) => long_platform(
+                self.obstacle_sheet.clone(),
+                offset_x,
+                &mut self.obstacle_pool,
+            ),
+            Ok(HANGING_BARRIER_SEGMENT) => {
+                hanging_barrier_run(self.stone.clone(), offset_x, &mut self.obstacle_pool)
+            }
+            Ok(REST_GAP_SEGMENT) => {
+                self.start_rest_gap();
+                return;
+            }
+            Ok(BOSS_SEGMENT) => boss_segment(
+                self.obstacle_sheet.clone(),
+                self.stone.clone(),
+                offset_x,
+                &mut self.obstacle_pool,
+            ),
+            _ => vec![],
+        };
+        if !matches!(next_segment, Ok(BOSS_SEGMENT)) && self.rng.gen_ratio(1, POWER_UP_PICKUP_ODDS)
+        {
+            let power_up = PowerUp::ALL[self.rng.gen_range(0..PowerUp::ALL.len())];
+            // Halfway through the buffer, out of the segment's way
+            let center = Point {
+                x: self.timeline + (offset_x - self.timeline) / 2,
+                y: POWER_UP_PICKUP_Y,
+            };
+            next_obstacles
+                .push(Box::new(PowerUpPickup::new(self.stone.clone(), center, power_up)).into());
+        }
+        if self.obstacles.len() + next_obstacles.len() > self.config.max_obstacles {
+            if !self.obstacle_cap_reached {
+                log!(
+                    "{} obstacles are live, new segments wait until some are gone",
+                    self.obstacles.len()
+                );
+            }
+            self.obstacle_cap_reached = true;
+            // The boss is still to come if his segment was the one to wait
+            if matches!(next_segment, Ok(BOSS_SEGMENT)) {
+                self.boss_encountered = false;
+            }
+            self.obstacle_pool.reclaim_all(&mut next_obstacles);
+            return;
+        }
+        self.obstacle_cap_reached = false;
+        self.timeline = rightmost(&next_obstacles);
+        self.obstacles.append(&mut next_obstacles);
+    }
+
+    /// Leaves `GameConfig::rest_duration` empty after the last segment.
+    /// Generation waits until the boy ran that far.
+    fn start_rest_gap(&mut self) {
+        self.rests = self.distance / REST_EVERY_DISTANCE;
+        if self.timeline <= self.view_width() {
+            self.safe_zone_banner_frames = SAFE_ZONE_BANNER_FRAMES;
+        }
+        self.timeline = self.timeline.saturating_add(self.config.rest_duration);
+        self.rest_gap_countdown = Some(self.config.rest_duration);
+    }
+
+    /// Counts the rest gap down by how far the world scrolled, `velocity`
+    /// being negative. The banner says so once the gap scrolls into view.
+    fn tick_rest_gap(&mut self, velocity: i16) {
+        self.safe_zone_banner_frames = self.safe_zone_banner_frames.saturating_sub(1);
+        if self.rest_gap_countdown.is_some() {
+            let gap_start = self.timeline - self.config.rest_duration;
+            let view_width = self.view_width();
+            if gap_start > view_width && gap_start + velocity <= view_width {
+                self.safe_zone_banner_frames = SAFE_ZONE_BANNER_FRAMES;
+            }
+        }
+        self.rest_gap_countdown = self
+            .rest_gap_countdown
+            .map(|left| left + velocity)
+            .filter(|left| *left > 0);
+    }
+
+    /// A random gap in the range of `entry`, narrowed by the difficulty.
+    fn next_buffer(&mut self, entry: &SegmentEntry) -> i16 {
+        let max_buffer = entry
+            .max_buffer
+            .min(self.max_safe_gap)
+            .max(entry.min_buffer);
+        self.rng.gen_range(entry.min_buffer..=max_buffer)
+    }
+
+    /// How close the walk is to generating the next obstacle set, from 0.0 to 1.0.
+    fn segment_progress(&self) -> f32 {
+        let timeline_minimum = self.timeline_minimum();
+        let rightmost = rightmost(&self.obstacles).max(self.timeline);
+        if rightmost <= timeline_minimum {
+            1.0
+        } else {
+            (timeline_minimum as f32 / rightmost as f32).clamp(0.0, 1.0)
+        }
+    }
+
+    /// Draws the walk `interpolation` of the way from the previous update to
+    /// the latest one. Everything but the boy moved by the same velocity, so
+    /// the world is shifted back as a whole, the boy on his own.
+    /// Things outside of `redraw` are skipped, they are still on the canvas.
+    fn draw_interpolated(&self, renderer: &Renderer, interpolation: f32, redraw: &Redraw) {
+        let behind = f64::from(1.0 - interpolation.clamp(0.0, 1.0));
+        let world_offset = -f64::from(self.current_scroll_velocity) * behind;
+        let boy_position = self.boy.position();
+        let boy_offset = (
+            f64::from(self.previous_boy_position.x - boy_position.x) * behind,
+            f64::from(self.previous_boy_position.y - boy_position.y) * behind,
+        );
+
+        // Only the world is scaled, the HUD is drawn in canvas coordinates
+        renderer.set_scale(self.config.render_scale);
+        renderer.translate(world_offset, 0.0);
+        let blend = theme_blend(self.distance, self.config.theme_distance, self.themes.len());
+        self.backgrounds
+            .iter()
+            .filter(|background| redraw.needs(background.bounding_box()))
+            .for_each(|background| {
+                if blend.alpha < 1.0 {
+                    background.draw_in_place_of(renderer, &self.themes[blend.outgoing], 1.0);
+                }
+                background.draw_in_place_of(renderer, &self.themes[blend.incoming], blend.alpha);
+            });
+        [Layer::Back, Layer::Middle].iter().for_each(|layer| {
+            self.drawables_in_view(redraw, *layer)
+                .for_each(|drawable| drawable.draw(renderer))
+        });
+        if self.config.debug_mode {
+            self.obstacles_in_view()
+                .for_each(|obj| obj.debug_draw(renderer));
+        }
+        renderer.translate(boy_offset.0 - world_offset, boy_offset.1);
+        if self.rocket_active() {
+            self.draw_rocket_flame(renderer);
+        }
+        if redraw.needs(&self.boy.destination_box()) {
+            self.boy.draw_with_alpha(renderer, self.boy_alpha());
+        }
+        if self.config.debug_mode {
+            renderer.draw_rect(&self.boy.bounding_box());
+        }
+        // Back to where the world is, the boy passes behind the front layer
+        renderer.translate(world_offset - boy_offset.0, -boy_offset.1);
+        self.drawables_in_view(redraw, Layer::Front)
+            .for_each(|drawable| drawable.draw(renderer));
+        renderer.reset_scale();
+
+        self.rain.draw(renderer);
+        if self.power_up_active(PowerUp::SlowMotion) {
+            renderer.draw_alpha_rect(
+                &self.screen.game_rect(),
+                SLOW_MOTION_TINT_COLOR,
+                SLOW_MOTION_TINT_ALPHA,
+            );
+        }
+        if let Some(active) = self.active_power_up {
+            let remaining = f32::from(active.ticks_left) / f32::from(active.power_up.duration());
+            renderer.fill_rect(
+                &Rect::new_from_x_y(
+                    0,
+                    PROGRESS_BAR_HEIGHT,
+                    (f32::from(self.screen.width) * remaining) as i16,
+                    POWER_UP_BAR_HEIGHT,
+                ),
+                POWER_UP_BAR_COLOR,
+            );
+        }
+        if self.difficulty.hard_mode {
+            // Right aligned, however wide the font draws it
+            let width = renderer.measure_text(HARD_MODE_LABEL, &engine::game_font(HUD_FONT_SIZE));
+            renderer.draw_text_centered(
+                HARD_MODE_LABEL,
+                &Point {
+                    x: self.screen.width - HUD_MARGIN - (width / 2.0).round() as i16,
+                    y: HUD_MARGIN + (Renderer::text_height(HUD_FONT_SIZE.into()) / 2.0) as i16,
+                },
+                HUD_FONT_SIZE,
+            );
+        }
+        if self.milestone_banner_frames > 0 && !self.knocked_out() {
+            self.draw_banner(
+                renderer,
+                &format!("{}m!", self.milestones * MILESTONE_DISTANCE),
+                self.screen.height / 4,
+                f64::from(self.milestone_banner_frames) / f64::from(MILESTONE_BANNER_FRAMES),
+            );
+        }
+        if self.safe_zone_banner_frames > 0 && !self.knocked_out() {
+            self.draw_banner(
+                renderer,
+                SAFE_ZONE_LABEL,
+                self.screen.height / 3,
+                f64::from(self.safe_zone_banner_frames) / f64::from(SAFE_ZONE_BANNER_FRAMES),
+            );
+        }
+        if self.air_popup_frames > 0 && !self.knocked_out() {
+            let boy_box = self.boy.bounding_box();
+            let rise = AIR_POPUP_RISE
+                - AIR_POPUP_RISE * i16::from(self.air_popup_frames) / i16::from(AIR_POPUP_FRAMES);
+            renderer.draw_text_centered_with_alpha(
+                &format!("AIR +{}", self.air_bonus),
+                &Point {
+                    x: boy_box.center().x,
+                    y: boy_box.y() - rise,
+                },
+                AIR_POPUP_FONT_SIZE,
+                f64::from(self.air_popup_frames) / f64::from(AIR_POPUP_FRAMES),
+            );
+        }
+    }
+
+    /// `text` on a panel just wide enough for it, centered across the screen
+    /// at `y`. Panel and text fade out together.
+    fn draw_banner(&self, renderer: &Renderer, text: &str, y: i16, alpha: f64) {
+        let width = renderer.measure_text(text, &engine::game_font(MILESTONE_FONT_SIZE));
+        let height = Renderer::text_height(MILESTONE_FONT_SIZE.into());
+        let center = Point {
+            x: self.screen.width / 2,
+            y,
+        };
+        let panel_width = width.round() as i16 + PANEL_PADDING * 2;
+        let panel_height = height.round() as i16 + PANEL_PADDING * 2;
+        let panel = Rect::new_from_x_y(
+            center.x - panel_width / 2,
+            center.y - panel_height / 2,
+            panel_width,
+            panel_height,
+        );
+        // A `draw_panel` that fades
+        renderer.draw_nine_slice_with_alpha(
+            &self.obstacle_sheet,
+            PANEL_CELL,
+            &panel,
+            PANEL_CORNER,
+            alpha,
+        );
+        renderer.draw_alpha_rect(&panel, PANEL_COLOR, PANEL_ALPHA * alpha);
+        renderer.draw_text_centered_with_alpha(text, &center, MILESTONE_FONT_SIZE, alpha);
+    }
+
+    /// Puffs of fire below the boy's back, flickering from tick to tick.
+    fn draw_rocket_flame(&self, renderer: &Renderer) {
+        let boy_box = self.boy.bounding_box();
+        let flicker = self
+            .active_power_up
+            .map_or(0, |active| (active.ticks_left % 3) as i16);
+        (0..ROCKET_FLAME_PUFFS).for_each(|puff| {
+            renderer.fill_circle(
+                &Point {
+                    x: boy_box.x() - puff * 8,
+                    y: boy_box.bottom() - 10 + puff * 6 + flicker,
+                },
+                10 - puff * 2 - flicker,
+                ROCKET_FLAME_COLORS[puff as usize],
+            );
+        });
+    }
+
+    fn knocked_out(&self) -> bool {
+        self.boy.knocked_out()
+    }
+
+    /// Where `rect` in the world ends up on the canvas after the render
+    /// scale, grown to whole pixels.
+    fn world_to_screen(&self, rect: &Rect) -> Rect {
+        let scale = self.config.render_scale;
+        let top = f64::from(self.screen.height) * (1.0 - scale);
+        let left = (f64::from(rect.x()) * scale).floor();
+        let right = (f64::from(rect.right()) * scale).ceil();
+        let y = (top + f64::from(rect.y()) * scale).floor();
+        let bottom = (top + f64::from(rect.bottom()) * scale).ceil();
+        Rect::new_from_x_y(
+            left as i16,
+            y as i16,
+            (right - left) as i16,
+            (bottom - y) as i16,
+        )
+    }
+
+    /// A digest of the observable state of the walk. Two walks with the same
+    /// seed and the same input produce the same sequence of hashes.
+    #[cfg(test)]
+    fn frame_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let context = self.boy.state_machine.context();
+        let state_bytes = self.boy.state_machine.frame_name().bytes();
+        let value_bytes = [
+            context.position.x,
+            context.position.y,
+            context.velocity.x,
+            context.velocity.y,
+            context.frame.into(),
+            self.timeline,
+        ]
+        .into_iter()
+        .chain(self.obstacles.iter().map(|obstacle| obstacle.right()))
+        .flat_map(i16::to_le_bytes);
+
+        state_bytes
+            .chain(value_bytes)
+            .fold(FNV_OFFSET_BASIS, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    fn zoom(&mut self, keystate: &KeyState) {
+        let key_bindings = &self.config.key_bindings;
+        let mut render_scale = self.config.render_scale;
+        if keystate.is_pressed(&key_bindings.zoom_in) {
+            render_scale += RENDER_SCALE_STEP;
+        }
+        if keystate.is_pressed(&key_bindings.zoom_out) {
+            render_scale -= RENDER_SCALE_STEP;
+        }
+        self.config.render_scale = render_scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+    }
+
+    /// Starts the music over, it is faded out while the game is over.
+    fn fade_in_music(&self) {
+        let audio = self.boy.audio();
+        if let Err(err) = audio.play_looping_sound_fade_in(&self.music, MUSIC_FADE_IN_MS) {
+            log!("Could not play music {:#?}", err);
+        }
+    }
+
+    fn fade_out_music(&self) {
+        if let Err(err) = self.boy.audio().fade_out(MUSIC_FADE_OUT_MS) {
+            log!("Could not fade out music {:#?}", err);
+        }
+    }
+
+    fn control_scheme(&self) -> ControlScheme {
+        self.config
+            .control_scheme
+            .unwrap_or(ControlScheme::Keyboard)
+    }
+
+    fn score(&self) -> u32 {
+        self.coins + self.bonus_score
+    }
+
+    fn can_continue(&self) -> bool {
+        !self.continue_used && self.coins >= CONTINUE_COST
+    }
+
+    /// Brings the boy back to life where he fell, paying for it with coins.
+    fn revive(walk: Self) -> Self {
+        let coins = walk.coins - CONTINUE_COST;
+        Walk {
+            coins,
+            continue_used: true,
+            ..Walk::respawn(walk)
+        }
+    }
+
+    /// Brings the boy back to life where he fell, for one of his lives.
+    fn lose_life(walk: Self) -> Self {
+        let lives = walk.lives - 1;
+        Walk {
+            lives,
+            ..Walk::respawn(walk)
+        }
+    }
+
+    /// The obstacles are cleared, but the timeline keeps going.
+    fn respawn(mut walk: Self) -> Self {
+        let mut boy = RedHatBoy::reset(walk.boy);
+        boy.run_right();
+        walk.obstacle_pool.reclaim_all(&mut walk.obstacles);
+        let grace_distance = walk.config.grace_distance;
+        Walk {
+            previous_boy_position: boy.position(),
+            current_scroll_velocity: 0,
+            boy,
+            timeline: walk.timeline.max(grace_distance),
+            grace_ends_at: grace_ends_at(walk.distance, grace_distance),
+            active_power_up: None,
+            ..walk
+        }
+    }
+
+    /// Everything about the run that can't be loaded from the network.
+    fn snapshot(&self) -> WalkSnapshot {
+        WalkSnapshot {
+            score: self.coins,
+            bonus_score: self.bonus_score,
+            boss_encountered: self.boss_encountered,
+            distance: self.distance,
+            elapsed_seconds: self.elapsed_seconds,
+            difficulty_floor: self.difficulty_floor,
+            max_safe_gap: self.max_safe_gap,
+            timeline: self.timeline,
+            continue_used: self.continue_used,
+            lives: self.lives,
+            tally: self.tally,
+            obstacles: self
+                .obstacles
+                .iter()
+                .map(|obstacle| obstacle.descriptor())
+                .collect(),
+            boy: self.boy.descriptor(),
+        }
+    }
+
+    /// Picks a saved run up again, keeping the images, sounds and settings
+    /// of `walk`.
+    fn restore(mut walk: Self, snapshot: WalkSnapshot) -> Self {
+        walk.obstacle_pool.reclaim_all(&mut walk.obstacles);
+        let obstacles = snapshot
+            .obstacles
+            .into_iter()
+            .map(|descriptor| {
+                walk.obstacle_pool
+                    .restore(descriptor, &walk.stone, &walk.obstacle_sheet)
+            })
+            .collect();
+        let boy = RedHatBoy::restore(walk.boy, &snapshot.boy);
+        Walk {
+            previous_boy_position: boy.position(),
+            current_scroll_velocity: 0,
+            boy,
+            distance: snapshot.distance,
+            elapsed_seconds: snapshot.elapsed_seconds,
+            difficulty_floor: snapshot.difficulty_floor,
+            max_safe_gap: snapshot.max_safe_gap,
+            obstacles,
+            timeline: snapshot.timeline,
+            coins: snapshot.score,
+            bonus_score: snapshot.bonus_score,
+            boss_encountered: snapshot.boss_encountered,
+            continue_used: snapshot.continue_used,
+            lives: snapshot.lives.max(1),
+            tally: snapshot.tally,
+            milestones: snapshot.distance / MILESTONE_DISTANCE,
+            milestone_banner_frames: 0,
+            air_bonus: 0,
+            air_popup_frames: 0,
+            rests: snapshot.distance / REST_EVERY_DISTANCE,
+            rest_gap_countdown: None,
+            safe_zone_banner_frames: 0,
+            grace_ends_at: None,
+            obstacle_cap_reached: false,
+            active_power_up: None,
+            ..walk
+        }
+    }
+
+    fn reset(mut walk: Self) -> Self {
+        walk.obstacle_pool.reclaim_all(&mut walk.obstacles);
+        let start_obstacles = stone_and_platform(
+            walk.stone.clone(),
+            walk.obstacle_sheet.clone(),
+            walk.config.grace_distance,
+            &mut walk.obstacle_pool,
+        );
+        let timeline = rightmost(&start_obstacles);
+        let mut boy = RedHatBoy::reset(walk.boy);
+        // A preset applied since may have tuned the physics
+        boy.set_physics(walk.config.physics);
+
+        Walk {
+            previous_boy_position: boy.position(),
+            current_scroll_velocity: 0,
+            boy,
+            backgrounds: walk.backgrounds,
+            themes: walk.themes,
+            distance: 0,
+            elapsed_seconds: 0.0,
+            difficulty_floor: 0,
+            max_safe_gap: max_safe_gap(&walk.config, 0),
+            obstacles: start_obstacles,
+            obstacle_pool: walk.obstacle_pool,
+            obstacle_sheet: walk.obstacle_sheet,
+            stone: walk.stone,
+            music: walk.music,
+            timeline,
+            difficulty: walk.difficulty,
+            rng: walk.rng,
+            coins: 0,
+            bonus_score: 0,
+            boss_encountered: false,
+            continue_used: false,
+            tally: RunTally::default(),
+            milestones: 0,
+            milestone_banner_frames: 0,
+            air_bonus: 0,
+            air_popup_frames: 0,
+            rests: 0,
+            rest_gap_countdown: None,
+            safe_zone_banner_frames: 0,
+            rain: RainSystem::new(&walk.screen),
+            grace_ends_at: grace_ends_at(0, walk.config.grace_distance),
+            obstacle_cap_reached: false,
+            lives: walk.config.starting_lives,
+            config: walk.config,
+            base_config: walk.base_config,
+            screen: walk.screen,
+            active_power_up: None,
+        }
+    }
+}
+
+/// Only the logical state of the walk is serialized, see `WalkSnapshot`.
+impl Serialize for Walk {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+/// A walk without its assets, which are always loaded fresh from the
+/// network.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WalkSnapshot {
+    score: u32,
+    // Missing from games saved before there was a boss
+    #[serde(default)]
+    bonus_score: u32,
+    #[serde(default)]
+    boss_encountered: bool,
+    distance: u32,
+    #[serde(default)]
+    elapsed_seconds: f64,
+    difficulty_floor: u8,
+    max_safe_gap: i16,
+    timeline: i16,
+    continue_used: bool,
+    // Missing from games saved before there were lives
+    #[serde(default)]
+    lives: u8,
+    #[serde(default)]
+    tally: RunTally,
+    obstacles: Vec<ObstacleDescriptor>,
+    boy: BoyDescriptor,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ObstacleDescriptor {
+    Barrier {
+        position: Point,
+    },
+    HangingBarrier {
+        position: Point,
+    },
+    Coin {
+        center: Point,
+        // Every coin was bronze before there were others
+        #[serde(default)]
+        kind: CoinKind,
+    },
+    WindZone {
+        area: Rect,
+    },
+    Ground {
+        area: Rect,
+        footing: Footing,
+    },
+    #[serde(rename_all = "camelCase")]
+    Boss {
+        area: Rect,
+        patrol: i16,
+        speed: i16,
+        health: u8,
+        dying_frame: u16,
+    },
+    #[serde(rename_all = "camelCase")]
+    Platform {
+        position: Point,
+        frames: Vec<Vec<Cow<'static, str>>>,
+        frame: u16,
+        frame_duration: u16,
+        bounding_boxes: Vec<Rect>,
+    },
+    #[serde(rename_all = "camelCase")]
+    PowerUpPickup {
+        center: Point,
+        power_up: PowerUp,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum BoyMove {
+    Running,
+    Sliding,
+    Jumping,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BoyDescriptor {
+    #[serde(rename = "move")]
+    boy_move: BoyMove,
+    position: Point,
+    velocity: Point,
+}
+
+/// The run saved when the player last left the page, if there is one.
+async fn load_saved_walk() -> Option<WalkSnapshot> {
+    let saved = match browser::idb_get(SAVE_STORE, SAVE_KEY).await {
+        Ok(saved) => saved?,
+        Err(err) => {
+            log!("Could not load the saved game {:#?}", err);
+            return None;
+        }
+    };
+    match saved.into_serde() {
+        Ok(snapshot) => Some(snapshot),
+        Err(err) => {
+            log!("Ignoring a saved game that can't be read {:#?}", err);
+            None
+        }
+    }
+}
+
+fn save_walk(walk: &Walk) {
+    match JsValue::from_serde(walk) {
+        Ok(value) => browser::spawn_local(async move {
+            if let Err(err) = browser::idb_put(SAVE_STORE, SAVE_KEY, &value).await {
+                log!("Could not save the game {:#?}", err);
+            }
+        }),
+        Err(err) => log!("Could not serialize the game {:#?}", err),
+    }
+}
+
+fn delete_saved_walk() {
+    browser::spawn_local(async {
+        if let Err(err) = browser::idb_delete(SAVE_STORE, SAVE_KEY).await {
+            log!("Could not delete the saved game {:#?}", err);
+        }
+    });
+}
+
+impl WalkTheDog {
+    pub fn new() -> Self {
+        WalkTheDog {
+            machine: None,
+            status: Rc::new(GameStatus::new()),
+            last_frame: RefCell::new(None),
+            virtual_buttons: Vec::new(),
+        }
+    }
+
+    pub fn status(&self) -> Rc<GameStatus> {
+        self.status.clone()
+    }
+
+    /// The virtual buttons are only there for the touch control scheme.
+    fn touch_buttons(&self) -> &[VirtualButton] {
+        match &self.machine {
+            Some(machine) if machine.walk().control_scheme() == ControlScheme::Touch => {
+                &self.virtual_buttons
+            }
+            _ => &[],
+        }
+    }
+
+    fn draw_virtual_buttons(&self, renderer: &Renderer, redraw: &Redraw) {
+        self.touch_buttons()
+            .iter()
+            .filter(|button| redraw.needs(&button.area))
+            .for_each(|button| button.draw(renderer));
+    }
+
+    /// Works out what changed since the last frame and remembers this one.
+    fn redraw(&self, machine: &WalkTheDogStateMachine) -> Redraw {
+        let walk = machine.walk();
+        let scene = Scene {
+            state: machine.state_name(),
+            render_scale_bits: walk.config.render_scale.to_bits(),
+            hard_mode: walk.difficulty.hard_mode,
+            stats_tallied: machine.stats_tallied(),
+            control_scheme: walk.control_scheme(),
+        };
+        let regions = vec![walk.boy.destination_box()];
+        // The frame stats are drawn over the game and need a clean canvas
+        let partial = walk.config.dirty_rect_rendering && !walk.config.debug_mode;
+
+        let redraw = match self.last_frame.borrow().as_ref() {
+            Some(last_frame) if partial && machine.is_static() && last_frame.scene == scene => {
+                Redraw::between(&last_frame.regions, &regions, &walk.screen.game_rect())
+            }
+            _ => Redraw::Full,
+        };
+        self.last_frame.replace(Some(DrawnFrame { scene, regions }));
+        redraw
+    }
+}
+
+pub struct Barrier {
+    image: Image,
+    /// How far inside the image the hitbox starts on the left, the right
+    /// and the top. The bottom rests on the ground.
+    hitbox_inset: i16,
+}
+
+impl Barrier {
+    pub fn new(image: Image) -> Self {
+        Barrier::with_hitbox_inset(image, 0)
+    }
+
+    /// A barrier that is only solid `hitbox_inset` inside its image, so the
+    /// transparent margins of the sprite can be grazed. It is still drawn
+    /// whole.
+    pub fn with_hitbox_inset(image: Image, hitbox_inset: i16) -> Self {
+        Barrier {
+            image,
+            hitbox_inset,
+        }
+    }
+
+    fn hitbox(&self) -> Rect {
+        let image_box = self.image.bounding_box();
+        Rect::new_from_x_y(
+            image_box.x() + self.hitbox_inset,
+            image_box.y() + self.hitbox_inset,
+            (image_box.width - self.hitbox_inset * 2).max(0),
+            (image_box.height - self.hitbox_inset).max(0),
+        )
+    }
+
+    #[cfg(not(feature = "pixel-perfect-collision"))]
+    fn collides_with(&self, boy: &RedHatBoy) -> bool {
+        boy.bounding_box().intersects(&self.hitbox())
+    }
+
+    #[cfg(feature = "pixel-perfect-collision")]
+    fn collides_with(&self, boy: &RedHatBoy) -> bool {
+        boy.bounding_box().intersects(&self.hitbox())
+            && self.check_intersection_pixel_perfect(boy, &boy.sprite_sheet)
+    }
+
+    /// Only reports a collision when a visible pixel of the boy overlaps a
+    /// visible pixel of the barrier. Falls back to the bounding box check
+    /// when the pixels cannot be read.
+    #[cfg(feature = "pixel-perfect-collision")]
+    fn check_intersection_pixel_perfect(&self, boy: &RedHatBoy, boy_sheet: &Sheet) -> bool {
+        let sprite = match boy_sheet.frames.get(boy.frame_name()) {
+            Some(sprite) => sprite,
+            None => return true,
+        };
+        let boy_box = boy.destination_box();
+        let barrier_box = self.image.bounding_box();
+        let masks = SpriteSheet::alpha_mask(sprite, &boy.image).and_then(|boy_mask| {
+            engine::alpha_mask(
+                self.image.element(),
+                &Rect::new_from_x_y(0, 0, barrier_box.width, barrier_box.height),
+            )
+            .map(|barrier_mask| (boy_mask, barrier_mask))
+        });
+        let (boy_mask, barrier_mask) = match masks {
+            Ok(masks) => masks,
+            Err(err) => {
+                log!("Could not read pixels for collision {:#?}", err);
+                return true;
+            }
+        };
+
+        let left = boy_box.x().max(barrier_box.x());
+        let right = boy_box.right().min(barrier_box.right());
+        let top = boy_box.y().max(barrier_box.y());
+        let bottom = boy_box.bottom().min(barrier_box.bottom());
+        (top..bottom).any(|y| {
+            (left..right).any(|x| {
+                boy_mask.is_opaque(x - boy_box.x(), y - boy_box.y())
+                    && barrier_mask.is_opaque(x - barrier_box.x(), y - barrier_box.y())
+            })
+        })
+    }
+}
+
+impl Drawable for Barrier {
+    fn draw(&self, renderer: &Renderer) {
+        self.image.draw(renderer);
+    }
+}
+
+impl Obstacle for Barrier {
+    fn check_intersection(&self, boy: &mut RedHatBoy, _difficulty: &Difficulty) {
+        if !boy.dashing() && self.collides_with(boy) {
+            boy.knock_out()
+        }
+    }
+
+    fn update(&mut self) {}
+
+    /// Flashes red while the boy is about to run into it.
+    fn track_boy(&mut self, boy_box: &Rect) {
+        let barrier_box = self.image.bounding_box();
+        let gap = (barrier_box.x() - boy_box.right()).max(boy_box.x() - barrier_box.right());
+        self.image.tint =
+            (gap <= BARRIER_WARNING_DISTANCE).then(|| BARRIER_WARNING_COLOR.to_string());
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.image.move_horizontally(x)
+    }
+
+    fn right(&self) -> i16 {
+        self.image.right()
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        ObstacleDescriptor::Barrier {
+            position: self.image.bounding_box().position,
+        }
+    }
+
+    fn draw_area(&self) -> Rect {
+        self.image.bounding_box().clone()
+    }
+}
+
+/// A `Barrier` up at `DUCK_HEIGHT`. Running or jumping into it knocks the
+/// boy out, sliding he fits under it.
+pub struct HangingBarrier {
+    barrier: Barrier,
+}
+
+impl HangingBarrier {
+    pub fn new(image: Image) -> Self {
+        HangingBarrier {
+            barrier: Barrier::new(image),
+        }
+    }
+}
+
+impl Drawable for HangingBarrier {
+    fn draw(&self, renderer: &Renderer) {
+        self.barrier.draw(renderer);
+    }
+}
+
+impl Obstacle for HangingBarrier {
+    fn check_intersection(&self, boy: &mut RedHatBoy, _difficulty: &Difficulty) {
+        let barrier_box = self.barrier.image.bounding_box();
+        if !boy.dashing() && boy.bounding_box().intersects(barrier_box) {
+            boy.knock_out()
+        }
+    }
+
+    fn update(&mut self) {}
+
+    fn track_boy(&mut self, boy_box: &Rect) {
+        self.barrier.track_boy(boy_box);
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.barrier.move_horizontally(x)
+    }
+
+    fn right(&self) -> i16 {
+        self.barrier.right()
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        ObstacleDescriptor::HangingBarrier {
+            position: self.barrier.image.bounding_box().position,
+        }
+    }
+
+    fn draw_area(&self) -> Rect {
+        self.barrier.draw_area()
+    }
+}
+
+/// What a coin is made of, the rarer the more it is worth.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CoinKind {
+    Bronze,
+    Silver,
+    Gold,
+}
+
+impl Default for CoinKind {
+    fn default() -> Self {
+        CoinKind::Bronze
+    }
+}
+
+impl CoinKind {
+    pub const ALL: [CoinKind; 3] = [CoinKind::Bronze, CoinKind::Silver, CoinKind::Gold];
+
+    /// How many times `GameConfig::coin_value` the coin is worth.
+    fn value(self) -> u32 {
+        match self {
+            CoinKind::Bronze => 1,
+            CoinKind::Silver => 3,
+            CoinKind::Gold => 10,
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            CoinKind::Bronze => "#CD7F32",
+            CoinKind::Silver => "#C0C0C0",
+            CoinKind::Gold => "#FFD700",
+        }
+    }
+}
+
+pub struct Coin {
+    center: Point,
+    frame: u16,
+    kind: CoinKind,
+}
+
+impl Coin {
+    pub fn new(center: Point, kind: CoinKind) -> Self {
+        Coin {
+            center,
+            frame: 0,
+            kind,
+        }
+    }
+
+    fn phase(&self) -> f64 {
+        f64::from(self.frame) * COIN_BOB_SPEED
+    }
+
+    fn scale(&self) -> f64 {
+        1.0 + COIN_BOB_AMPLITUDE * self.phase().sin()
+    }
+
+    /// Floats up and down around the center it was put at, so coins stand
+    /// out from the obstacles that keep still.
+    fn floating_center(&self) -> Point {
+        Point {
+            x: self.center.x,
+            y: self.center.y + (self.phase().sin() * COIN_FLOAT_HEIGHT) as i16,
+        }
+    }
+}
+
+impl Drawable for Coin {
+    fn draw(&self, renderer: &Renderer) {
+        let radius = (f64::from(COIN_RADIUS) * self.scale()).round() as i16;
+        renderer.fill_circle(&self.floating_center(), radius, self.kind.color());
+    }
+}
+
+impl Obstacle for Coin {
+    // Touching a coin collects it, that is up to the walk
+    fn check_intersection(&self, _boy: &mut RedHatBoy, _difficulty: &Difficulty) {}
+
+    fn update(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.center.x += x;
+    }
+
+    fn right(&self) -> i16 {
+        self.center.x + COIN_RADIUS
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        ObstacleDescriptor::Coin {
+            center: self.center,
+            kind: self.kind,
+        }
+    }
+
+    fn draw_area(&self) -> Rect {
+        let float_height = COIN_FLOAT_HEIGHT as i16;
+        let area = Rect::new_from_x_y(
+            self.center.x - COIN_RADIUS,
+            self.center.y - COIN_RADIUS - float_height,
+            COIN_RADIUS * 2,
+            (COIN_RADIUS + float_height) * 2,
+        );
+        area.scaled(1.0 + COIN_BOB_AMPLITUDE)
+    }
+}
+
+/// Sets its power-up off when the boy touches it. A small stone, tinted in
+/// the power-up's color.
+pub struct PowerUpPickup {
+    image: Image,
+    center: Point,
+    frame: u16,
+    power_up: PowerUp,
+}
+
+impl PowerUpPickup {
+    pub fn new(stone: HtmlImageElement, center: Point, power_up: PowerUp) -> Self {
+        let mut image = Image::new(stone, Point::default());
+        image.scale = POWER_UP_PICKUP_SCALE;
+        image.tint = Some(power_up.color().to_string());
+        let mut pickup = PowerUpPickup {
+            image,
+            center,
+            frame: 0,
+            power_up,
+        };
+        pickup.image.set_position(pickup.floating_position());
+        pickup
+    }
+
+    /// Floats up and down around its center just like the coins do.
+    fn floating_position(&self) -> Point {
+        let size = self.image.bounding_box();
+        let phase = f64::from(self.frame) * COIN_BOB_SPEED;
+        Point {
+            x: self.center.x - size.width / 2,
+            y: self.center.y - size.height / 2 + (phase.sin() * COIN_FLOAT_HEIGHT) as i16,
+        }
+    }
+
+    /// Where the scaled down stone is drawn.
+    fn bounding_box(&self) -> Rect {
+        self.image.bounding_box().scaled(POWER_UP_PICKUP_SCALE)
+    }
+}
+
+impl Drawable for PowerUpPickup {
+    fn draw(&self, renderer: &Renderer) {
+        self.image.draw(renderer);
+    }
+}
+
+impl Obstacle for PowerUpPickup {
+    // Touching a pickup sets its power-up off, that is up to the walk
+    fn check_intersection(&self, _boy: &mut RedHatBoy, _difficulty: &Difficulty) {}
+
+    fn update(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+        self.image.set_position(self.floating_position());
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.center.x += x;
+        self.image.move_horizontally(x);
+    }
+
+    fn right(&self) -> i16 {
+        self.image.right()
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        ObstacleDescriptor::PowerUpPickup {
+            center: self.center,
+            power_up: self.power_up,
+        }
+    }
+
+    fn draw_area(&self) -> Rect {
+        let float_height = COIN_FLOAT_HEIGHT as i16;
+        let area = self.bounding_box();
+        Rect::new_from_x_y(
+            area.x(),
+            self.center.y - area.height / 2 - float_height,
+            area.width,
+            area.height + float_height * 2,
+        )
+    }
+}
+
+struct RainDrop {
+    x: f64,
+    y: f64,
+    length: f64,
+    speed: f64,
+}
+
+/// Falls over the whole screen once the boy walked `RAIN_START_SECONDS`.
+/// Unlike the obstacles it doesn't scroll with the world.
+struct RainSystem {
+    drops: Vec<RainDrop>,
+    active: bool,
+}
+
+impl RainSystem {
+    fn new(screen: &Screen) -> Self {
+        // Scattered without the walk's rng, so rain never changes which
+        // segments a seed generates
+        let width = screen.width.max(1) as usize;
+        let height = screen.height.max(1) as usize;
+        let drops = (0..RAIN_DROPS)
+            .map(|index| RainDrop {
+                x: ((index * 97) % width) as f64,
+                y: ((index * 53) % height) as f64,
+                length: (10 + index % 3 * 4) as f64,
+                speed: (12 + index % 4 * 3) as f64,
+            })
+            .collect();
+        RainSystem {
+            drops,
+            active: false,
+        }
+    }
+
+    /// Drops that fell out of the screen start over at the top.
+    fn update(&mut self, screen: &Screen) {
+        if !self.active {
+            return;
+        }
+        let width = f64::from(screen.width);
+        let height = f64::from(screen.height);
+        self.drops.iter_mut().for_each(|drop| {
+            drop.y += drop.speed;
+            drop.x = (drop.x - drop.speed * RAIN_SLANT).rem_euclid(width);
+            if drop.y > height {
+                drop.y = -drop.length;
+            }
+        });
+    }
+}
+
+impl Drawable for RainSystem {
+    /// Darkens the screen behind the drops, the sky is overcast.
+    fn draw(&self, renderer: &Renderer) {
+        if !self.active {
+            return;
+        }
+        let (width, height) = renderer.canvas_size();
+        renderer.draw_alpha_rect(
+            &Rect::new_from_x_y(0, 0, width as i16, height as i16),
+            RAIN_TINT_COLOR,
+            RAIN_TINT_ALPHA,
+        );
+        self.drops.iter().for_each(|drop| {
+            renderer.draw_line(
+                drop.x,
+                drop.y,
+                drop.x - drop.length * RAIN_SLANT,
+                drop.y + drop.length,
+                RAIN_COLOR,
+                RAIN_LINE_WIDTH,
+            );
+        });
+    }
+}
+
+/// Blows against the boy while he is inside it, without ever knocking him
+/// out.
+pub struct WindZone {
+    area: Rect,
+    frame: u16,
+}
+
+impl WindZone {
+    pub fn new(area: Rect) -> Self {
+        WindZone { area, frame: 0 }
+    }
+}
+
+impl Drawable for WindZone {
+    /// Streaks of air moving left, spread over the height of the zone.
+    fn draw(&self, renderer: &Renderer) {
+        let travel = (self.area.width - WIND_LINE_LENGTH).max(1);
+        let spacing = self.area.height / WIND_LINES;
+        (0..WIND_LINES).for_each(|line| {
+            // Staggered so the lines don't move in lockstep
+            let offset = (self.frame.wrapping_mul(WIND_LINE_SPEED) as i16).wrapping_add(line * 53);
+            renderer.draw_alpha_rect(
+                &Rect::new_from_x_y(
+                    self.area.right() - WIND_LINE_LENGTH - offset.rem_euclid(travel),
+                    self.area.y() + line * spacing + spacing / 2,
+                    WIND_LINE_LENGTH,
+                    2,
+                ),
+                WIND_LINE_COLOR,
+                WIND_LINE_ALPHA,
+            );
+        });
+    }
+}
+
+impl Obstacle for WindZone {
+    fn check_intersection(&self, boy: &mut RedHatBoy, _difficulty: &Difficulty) {
+        if boy.bounding_box().intersects(&self.area) {
+            boy.headwind = boy.headwind.max(WIND_FORCE);
+        }
+    }
+
+    fn update(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.area.set_x(self.area.x() + x);
+    }
+
+    fn right(&self) -> i16 {
+        self.area.right()
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        ObstacleDescriptor::WindZone {
+            area: self.area.clone(),
+        }
+    }
+
+    fn draw_area(&self) -> Rect {
+        self.area.clone()
+    }
+}
+
+/// What the boy's feet rest on.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Footing {
+    /// Where the feet are when he stands on it.
+    Ground(i16),
+    /// Nothing at all, whoever misses the jump falls off the screen.
+    Pit,
+}
+
+/// A stretch of ground at its own level, raised above the rest or a pit.
+pub struct Ground {
+    area: Rect,
+    footing: Footing,
+}
+
+impl Ground {
+    pub fn raised(x: i16, width: i16, level: i16) -> Self {
+        Ground {
+            area: Rect::new_from_x_y(x, level, width, GROUND - level),
+            footing: Footing::Ground(level),
+        }
+    }
+
+    pub fn pit(x: i16, width: i16) -> Self {
+        Ground {
+            area: Rect::new_from_x_y(x, GROUND - PIT_EDGE_HEIGHT, width, PIT_EDGE_HEIGHT),
+            footing: Footing::Pit,
+        }
+    }
+}
+
+impl Drawable for Ground {
+    fn draw(&self, renderer: &Renderer) {
+        let color = if self.footing == Footing::Pit {
+            PIT_COLOR
+        } else {
+            RAISED_GROUND_COLOR
+        };
+        renderer.fill_rect(&self.area, color);
+    }
+}
+
+impl Obstacle for Ground {
+    /// The walk asks `footing_at` where the boy stands, there is nothing to
+    /// crash into.
+    fn check_intersection(&self, _boy: &mut RedHatBoy, _difficulty: &Difficulty) {}
+
+    fn update(&mut self) {}
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.area.set_x(self.area.x() + x);
+    }
+
+    fn right(&self) -> i16 {
+        self.area.right()
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        ObstacleDescriptor::Ground {
+            area: self.area.clone(),
+            footing: self.footing,
+        }
+    }
+
+    fn draw_area(&self) -> Rect {
+        self.area.clone()
+    }
+
+    fn footing_at(&self, x: i16) -> Option<Footing> {
+        (self.area.x() <= x && x < self.area.right()).then(|| self.footing)
+    }
+}
+
+/// Paces back and forth on the ground until the boy stomps it
+/// `BOSS_HEALTH` times, then fades away. Running into it knocks the boy out.
+pub struct Boss {
+    image: HtmlImageElement,
+    area: Rect,
+    /// How far the boss walked from the left end of its patrol.
+    patrol: i16,
+    speed: i16,
+    // Stomped in check_intersection, which only borrows the boss
+    health: cell::Cell<u8>,
+    pending_score: cell::Cell<u32>,
+    dying_frame: u16,
+}
+
+impl Boss {
+    pub fn new(image: HtmlImageElement, position: Point) -> Self {
+        Boss {
+            image,
+            area: Rect::new(position, BOSS_WIDTH, BOSS_HEIGHT),
+            patrol: 0,
+            speed: BOSS_SPEED,
+            health: cell::Cell::new(BOSS_HEALTH),
+            pending_score: cell::Cell::new(0),
+            dying_frame: 0,
+        }
+    }
+
+    fn alive(&self) -> bool {
+        self.health.get() > 0
+    }
+
+    fn stomp(&self) {
+        self.health.set(self.health.get().saturating_sub(1));
+        self.pending_score
+            .set(self.pending_score.get() + BOSS_SCORE);
+    }
+
+    fn source(&self) -> Rect {
+        Rect::new_from_x_y(0, 0, self.image.width() as i16, self.image.height() as i16)
+    }
+}
+
+impl Drawable for Boss {
+    fn draw(&self, renderer: &Renderer) {
+        if self.alive() {
+            renderer.draw_image(&self.image, &self.source(), &self.area);
+            (0..i16::from(self.health.get())).for_each(|pip| {
+                renderer.fill_circle(
+                    &Point {
+                        x: self.area.x() + (pip + 1) * BOSS_HEALTH_PIP_SPACING,
+                        y: self.area.y() - BOSS_HEALTH_PIP_HEIGHT,
+                    },
+                    BOSS_HEALTH_PIP_RADIUS,
+                    BOSS_HEALTH_COLOR,
+                );
+            });
+        } else if (self.dying_frame / BOSS_DYING_FLICKER) % 2 == 0 {
+            let alpha = 1.0 - f64::from(self.dying_frame) / f64::from(BOSS_DYING_FRAMES);
+            renderer.draw_image_with_alpha(&self.image, &self.source(), &self.area, alpha);
+        }
+    }
+}
+
+impl Obstacle for Boss {
+    fn check_intersection(&self, boy: &mut RedHatBoy, _difficulty: &Difficulty) {
+        if !self.alive() || !boy.bounding_box().intersects(&self.area) {
+            return;
+        }
+        // The feet were above the boss on the previous frame
+        let stomping = !boy.is_on_ground()
+            && boy.velocity_y() > 0
+            && boy.bounding_box().bottom() - boy.velocity_y() <= self.area.y();
+        if stomping {
+            self.stomp();
+            // Bounces off the boss's head
+            boy.land_on(self.area.y());
+            boy.jump();
+        } else if !boy.dashing() {
+            boy.knock_out();
+        }
+    }
+
+    fn update(&mut self) {
+        if self.alive() {
+            if !(0..=BOSS_PATROL_RANGE).contains(&(self.patrol + self.speed)) {
+                self.speed = -self.speed;
+            }
+            self.patrol += self.speed;
+            self.area.set_x(self.area.x() + self.speed);
+        } else {
+            self.dying_frame = self.dying_frame.saturating_add(1);
+        }
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.area.set_x(self.area.x() + x);
+    }
+
+    /// A dead boss is gone as soon as it faded away.
+    fn right(&self) -> i16 {
+        if self.dying_frame >= BOSS_DYING_FRAMES {
+            i16::MIN
+        } else {
+            self.area.right()
+        }
+    }
+
+    // There is only one boss per run, it's not worth pooling
+    fn draw_area(&self) -> Rect {
+        let above = BOSS_HEALTH_PIP_HEIGHT + BOSS_HEALTH_PIP_RADIUS;
+        Rect::new_from_x_y(
+            self.area.x(),
+            self.area.y() - above,
+            self.area.width,
+            self.area.height + above,
+        )
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        ObstacleDescriptor::Boss {
+            area: self.area.clone(),
+            patrol: self.patrol,
+            speed: self.speed,
+            health: self.health.get(),
+            dying_frame: self.dying_frame,
+        }
+    }
+
+    fn take_score(&mut self) -> u32 {
+        self.pending_score.take()
+    }
+}
+
+impl Collectible for Coin {
+    // Where the coin is drawn, it is picked up there too
+    fn bounding_box(&self) -> Rect {
+        let center = self.floating_center();
+        Rect::new_from_x_y(
+            center.x - COIN_RADIUS,
+            center.y - COIN_RADIUS,
+            COIN_RADIUS * 2,
+            COIN_RADIUS * 2,
+        )
+    }
+
+    fn center(&self) -> Point {
+        self.floating_center()
+    }
+
+    fn move_by(&mut self, offset: Point) {
+        self.center = self.center + offset;
+    }
+
+    fn kind(&self) -> CoinKind {
+        self.kind
+    }
+}
+
+pub struct RedHatBoy {
+    state_machine: RedHatBoyStateMachine,
+    sprite_sheet: Sheet,
+    image: HtmlImageElement,
+    current_frame: CachedFrame,
+    /// Slows the boy down for the next update.
+    headwind: i16,
+}
+
+/// The sprite for the boy's current animation frame. It is only looked up
+/// again when the animation or its cell changes, not on every draw.
+struct CachedFrame {
+    animation: &'static str,
+    cell_number: u8,
+    name: String,
+    sprite: Option<Cell>,
+    /// `Sheet::cell_ends` of every animation shown so far.
+    cell_ends: HashMap<&'static str, Vec<u8>>,
+}
+
+impl CachedFrame {
+    fn new() -> Self {
+        CachedFrame {
+            animation: "",
+            cell_number: 0,
+            name: String::new(),
+            sprite: None,
+            cell_ends: HashMap::new(),
+        }
+    }
+
+    fn refresh(&mut self, state_machine: &RedHatBoyStateMachine, sheet: &Sheet) {
+        let animation = state_machine.frame_name();
+        let frame = state_machine.context().frame;
+        let cell_ends = self
+            .cell_ends
+            .entry(animation)
+            .or_insert_with(|| sheet.cell_ends(animation));
+        // Past the last cell, as the falling boy is, the last cell stays up
+        let cell_number = cell_ends
+            .iter()
+            .position(|end| frame < *end)
+            .unwrap_or_else(|| cell_ends.len().saturating_sub(1)) as u8
+            + 1;
+        if self.animation != animation || self.cell_number != cell_number {
+            self.animation = animation;
+            self.cell_number = cell_number;
+            self.name = format!("{} ({}).png", animation, cell_number);
+            self.sprite = sheet.frames.get(&self.name).cloned();
+        }
+    }
+}
+
+impl RedHatBoy {
+    fn new(
+        sheet: Sheet,
+        image: HtmlImageElement,
+        audio: Rc<dyn AudioBackend>,
+        sound: WebAudioSound,
+        physics: PhysicsConfig,
+    ) -> Self {
+        let state_machine =
+            RedHatBoyStateMachine::Idle(RedHatBoyState::new(&sheet, audio, sound, physics));
+        let mut current_frame = CachedFrame::new();
+        current_frame.refresh(&state_machine, &sheet);
+        RedHatBoy {
+            state_machine,
+            sprite_sheet: sheet,
+            image,
+            current_frame,
+            headwind: 0,
+        }
+    }
+
+    fn walking_speed(&self) -> i16 {
+        self.state_machine.context().velocity.x
+    }
+
+    fn frame_name(&self) -> &str {
+        &self.current_frame.name
+    }
+
+    fn current_sprite(&self) -> Option<&Cell> {
+        self.current_frame.sprite.as_ref()
+    }
+
+    fn set_slow_motion(&mut self, slow_motion: bool) {
+        self.state_machine.context_mut().frame_advance_every = if slow_motion {
+            SLOW_MOTION_FACTOR as u8
+        } else {
+            1
+        };
+    }
+
+    fn set_rocket(&mut self, rocket: bool) {
+        self.state_machine.context_mut().rocket = rocket;
+    }
+
+    /// When the flip wears off the boy is snapped back onto the ground
+    /// under him, rather than falling the height of the screen. Over a pit
+    /// there is nothing to snap to, and down he goes.
+    fn set_gravity_flipped(&mut self, gravity_flipped: bool) {
+        let context = self.state_machine.context_mut();
+        if context.gravity_flipped && !gravity_flipped {
+            if let Some(floor) = context.floor {
+                context.position.y = floor;
+            }
+            context.velocity.y = 0;
+        }
+        context.gravity_flipped = gravity_flipped;
+    }
+
+    fn gravity_flipped(&self) -> bool {
+        self.state_machine.context().gravity_flipped
+    }
+
+    /// Takes effect on the next update. A jump under way keeps its velocity,
+    /// only what is added to it from then on changes.
+    fn set_physics(&mut self, physics: PhysicsConfig) {
+        self.state_machine.context_mut().physics = physics;
+    }
+
+    /// Where the boy stops falling from the next update on, if he does.
+    fn set_footing(&mut self, footing: Footing) {
+        self.state_machine.context_mut().floor = match footing {
+            Footing::Ground(ground) => Some(ground - PLAYER_HEIGHT),
+            Footing::Pit => None,
+        };
+    }
+
+    fn set_state_machine(&mut self, state_machine: RedHatBoyStateMachine) {
+        self.current_frame
+            .refresh(&state_machine, &self.sprite_sheet);
+        self.state_machine = state_machine;
+    }
+
+    /// Upside down, the sprite is mirrored within `PLAYER_HEIGHT`, so his
+    /// feet touch the ceiling the way they touch the floor.
+    fn destination_box(&self) -> Rect {
+        let sprite = self.current_sprite().expect("Cell not found");
+        let offset_y = if self.gravity_flipped() {
+            PLAYER_HEIGHT - sprite.sprite_source_size.y - sprite.frame.h
+        } else {
+            sprite.sprite_source_size.y
+        };
+        Rect::new_from_x_y(
+            self.state_machine.context().position.x + sprite.sprite_source_size.x,
+            self.state_machine.context().position.y + offset_y,
+            sprite.frame.w.into(),
+            sprite.frame.h.into(),
+        )
+    }
+
+    /// A sliding boy leaves his head out, so he passes under a
+    /// `HangingBarrier`. Upside down, his head is at the bottom.
+    fn bounding_box(&self) -> Rect {
+        const X_OFFSET: i16 = 18;
+        const Y_OFFSET: i16 = 14;
+        const WIDTH_OFFSET: i16 = 28;
+        let y_offset = if self.is_sliding() {
+            Y_OFFSET + self.state_machine.context().physics.slide_box_crop
+        } else {
+            Y_OFFSET
+        };
+        let bounding_box = self.destination_box();
+        let top = if self.gravity_flipped() {
+            bounding_box.y()
+        } else {
+            bounding_box.y() + y_offset
+        };
+        Rect::new_from_x_y(
+            bounding_box.x() + X_OFFSET,
+            top,
+            bounding_box.width - WIDTH_OFFSET,
+            bounding_box.height - y_offset,
+        )
+    }
+
+    fn audio(&self) -> &Rc<dyn AudioBackend> {
+        &self.state_machine.context().audio
+    }
+
+    fn velocity_y(&self) -> i16 {
+        self.state_machine.context().velocity.y
+    }
+
+    fn pos_y(&self) -> i16 {
+        self.state_machine.context().position.y
+    }
+
+    fn position(&self) -> Point {
+        self.state_machine.context().position
+    }
+
+    fn dashing(&self) -> bool {
+        self.state_machine.context().dashing()
+    }
+
+    /// On the ground itself, a boy on a platform or running off one is not.
+    fn is_on_ground(&self) -> bool {
+        self.state_machine.context().on_floor()
+    }
+
+    fn is_jumping(&self) -> bool {
+        self.state_machine.boy_move() == BoyMove::Jumping
+    }
+
+    fn is_sliding(&self) -> bool {
+        self.state_machine.boy_move() == BoyMove::Sliding
+    }
+
+    /// How long the boy was airborne, once after every landing.
+    fn take_landed_air_frames(&mut self) -> Option<u16> {
+        self.state_machine.context_mut().landed_air_frames.take()
+    }
+
+    /// Whether the boy did a slide jump since the last call.
+    fn take_slide_jump(&mut self) -> bool {
+        std::mem::take(&mut self.state_machine.context_mut().slide_jumped)
+    }
+
+    /// While dashing, fading copies of the boy trail behind him.
+    fn draw_with_alpha(&self, renderer: &Renderer, alpha: f64) {
+        let sprite = self.current_sprite().expect("Cell not found");
+        let frame = Rect::new_from_x_y(
+            sprite.frame.x,
+            sprite.frame.y,
+            sprite.frame.w.into(),
+            sprite.frame.h.into(),
+        );
+        let destination = self.destination_box();
+        if self.dashing() {
+            (1..=DASH_TRAIL_LENGTH).rev().for_each(|copy| {
+                let mut trail = destination.clone();
+                trail.set_x(destination.x() - copy * DASH_TRAIL_SPACING);
+                self.draw_frame(
+                    renderer,
+                    &frame,
+                    &trail,
+                    alpha * DASH_TRAIL_ALPHA / f64::from(copy),
+                );
+            });
+        }
+        self.draw_frame(renderer, &frame, &destination, alpha);
+    }
+
+    fn draw_frame(&self, renderer: &Renderer, frame: &Rect, destination: &Rect, alpha: f64) {
+        if self.gravity_flipped() {
+            renderer.draw_image_flipped_vertical(&self.image, frame, destination, alpha);
+        } else {
+            renderer.draw_image_with_alpha(&self.image, frame, destination, alpha);
+        }
+    }
+
+    fn update(&mut self) {
+        self.set_state_machine(self.state_machine.clone().update());
+    }
+
+    fn run_right(&mut self) {
+        self.set_state_machine(self.state_machine.clone().transition(Event::Run));
+    }
+
+    /// Keeps a running boy from slowing down this update, without speeding
+    /// him back up.
+    fn keep_running(&mut self) {
+        self.state_machine.context_mut().run_commanded_this_tick = true;
+    }
+    fn knock_out(&mut self) {
+        self.set_state_machine(self.state_machine.clone().transition(Event::KnockOut));
+    }
+    fn fall_off(&mut self) {
+        self.set_state_machine(self.state_machine.clone().transition(Event::FallOff));
+    }
+    fn slide(&mut self) {
+        self.set_state_machine(self.state_machine.clone().transition(Event::Slide));
+    }
+
+    fn jump(&mut self) {
+        self.set_state_machine(self.state_machine.clone().transition(Event::Jump));
+    }
+
+    fn dash(&mut self) {
+        self.set_state_machine(self.state_machine.clone().transition(Event::Dash));
+    }
+
+    fn land_on(&mut self, y: i16) {
+        self.set_state_machine(self.state_machine.clone().transition(Event::Land(y)));
+    }
+
+    fn knocked_out(&self) -> bool {
+        self.state_machine.knocked_out()
+    }
+
+    fn descriptor(&self) -> BoyDescriptor {
+        let context = self.state_machine.context();
+        BoyDescriptor {
+            boy_move: self.state_machine.boy_move(),
+            position: context.position,
+            velocity: context.velocity,
+        }
+    }
+
+    /// A fresh boy, doing what the saved one was doing where he was doing it.
+    fn restore(boy: Self, saved: &BoyDescriptor) -> Self {
+        let mut boy = RedHatBoy::reset(boy);
+        boy.run_right();
+        match saved.boy_move {
+            BoyMove::Running => {}
+            BoyMove::Sliding => boy.slide(),
+            BoyMove::Jumping => boy.jump(),
+        }
+        let context = boy.state_machine.context_mut();
+        context.position = saved.position;
+        context.velocity = saved.velocity;
+        boy
+    }
+
+    fn reset(boy: Self) -> Self {
+        RedHatBoy::new(
+            boy.sprite_sheet,
+            boy.image,
+            boy.state_machine.context().audio.clone(),
+            boy.state_machine.context().jump_sound.clone(),
+            boy.state_machine.context().physics,
+        )
+    }
+}
+
+/// Drawn without a ghostly fade, the walk uses `draw_with_alpha` for that.
+impl Drawable for RedHatBoy {
+    fn draw(&self, renderer: &Renderer) {
+        self.draw_with_alpha(renderer, 1.0);
+    }
+}
 
 #[derive(Clone)]
 enum RedHatBoyStateMachine {
@@ -484,632 +3894,3403 @@ enum RedHatBoyStateMachine {
     KnockedOut(RedHatBoyState<KnockedOut>),
 }
 
-pub enum Event {
-    Run,
-    Slide,
-    Jump,
-    KnockOut,
-    Land(i16),
-    Update,
+pub enum Event {
+    Run,
+    Slide,
+    Jump,
+    Dash,
+    KnockOut,
+    FallOff,
+    Land(i16),
+    Update,
+}
+
+impl RedHatBoyStateMachine {
+    fn transition(self, event: Event) -> Self {
+        match (self.clone(), event) {
+            (RedHatBoyStateMachine::Idle(state), Event::Run) => state.run().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Run) => state.run().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Slide) => state.slide().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Jump) => state.jump().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Dash) => state.dash().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Dash) => state.dash().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::Dash) => state.dash().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Jump) => state.jump().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Slide) => state.keep_sliding().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::Slide) => {
+                state.slide_on_landing().into()
+            }
+            (RedHatBoyStateMachine::Running(state), Event::KnockOut) => state.knock_out().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Land(position)) => {
+                state.land_on(position).into()
+            }
+            (RedHatBoyStateMachine::Jumping(state), Event::KnockOut) => state.knock_out().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::Land(position)) => {
+                state.land_on(position).into()
+            }
+            (RedHatBoyStateMachine::Running(state), Event::Land(position)) => {
+                state.land_on(position).into()
+            }
+            (RedHatBoyStateMachine::Sliding(state), Event::KnockOut) => state.knock_out().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Land(position)) => {
+                state.land_on(position).into()
+            }
+            (RedHatBoyStateMachine::KnockedOut(state), Event::Land(position)) => {
+                state.land_on(position).into()
+            }
+            (RedHatBoyStateMachine::Running(state), Event::FallOff) => state.fall_off().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::FallOff) => state.fall_off().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::FallOff) => state.fall_off().into(),
+            (RedHatBoyStateMachine::Falling(state), Event::FallOff) => state.fall_off().into(),
+
+            (RedHatBoyStateMachine::Idle(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Running(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Sliding(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Jumping(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::Falling(state), Event::Update) => state.update().into(),
+            (RedHatBoyStateMachine::KnockedOut(state), Event::Update) => state.update().into(),
+            _ => self,
+        }
+    }
+
+    fn frame_name(&self) -> &'static str {
+        match self {
+            RedHatBoyStateMachine::Idle(state) => state.frame_name(),
+            RedHatBoyStateMachine::Running(state) => state.frame_name(),
+            RedHatBoyStateMachine::Sliding(state) => state.frame_name(),
+            RedHatBoyStateMachine::Jumping(state) => state.frame_name(),
+            RedHatBoyStateMachine::Falling(state) => state.frame_name(),
+            RedHatBoyStateMachine::KnockedOut(state) => state.frame_name(),
+        }
+    }
+    fn context(&self) -> &RedHatBoyContext {
+        match self {
+            RedHatBoyStateMachine::Idle(state) => &state.context(),
+            RedHatBoyStateMachine::Running(state) => &state.context(),
+            RedHatBoyStateMachine::Sliding(state) => &state.context(),
+            RedHatBoyStateMachine::Jumping(state) => &state.context(),
+            RedHatBoyStateMachine::Falling(state) => &state.context(),
+            RedHatBoyStateMachine::KnockedOut(state) => &state.context(),
+        }
+    }
+
+    fn context_mut(&mut self) -> &mut RedHatBoyContext {
+        match self {
+            RedHatBoyStateMachine::Idle(state) => state.context_mut(),
+            RedHatBoyStateMachine::Running(state) => state.context_mut(),
+            RedHatBoyStateMachine::Sliding(state) => state.context_mut(),
+            RedHatBoyStateMachine::Jumping(state) => state.context_mut(),
+            RedHatBoyStateMachine::Falling(state) => state.context_mut(),
+            RedHatBoyStateMachine::KnockedOut(state) => state.context_mut(),
+        }
+    }
+
+    fn update(self) -> Self {
+        self.transition(Event::Update)
+    }
+
+    fn knocked_out(&self) -> bool {
+        matches!(self, RedHatBoyStateMachine::KnockedOut(_))
+    }
+
+    /// A boy that is down is saved as running, a run is never saved then.
+    fn boy_move(&self) -> BoyMove {
+        match self {
+            RedHatBoyStateMachine::Sliding(_) => BoyMove::Sliding,
+            RedHatBoyStateMachine::Jumping(_) => BoyMove::Jumping,
+            _ => BoyMove::Running,
+        }
+    }
+}
+
+impl From<RedHatBoyState<Idle>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Idle>) -> Self {
+        RedHatBoyStateMachine::Idle(state)
+    }
+}
+
+impl From<RedHatBoyState<Running>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Running>) -> Self {
+        RedHatBoyStateMachine::Running(state)
+    }
+}
+
+impl From<RedHatBoyState<Sliding>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Sliding>) -> Self {
+        RedHatBoyStateMachine::Sliding(state)
+    }
+}
+
+impl From<RedHatBoyState<Jumping>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Jumping>) -> Self {
+        RedHatBoyStateMachine::Jumping(state)
+    }
+}
+
+impl From<RedHatBoyState<Falling>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<Falling>) -> Self {
+        RedHatBoyStateMachine::Falling(state)
+    }
+}
+
+impl From<RedHatBoyState<KnockedOut>> for RedHatBoyStateMachine {
+    fn from(state: RedHatBoyState<KnockedOut>) -> Self {
+        RedHatBoyStateMachine::KnockedOut(state)
+    }
+}
+
+impl From<SlidingEndState> for RedHatBoyStateMachine {
+    fn from(end_state: SlidingEndState) -> Self {
+        match end_state {
+            SlidingEndState::Complete(running_state) => running_state.into(),
+            SlidingEndState::Sliding(sliding_state) => sliding_state.into(),
+        }
+    }
+}
+
+impl From<JumpingEndState> for RedHatBoyStateMachine {
+    fn from(end_state: JumpingEndState) -> Self {
+        match end_state {
+            JumpingEndState::Complete(running_state) => running_state.into(),
+            JumpingEndState::Sliding(sliding_state) => sliding_state.into(),
+            JumpingEndState::Jumping(jumping_state) => jumping_state.into(),
+        }
+    }
+}
+
+impl From<FallingState> for RedHatBoyStateMachine {
+    fn from(falling_state: FallingState) -> Self {
+        match falling_state {
+            FallingState::Complete(knockout_state) => knockout_state.into(),
+            FallingState::Falling(falling_state) => falling_state.into(),
+        }
+    }
+}
+
+pub(crate) fn rightmost(obstacle_list: &Vec<ObstacleKind>) -> i16 {
+    obstacle_list
+        .iter()
+        .map(|obstacle| obstacle.right())
+        .max_by(|x, y| x.cmp(&y))
+        .unwrap_or(0)
+}
+
+pub struct Platform {
+    sheet: Rc<SpriteSheet>,
+    /// Sprite names, looked up in `sheet` when drawn so a saved game only
+    /// has to name them.
+    frames: Vec<Vec<Cow<'static, str>>>,
+    frame: u16,
+    frame_duration: u16,
+    position: Point,
+    bounding_boxes: Vec<Rect>,
+}
+
+impl Drawable for Platform {
+    fn draw(&self, renderer: &Renderer) {
+        let mut x = 0;
+        self.current_sprites().for_each(|sprite| {
+            self.sheet.draw(
+                renderer,
+                &Rect::new_from_x_y(
+                    sprite.frame.x,
+                    sprite.frame.y,
+                    sprite.frame.w,
+                    sprite.frame.h,
+                ),
+                // Just use position and the standard widths in the tileset
+                &Rect::new_from_x_y(
+                    self.position.x + x,
+                    self.position.y,
+                    sprite.frame.w,
+                    sprite.frame.h,
+                ),
+            );
+            x += sprite.frame.w;
+        });
+    }
+}
+
+impl Obstacle for Platform {
+    fn check_intersection(&self, boy: &mut RedHatBoy, difficulty: &Difficulty) {
+        if let Some(box_to_land_on) = self
+            .bounding_boxes()
+            .iter()
+            .find(|&bounding_box| boy.bounding_box().intersects(bounding_box))
+        {
+            // Upside down the boy lands on the underside, coming from below
+            let landing = if boy.gravity_flipped() {
+                let below_platform = if difficulty.hard_mode {
+                    boy.bounding_box().y() - boy.velocity_y() >= box_to_land_on.bottom()
+                } else {
+                    boy.bounding_box().bottom() > box_to_land_on.bottom()
+                };
+                (boy.velocity_y() < 0 && below_platform).then(|| box_to_land_on.bottom())
+            } else {
+                let above_platform = if difficulty.hard_mode {
+                    // The feet must have been above the platform top on the previous frame
+                    boy.bounding_box().bottom() - boy.velocity_y() <= box_to_land_on.y()
+                } else {
+                    boy.pos_y() < self.position.y
+                };
+                (boy.velocity_y() > 0 && above_platform).then(|| box_to_land_on.y())
+            };
+            if let Some(feet) = landing {
+                boy.land_on(feet);
+            } else if !boy.dashing() {
+                boy.knock_out();
+            }
+        }
+    }
+
+    fn update(&mut self) {
+        // Platforms with a single frame never animate, so there is nothing to advance
+        if self.frames.len() > 1 {
+            self.frame = (self.frame + 1) % (self.frames.len() as u16 * self.frame_duration);
+        }
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.position.x += x;
+        self.bounding_boxes.iter_mut().for_each(|bounding_box| {
+            bounding_box.set_x(bounding_box.position.x + x);
+        });
+    }
+
+    fn right(&self) -> i16 {
+        self.bounding_boxes()
+            .last()
+            .unwrap_or(&Rect::default())
+            .right()
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        ObstacleDescriptor::Platform {
+            position: self.position,
+            frames: self.frames.clone(),
+            frame: self.frame,
+            frame_duration: self.frame_duration,
+            bounding_boxes: self.bounding_boxes.clone(),
+        }
+    }
+
+    fn draw_area(&self) -> Rect {
+        Rect::new(
+            self.position,
+            self.current_sprites().map(|sprite| sprite.frame.w).sum(),
+            self.current_sprites()
+                .map(|sprite| sprite.frame.h)
+                .max()
+                .unwrap_or(0),
+        )
+    }
+
+    fn debug_draw(&self, renderer: &Renderer) {
+        self.bounding_box_debug_draw(renderer);
+    }
+}
+
+impl Platform {
+    pub fn new(
+        sheet: Rc<SpriteSheet>,
+        position: Point,
+        sprite_names: &[&'static str],
+        bounding_boxes: &[Rect],
+    ) -> Self {
+        Platform::animated(sheet, position, &[sprite_names], bounding_boxes, 1)
+    }
+
+    /// Outlines every bounding box in its own color, to tell where one
+    /// ends and the next begins.
+    pub fn bounding_box_debug_draw(&self, renderer: &Renderer) {
+        self.bounding_boxes
+            .iter()
+            .zip(DEBUG_BOX_COLORS.iter().cycle())
+            .for_each(|(bounding_box, color)| renderer.draw_rect_with_color(bounding_box, color));
+    }
+
+    /// A floating platform at least `width` pixels wide, made of a left cap,
+    /// as many middle tiles as it takes and a right cap. The tile widths are
+    /// read from the sheet, so the actual width is rounded up to whole tiles.
+    pub fn from_width(sheet: Rc<SpriteSheet>, position: Point, width: i16) -> Self {
+        let mut platform = Platform::empty(sheet);
+        platform.reset_to_width(position, width);
+        platform
+    }
+
+    /// A platform that cycles through `frames`, showing every set of sprite
+    /// names for `frame_duration` updates.
+    pub fn animated(
+        sheet: Rc<SpriteSheet>,
+        position: Point,
+        frames: &[&[&'static str]],
+        bounding_boxes: &[Rect],
+        frame_duration: u16,
+    ) -> Self {
+        let mut platform = Platform::empty(sheet);
+        platform.reset(position, frames, bounding_boxes, frame_duration);
+        platform
+    }
+
+    fn empty(sheet: Rc<SpriteSheet>) -> Self {
+        Platform {
+            sheet,
+            bounding_boxes: vec![],
+            frames: vec![],
+            frame: 0,
+            frame_duration: 1,
+            position: Point::default(),
+        }
+    }
+
+    /// Turns this platform into a new one in place, reusing the memory
+    /// of its frames and bounding boxes.
+    fn reset(
+        &mut self,
+        position: Point,
+        frames: &[&[&'static str]],
+        bounding_boxes: &[Rect],
+        frame_duration: u16,
+    ) {
+        self.frames.resize_with(frames.len(), Vec::new);
+        self.frames
+            .iter_mut()
+            .zip(frames)
+            .for_each(|(names, sprite_names)| {
+                names.clear();
+                names.extend(sprite_names.iter().map(|&name| Cow::Borrowed(name)));
+            });
+        self.set_bounding_boxes(position, bounding_boxes);
+        self.frame = 0;
+        // Every frame shows for at least an update, 0 would divide by zero
+        self.frame_duration = frame_duration.max(1);
+        self.position = position;
+    }
+
+    fn reset_to_width(&mut self, position: Point, width: i16) {
+        let [left, middle, right] = FLOATING_PLATFORM_SPRITES;
+        let sheet = &self.sheet;
+        let tile_width = |name: &str| sheet.cell(name).map_or(0, |cell| cell.frame.w);
+        let caps_width = tile_width(left) + tile_width(right);
+        let middle_width = tile_width(middle);
+        let middle_count = if middle_width > 0 {
+            ((width - caps_width).max(0) + middle_width - 1) / middle_width
+        } else {
+            0
+        };
+
+        self.frames.resize_with(1, Vec::new);
+        let names = &mut self.frames[0];
+        names.clear();
+        names.extend(
+            std::iter::once(left)
+                .chain(std::iter::repeat(middle).take(middle_count as usize))
+                .chain(std::iter::once(right))
+                .map(Cow::Borrowed),
+        );
+        self.set_bounding_boxes(
+            position,
+            &floating_platform_bounding_boxes(caps_width + middle_count * middle_width),
+        );
+        self.frame = 0;
+        self.frame_duration = 1;
+        self.position = position;
+    }
+
+    fn set_bounding_boxes(&mut self, position: Point, bounding_boxes: &[Rect]) {
+        self.bounding_boxes.clear();
+        self.bounding_boxes
+            .extend(bounding_boxes.iter().map(|bounding_box| {
+                Rect::new_from_x_y(
+                    bounding_box.x() + position.x,
+                    bounding_box.y() + position.y,
+                    bounding_box.width,
+                    bounding_box.height,
+                )
+            }));
+    }
+
+    fn current_sprites(&self) -> impl Iterator<Item = &Cell> {
+        self.frames
+            .get((self.frame / self.frame_duration) as usize)
+            .into_iter()
+            .flatten()
+            .filter_map(move |sprite_name| self.sheet.cell(sprite_name))
+    }
+
+    fn bounding_boxes(&self) -> &Vec<Rect> {
+        &self.bounding_boxes
+    }
+}
+
+/// Obstacles are drawn back to front, the boy between the middle and the
+/// front layer.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Layer {
+    Back,
+    Middle,
+    Front,
+}
+
+pub trait Obstacle: Drawable {
+    fn check_intersection(&self, boy: &mut RedHatBoy, difficulty: &Difficulty);
+    fn move_horizontally(&mut self, x: i16);
+    fn right(&self) -> i16;
+    fn update(&mut self);
+    /// Everything `draw` can touch.
+    fn draw_area(&self) -> Rect;
+    /// What it takes to put the obstacle back after loading a saved game.
+    fn descriptor(&self) -> ObstacleDescriptor;
+
+    /// Points scored off the obstacle since the last call.
+    fn take_score(&mut self) -> u32 {
+        0
+    }
+
+    /// Where the obstacle is drawn relative to the boy, collisions don't
+    /// care.
+    fn layer(&self) -> Layer {
+        Layer::Middle
+    }
+
+    /// Lets the obstacle react to where the boy is, every update.
+    fn track_boy(&mut self, _boy_box: &Rect) {}
+
+    /// What the feet rest on at `x`, if the obstacle sets the ground there.
+    fn footing_at(&self, _x: i16) -> Option<Footing> {
+        None
+    }
+
+    /// Outlines the obstacle for the debug overlay.
+    fn debug_draw(&self, renderer: &Renderer) {
+        renderer.draw_rect(&self.draw_area());
+    }
+}
+
+/// Something the boy picks up by touching it instead of crashing into it.
+pub trait Collectible {
+    fn bounding_box(&self) -> Rect;
+    fn center(&self) -> Point;
+    fn move_by(&mut self, offset: Point);
+    fn kind(&self) -> CoinKind;
+}
+
+/// Every obstacle there is, so code that only cares about one kind of them
+/// can pick it out without downcasting.
+pub enum ObstacleKind {
+    Barrier(Box<Barrier>),
+    HangingBarrier(Box<HangingBarrier>),
+    Platform(Box<Platform>),
+    Coin(Box<Coin>),
+    WindZone(Box<WindZone>),
+    Ground(Box<Ground>),
+    Boss(Box<Boss>),
+    PowerUpPickup(Box<PowerUpPickup>),
+}
+
+impl ObstacleKind {
+    fn as_obstacle(&self) -> &dyn Obstacle {
+        match self {
+            ObstacleKind::Barrier(barrier) => &**barrier,
+            ObstacleKind::HangingBarrier(hanging_barrier) => &**hanging_barrier,
+            ObstacleKind::Platform(platform) => &**platform,
+            ObstacleKind::Coin(coin) => &**coin,
+            ObstacleKind::WindZone(wind_zone) => &**wind_zone,
+            ObstacleKind::Ground(ground) => &**ground,
+            ObstacleKind::Boss(boss) => &**boss,
+            ObstacleKind::PowerUpPickup(pickup) => &**pickup,
+        }
+    }
+
+    fn as_obstacle_mut(&mut self) -> &mut dyn Obstacle {
+        match self {
+            ObstacleKind::Barrier(barrier) => &mut **barrier,
+            ObstacleKind::HangingBarrier(hanging_barrier) => &mut **hanging_barrier,
+            ObstacleKind::Platform(platform) => &mut **platform,
+            ObstacleKind::Coin(coin) => &mut **coin,
+            ObstacleKind::WindZone(wind_zone) => &mut **wind_zone,
+            ObstacleKind::Ground(ground) => &mut **ground,
+            ObstacleKind::Boss(boss) => &mut **boss,
+            ObstacleKind::PowerUpPickup(pickup) => &mut **pickup,
+        }
+    }
+}
+
+impl Drawable for ObstacleKind {
+    fn draw(&self, renderer: &Renderer) {
+        self.as_obstacle().draw(renderer);
+    }
+}
+
+impl Obstacle for ObstacleKind {
+    fn check_intersection(&self, boy: &mut RedHatBoy, difficulty: &Difficulty) {
+        self.as_obstacle().check_intersection(boy, difficulty);
+    }
+
+    fn move_horizontally(&mut self, x: i16) {
+        self.as_obstacle_mut().move_horizontally(x);
+    }
+
+    fn right(&self) -> i16 {
+        self.as_obstacle().right()
+    }
+
+    fn update(&mut self) {
+        self.as_obstacle_mut().update();
+    }
+
+    fn draw_area(&self) -> Rect {
+        self.as_obstacle().draw_area()
+    }
+
+    fn descriptor(&self) -> ObstacleDescriptor {
+        self.as_obstacle().descriptor()
+    }
+
+    fn take_score(&mut self) -> u32 {
+        self.as_obstacle_mut().take_score()
+    }
+
+    fn layer(&self) -> Layer {
+        self.as_obstacle().layer()
+    }
+
+    fn track_boy(&mut self, boy_box: &Rect) {
+        self.as_obstacle_mut().track_boy(boy_box);
+    }
+
+    fn footing_at(&self, x: i16) -> Option<Footing> {
+        self.as_obstacle().footing_at(x)
+    }
+
+    fn debug_draw(&self, renderer: &Renderer) {
+        self.as_obstacle().debug_draw(renderer);
+    }
+}
+
+/// The obstacles `Walk::obstacles_of_type` can pick out of an
+/// `ObstacleKind`.
+pub trait ObstacleType {
+    fn of_kind(kind: &ObstacleKind) -> Option<&Self>;
+    fn of_kind_mut(kind: &mut ObstacleKind) -> Option<&mut Self>;
+}
+
+macro_rules! obstacle_types {
+    ($($variant:ident),*) => {
+        $(
+            impl ObstacleType for $variant {
+                fn of_kind(kind: &ObstacleKind) -> Option<&Self> {
+                    match kind {
+                        ObstacleKind::$variant(obstacle) => Some(&**obstacle),
+                        _ => None,
+                    }
+                }
+
+                fn of_kind_mut(kind: &mut ObstacleKind) -> Option<&mut Self> {
+                    match kind {
+                        ObstacleKind::$variant(obstacle) => Some(&mut **obstacle),
+                        _ => None,
+                    }
+                }
+            }
+
+            impl From<Box<$variant>> for ObstacleKind {
+                fn from(obstacle: Box<$variant>) -> Self {
+                    ObstacleKind::$variant(obstacle)
+                }
+            }
+        )*
+    };
+}
+
+obstacle_types!(
+    Barrier,
+    HangingBarrier,
+    Platform,
+    Coin,
+    WindZone,
+    Ground,
+    Boss,
+    PowerUpPickup
+);
+
+/// Obstacles that scrolled off screen, kept around so new segments can
+/// reuse them instead of allocating fresh ones.
+#[derive(Default)]
+pub struct ObstaclePool {
+    barriers: Vec<Box<Barrier>>,
+    hanging_barriers: Vec<Box<HangingBarrier>>,
+    platforms: Vec<Box<Platform>>,
+    coins: Vec<Box<Coin>>,
+    wind_zones: Vec<Box<WindZone>>,
+}
+
+impl ObstaclePool {
+    pub fn wind_zone(&mut self, area: Rect) -> ObstacleKind {
+        match self.wind_zones.pop() {
+            Some(mut wind_zone) => {
+                wind_zone.area = area;
+                wind_zone.frame = 0;
+                wind_zone.into()
+            }
+            None => Box::new(WindZone::new(area)).into(),
+        }
+    }
+
+    pub fn coin(&mut self, center: Point, kind: CoinKind) -> ObstacleKind {
+        match self.coins.pop() {
+            Some(mut coin) => {
+                coin.center = center;
+                coin.frame = 0;
+                coin.kind = kind;
+                coin.into()
+            }
+            None => Box::new(Coin::new(center, kind)).into(),
+        }
+    }
+
+    pub fn barrier(&mut self, image: Image, hitbox_inset: i16) -> ObstacleKind {
+        match self.barriers.pop() {
+            Some(mut barrier) => {
+                barrier.image = image;
+                barrier.hitbox_inset = hitbox_inset;
+                barrier.into()
+            }
+            None => Box::new(Barrier::with_hitbox_inset(image, hitbox_inset)).into(),
+        }
+    }
+
+    pub fn hanging_barrier(&mut self, image: Image) -> ObstacleKind {
+        match self.hanging_barriers.pop() {
+            Some(mut hanging_barrier) => {
+                hanging_barrier.barrier.image = image;
+                hanging_barrier.into()
+            }
+            None => Box::new(HangingBarrier::new(image)).into(),
+        }
+    }
+
+    pub fn platform(
+        &mut self,
+        sheet: Rc<SpriteSheet>,
+        position: Point,
+        sprite_names: &[&'static str],
+        bounding_boxes: &[Rect],
+    ) -> ObstacleKind {
+        match self.platforms.pop() {
+            Some(mut platform) => {
+                platform.sheet = sheet;
+                platform.reset(position, &[sprite_names], bounding_boxes, 1);
+                platform.into()
+            }
+            None => Box::new(Platform::new(sheet, position, sprite_names, bounding_boxes)).into(),
+        }
+    }
+
+    pub fn platform_from_width(
+        &mut self,
+        sheet: Rc<SpriteSheet>,
+        position: Point,
+        width: i16,
+    ) -> ObstacleKind {
+        match self.platforms.pop() {
+            Some(mut platform) => {
+                platform.sheet = sheet;
+                platform.reset_to_width(position, width);
+                platform.into()
+            }
+            None => Box::new(Platform::from_width(sheet, position, width)).into(),
+        }
+    }
+
+    pub fn animated_platform(
+        &mut self,
+        sheet: Rc<SpriteSheet>,
+        position: Point,
+        frames: &[&[&'static str]],
+        bounding_boxes: &[Rect],
+        frame_duration: u16,
+    ) -> ObstacleKind {
+        match self.platforms.pop() {
+            Some(mut platform) => {
+                platform.sheet = sheet;
+                platform.reset(position, frames, bounding_boxes, frame_duration);
+                platform.into()
+            }
+            None => Box::new(Platform::animated(
+                sheet,
+                position,
+                frames,
+                bounding_boxes,
+                frame_duration,
+            ))
+            .into(),
+        }
+    }
+
+    /// Builds the obstacle a saved game described. Barriers and the boss
+    /// are always stones.
+    fn restore(
+        &mut self,
+        descriptor: ObstacleDescriptor,
+        stone: &HtmlImageElement,
+        sheet: &Rc<SpriteSheet>,
+    ) -> ObstacleKind {
+        match descriptor {
+            ObstacleDescriptor::Barrier { position } => {
+                self.barrier(Image::new(stone.clone(), position), STONE_HITBOX_INSET)
+            }
+            ObstacleDescriptor::HangingBarrier { position } => {
+                self.hanging_barrier(Image::new(stone.clone(), position))
+            }
+            ObstacleDescriptor::Coin { center, kind } => self.coin(center, kind),
+            ObstacleDescriptor::PowerUpPickup { center, power_up } => {
+                Box::new(PowerUpPickup::new(stone.clone(), center, power_up)).into()
+            }
+            ObstacleDescriptor::WindZone { area } => self.wind_zone(area),
+            ObstacleDescriptor::Ground { area, footing } => {
+                Box::new(Ground { area, footing }).into()
+            }
+            ObstacleDescriptor::Boss {
+                area,
+                patrol,
+                speed,
+                health,
+                dying_frame,
+            } => Box::new(Boss {
+                image: stone.clone(),
+                area,
+                patrol,
+                speed,
+                health: cell::Cell::new(health),
+                pending_score: cell::Cell::new(0),
+                dying_frame,
+            })
+            .into(),
+            ObstacleDescriptor::Platform {
+                position,
+                frames,
+                frame,
+                frame_duration,
+                bounding_boxes,
+            } => {
+                let mut platform = self
+                    .platforms
+                    .pop()
+                    .unwrap_or_else(|| Box::new(Platform::empty(sheet.clone())));
+                platform.sheet = sheet.clone();
+                platform.position = position;
+                platform.frames = frames;
+                platform.frame = frame;
+                platform.frame_duration = frame_duration.max(1);
+                platform.bounding_boxes = bounding_boxes;
+                platform.into()
+            }
+        }
+    }
+
+    /// Moves every obstacle that is completely off screen into the pool,
+    /// keeping the order of the rest.
+    /// Hands an obstacle that is done with back, for the next segments to
+    /// reuse. Only grounds and the boss are left to be dropped.
+    fn reclaim(&mut self, obstacle: ObstacleKind) {
+        match obstacle {
+            ObstacleKind::Barrier(barrier) => self.barriers.push(barrier),
+            ObstacleKind::HangingBarrier(hanging_barrier) => {
+                self.hanging_barriers.push(hanging_barrier)
+            }
+            ObstacleKind::Platform(platform) => self.platforms.push(platform),
+            ObstacleKind::Coin(coin) => self.coins.push(coin),
+            ObstacleKind::WindZone(wind_zone) => self.wind_zones.push(wind_zone),
+            ObstacleKind::Ground(_) | ObstacleKind::Boss(_) | ObstacleKind::PowerUpPickup(_) => {}
+        }
+    }
+
+    /// Moves every obstacle that is completely off screen into the pool,
+    /// keeping the order of the rest.
+    fn reclaim_off_screen(&mut self, obstacles: &mut Vec<ObstacleKind>) {
+        let mut index = 0;
+        while index < obstacles.len() {
+            if obstacles[index].right() > 0 {
+                index += 1;
+            } else {
+                self.reclaim(obstacles.remove(index));
+            }
+        }
+    }
+
+    fn reclaim_all(&mut self, obstacles: &mut Vec<ObstacleKind>) {
+        obstacles
+            .drain(..)
+            .for_each(|obstacle| self.reclaim(obstacle));
+    }
+}
+
+mod red_hat_boy_states {
+    use crate::config::PhysicsConfig;
+    use crate::engine::{AudioBackend, WebAudioSound};
+    use crate::game::{Point, Sheet};
+    use std::rc::Rc;
+
+    // The obstacles are laid out for a 600x600 world, the render scale
+    // fits it to whatever screen the game runs on
+    const WORLD_WIDTH: i16 = 600;
+    const WORLD_HEIGHT: i16 = 600;
+    const FLOOR: i16 = 479;
+    // Where an upside down boy runs
+    const CEILING: i16 = 0;
+    const STARTING_POINT: i16 = -20;
+    pub const PLAYER_HEIGHT: i16 = WORLD_HEIGHT - FLOOR;
+
+    const IDLE_FRAME_NAME: &str = "Idle";
+    const RUN_FRAME_NAME: &str = "Run";
+    const SLIDING_FRAME_NAME: &str = "Slide";
+    const JUMPING_FRAME_NAME: &str = "Jump";
+    const FALLING_FRAME_NAME: &str = "Dead";
+
+    // Every cell of the sprite sheet is shown for this many updates
+    pub const FRAMES_PER_CELL: u8 = 3;
+
+    pub const DASH_FRAMES: u8 = 12;
+    pub const DASH_SPEED_MULTIPLIER: i16 = 3;
+    // Counted from the end of the dash
+    pub const DASH_COOLDOWN_FRAMES: u8 = 90;
+
+    // Cancelling a slide into a jump late only gets this much of a full jump
+    pub const LOW_JUMP_SPEED_PERCENT: i16 = 70;
+    // Jumping early in a slide springs this much higher than a plain jump
+    pub const SLIDE_JUMP_SPEED_PERCENT: i16 = 120;
+
+    // A running boy slows down this much every update nobody tells him to run
+    pub const DECELERATION_X: i16 = 1;
+
+    pub const ROCKET_SPEED: i16 = -8;
+    // Keeps a flying boy on screen
+    const ROCKET_CEILING: i16 = 60;
+
+    #[derive(Clone)]
+    pub struct RedHatBoyState<S> {
+        pub context: RedHatBoyContext,
+        _state: S,
+    }
+
+    impl<S> RedHatBoyState<S> {
+        pub fn context(&self) -> &RedHatBoyContext {
+            &self.context
+        }
+
+        pub fn context_mut(&mut self) -> &mut RedHatBoyContext {
+            &mut self.context
+        }
+
+        pub fn dash(self) -> Self {
+            RedHatBoyState {
+                context: self.context.dash(),
+                _state: self._state,
+            }
+        }
+
+        /// Off the bottom of the screen there is nothing left to animate,
+        /// the boy is out on the spot.
+        pub fn fall_off(self) -> RedHatBoyState<KnockedOut> {
+            RedHatBoyState {
+                context: self.context.stop(),
+                _state: KnockedOut {},
+            }
+        }
+    }
+
+    impl RedHatBoyState<Idle> {
+        // Transition from Idle to Running!
+        pub fn run(self) -> RedHatBoyState<Running> {
+            RedHatBoyState {
+                context: self.context.reset_frame().run_right(),
+                _state: Running {},
+            }
+        }
+
+        pub fn new(
+            sheet: &Sheet,
+            audio: Rc<dyn AudioBackend>,
+            jump_sound: WebAudioSound,
+            physics: PhysicsConfig,
+        ) -> Self {
+            RedHatBoyState {
+                context: RedHatBoyContext {
+                    frame: 0,
+                    position: Point {
+                        x: STARTING_POINT,
+                        y: FLOOR,
+                    },
+                    velocity: Point { x: 0, y: 0 },
+                    audio,
+                    jump_sound,
+                    physics,
+                    animation_frames: AnimationFrames::from_sheet(sheet),
+                    frame_advance_every: 1,
+                    ticks: 0,
+                    dash_frames: 0,
+                    dash_cooldown: 0,
+                    rocket: false,
+                    gravity_flipped: false,
+                    floor: Some(FLOOR),
+                    slide_on_landing: false,
+                    air_frames: 0,
+                    landed_air_frames: None,
+                    slide_jumped: false,
+                    run_commanded_this_tick: false,
+                },
+                _state: Idle {},
+            }
+        }
+
+        pub fn update(mut self) -> Self {
+            let idle_frames = self.context.animation_frames.idle;
+            self.context = self.context.update(idle_frames);
+            self
+        }
+
+        pub fn frame_name(&self) -> &'static str {
+            IDLE_FRAME_NAME
+        }
+    }
+
+    impl RedHatBoyState<Running> {
+        pub fn frame_name(&self) -> &'static str {
+            RUN_FRAME_NAME
+        }
+
+        pub fn update(mut self) -> Self {
+            let running_frames = self.context.animation_frames.running;
+            if !std::mem::take(&mut self.context.run_commanded_this_tick) {
+                self.context = self.context.decelerate_x();
+            }
+            self.context = self.context.update(running_frames);
+            self
+        }
+
+        /// Running on gets the boy back up to speed if he slowed down.
+        pub fn run(mut self) -> Self {
+            self.context = self.context.run_on();
+            self
+        }
+
+        pub fn slide(self) -> RedHatBoyState<Sliding> {
+            RedHatBoyState {
+                context: self.context.reset_frame(),
+                _state: Sliding {},
+            }
+        }
+
+        pub fn knock_out(self) -> RedHatBoyState<Falling> {
+            RedHatBoyState {
+                context: self.context.reset_frame().stop(),
+                _state: Falling {},
+            }
+        }
+
+        pub fn jump(self) -> RedHatBoyState<Jumping> {
+            RedHatBoyState {
+                context: self
+                    .context
+                    .set_vertical_velocity(self.context.physics.jump_speed)
+                    .reset_frame()
+                    .play_jump_sound(),
+                _state: Jumping {},
+            }
+        }
+
+        pub fn land_on(self, position: i16) -> Self {
+            RedHatBoyState {
+                context: self.context.set_on(position),
+                _state: Running {},
+            }
+        }
+    }
+
+    pub enum SlidingEndState {
+        Complete(RedHatBoyState<Running>),
+        Sliding(RedHatBoyState<Sliding>),
+    }
+
+    impl RedHatBoyState<Sliding> {
+        pub fn frame_name(&self) -> &'static str {
+            SLIDING_FRAME_NAME
+        }
+        pub fn update(mut self) -> SlidingEndState {
+            let sliding_frames = self.context.animation_frames.sliding;
+            self.context = self.context.update(sliding_frames);
+            if self.context.frame >= sliding_frames {
+                SlidingEndState::Complete(self.stand())
+            } else {
+                SlidingEndState::Sliding(self)
+            }
+        }
+        /// Holding slide keeps the boy down, the last cell repeats instead
+        /// of him standing up.
+        pub fn keep_sliding(mut self) -> Self {
+            let sliding_frames = self.context.animation_frames.sliding;
+            if self.context.frame + 1 >= sliding_frames {
+                self.context.frame = sliding_frames.saturating_sub(FRAMES_PER_CELL);
+            }
+            self
+        }
+
+        pub fn stand(self) -> RedHatBoyState<Running> {
+            RedHatBoyState {
+                context: self.context.reset_frame(),
+                _state: Running {},
+            }
+        }
+
+        /// Jumping in the first half of the slide is a slide jump, higher
+        /// than a plain one. Later on, the slide is cancelled into a low jump.
+        pub fn jump(mut self) -> RedHatBoyState<Jumping> {
+            let slide_jump = self.context.frame < self.context.animation_frames.sliding / 2;
+            let speed = if slide_jump {
+                self.context.slide_jumped = true;
+                self.context.physics.jump_speed * SLIDE_JUMP_SPEED_PERCENT / 100
+            } else {
+                self.context.physics.jump_speed * LOW_JUMP_SPEED_PERCENT / 100
+            };
+            RedHatBoyState {
+                context: self
+                    .context
+                    .set_vertical_velocity(speed)
+                    .reset_frame()
+                    .play_jump_sound(),
+                _state: Jumping {},
+            }
+        }
+        pub fn knock_out(self) -> RedHatBoyState<Falling> {
+            RedHatBoyState {
+                context: self.context.reset_frame().stop(),
+                _state: Falling {},
+            }
+        }
+        pub fn land_on(self, position: i16) -> Self {
+            RedHatBoyState {
+                context: self.context.set_on(position),
+                _state: Sliding {},
+            }
+        }
+    }
+
+    pub enum JumpingEndState {
+        Complete(RedHatBoyState<Running>),
+        /// Landed with a slide asked for on the way down.
+        Sliding(RedHatBoyState<Sliding>),
+        Jumping(RedHatBoyState<Jumping>),
+    }
+
+    impl RedHatBoyState<Jumping> {
+        pub fn update(mut self) -> JumpingEndState {
+            let jumping_frames = self.context.animation_frames.jumping;
+            self.context = self.context.update(jumping_frames);
+            // A rocket flight is no jump, and slowed down time no longer one
+            if !self.context.rocket && self.context.moves_this_update() {
+                self.context.air_frames = self.context.air_frames.saturating_add(1);
+            }
+            match self.context.footing() {
+                Some(footing) if self.context.on_floor() => self.land_on(footing),
+                _ => JumpingEndState::Jumping(self),
+            }
+        }
+
+        /// Slides the moment the boy is back on his feet.
+        pub fn slide_on_landing(mut self) -> Self {
+            self.context.slide_on_landing = true;
+            self
+        }
+
+        pub fn frame_name(&self) -> &'static str {
+            JUMPING_FRAME_NAME
+        }
+
+        pub fn land_on(mut self, position: i16) -> JumpingEndState {
+            let slide = std::mem::take(&mut self.context.slide_on_landing);
+            self.context.landed_air_frames = Some(std::mem::take(&mut self.context.air_frames));
+            let context = self.context.reset_frame().set_on(position);
+            if slide {
+                JumpingEndState::Sliding(RedHatBoyState {
+                    context,
+                    _state: Sliding {},
+                })
+            } else {
+                JumpingEndState::Complete(RedHatBoyState {
+                    context,
+                    _state: Running {},
+                })
+            }
+        }
+
+        pub fn knock_out(mut self) -> RedHatBoyState<Falling> {
+            self.context.slide_on_landing = false;
+            self.context.air_frames = 0;
+            RedHatBoyState {
+                context: self.context.reset_frame().stop(),
+                _state: Falling {},
+            }
+        }
+    }
+
+    pub enum FallingState {
+        Complete(RedHatBoyState<KnockedOut>),
+        Falling(RedHatBoyState<Falling>),
+    }
+
+    impl RedHatBoyState<Falling> {
+        pub(crate) fn update(mut self) -> FallingState {
+            let falling_frames = self.context.animation_frames.falling;
+            self.context = self.context.update(falling_frames);
+            if self.context.frame >= falling_frames {
+                FallingState::Complete(self.dead())
+            } else {
+                FallingState::Falling(self)
+            }
+        }
+        pub fn frame_name(&self) -> &'static str {
+            FALLING_FRAME_NAME
+        }
+        pub fn dead(self) -> RedHatBoyState<KnockedOut> {
+            RedHatBoyState {
+                context: self.context,
+                _state: KnockedOut {},
+            }
+        }
+    }
+
+    impl RedHatBoyState<KnockedOut> {
+        pub fn frame_name(&self) -> &'static str {
+            FALLING_FRAME_NAME
+        }
+
+        pub fn update(mut self) -> Self {
+            self.context = self.context.apply_velocity();
+            self
+        }
+
+        pub fn land_on(self, position: i16) -> Self {
+            RedHatBoyState {
+                context: self.context.set_on(position),
+                _state: KnockedOut {},
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct RedHatBoyContext {
+        pub frame: u8,
+        pub position: Point,
+        pub velocity: Point,
+        pub(crate) audio: Rc<dyn AudioBackend>,
+        pub(crate) jump_sound: WebAudioSound,
+        pub physics: PhysicsConfig,
+        pub animation_frames: AnimationFrames,
+        /// The boy only moves every this many updates, 1 unless time is slowed down.
+        pub frame_advance_every: u8,
+        ticks: u8,
+        /// Updates left in the current dash. A dashing boy can't be knocked out.
+        pub dash_frames: u8,
+        /// Updates until the boy can dash again.
+        pub dash_cooldown: u8,
+        /// Flies at `ROCKET_SPEED` instead of falling.
+        pub rocket: bool,
+        /// Falls up to the `CEILING` and runs along it.
+        pub gravity_flipped: bool,
+        /// The lowest the boy's position goes, the ground under him. Over a
+        /// pit there is none.
+        pub floor: Option<i16>,
+        /// A slide asked for mid-jump, it starts when the boy lands.
+        pub slide_on_landing: bool,
+        /// Frames the boy has been airborne in the current jump.
+        pub air_frames: u16,
+        /// How long the last jump stayed airborne, until someone takes it.
+        pub landed_air_frames: Option<u16>,
+        /// Set by a slide jump, until someone takes it.
+        pub slide_jumped: bool,
+        /// Whether anyone told the boy to run since his last update.
+        pub run_commanded_this_tick: bool,
+    }
+
+    /// The number of updates every animation lasts, looked up once from the
+    /// sprite sheet.
+    #[derive(Clone, Copy)]
+    pub struct AnimationFrames {
+        idle: u8,
+        running: u8,
+        sliding: u8,
+        jumping: u8,
+        falling: u8,
+    }
+
+    impl AnimationFrames {
+        fn from_sheet(sheet: &Sheet) -> Self {
+            let updates_for = |animation: &str| {
+                sheet
+                    .cell_ends(animation)
+                    .last()
+                    .map_or(0, |end| end.saturating_sub(1))
+            };
+            AnimationFrames {
+                idle: updates_for(IDLE_FRAME_NAME),
+                running: updates_for(RUN_FRAME_NAME),
+                sliding: updates_for(SLIDING_FRAME_NAME),
+                jumping: updates_for(JUMPING_FRAME_NAME),
+                falling: updates_for(FALLING_FRAME_NAME),
+            }
+        }
+    }
+
+    impl RedHatBoyContext {
+        /// Advances the animation by one update, `frame_count` being the
+        /// updates the animation of the state lasts. Updates are fixed steps
+        /// of time, see `Sheet::cell_ends`.
+        pub fn update(mut self, frame_count: u8) -> Self {
+            // Skipping whole updates slows the animation and the fall alike,
+            // halving the integer gravity would just round it away
+            self.ticks = self.ticks.wrapping_add(1);
+            if !self.moves_this_update() {
+                return self;
+            }
+
+            if self.frame < frame_count {
+                self.frame += 1;
+            } else {
+                self.frame = 0;
+            }
+
+            let context = self.tick_dash();
+            if context.rocket {
+                context.apply_rocket_velocity()
+            } else {
+                context.apply_velocity()
+            }
+        }
+
+        /// False on the updates slow motion skips.
+        fn moves_this_update(&self) -> bool {
+            self.ticks % self.frame_advance_every.max(1) == 0
+        }
+
+        pub fn dashing(&self) -> bool {
+            self.dash_frames > 0
+        }
+
+        /// Standing on the ground under him, not on a platform. Over a pit
+        /// he never is. Upside down, the ceiling is his ground.
+        pub fn on_floor(&self) -> bool {
+            if self.gravity_flipped {
+                self.position.y <= CEILING
+            } else {
+                self.floor.map_or(false, |floor| self.position.y >= floor)
+            }
+        }
+
+        /// What the boy's feet rest on when he is on the floor, for
+        /// `set_on`.
+        fn footing(&self) -> Option<i16> {
+            if self.gravity_flipped {
+                Some(CEILING)
+            } else {
+                self.floor.map(|floor| floor + PLAYER_HEIGHT)
+            }
+        }
+
+        fn dash(mut self) -> Self {
+            if self.dash_cooldown == 0 {
+                self.dash_frames = DASH_FRAMES;
+                self.dash_cooldown = DASH_FRAMES + DASH_COOLDOWN_FRAMES;
+            }
+            self
+        }
+
+        fn tick_dash(mut self) -> Self {
+            self.dash_frames = self.dash_frames.saturating_sub(1);
+            self.dash_cooldown = self.dash_cooldown.saturating_sub(1);
+            self
+        }
+
+        fn play_jump_sound(self) -> Self {
+            if let Err(err) = self.audio.play_sound_at(
+                &self.jump_sound,
+                self.position.x.into(),
+                WORLD_WIDTH.into(),
+            ) {
+                log!("Error playing jump sound {:#?}", err);
+            }
+            self
+        }
+
+        fn apply_velocity(mut self) -> Self {
+            self.position.y += self.velocity.y;
+            if self.gravity_flipped {
+                self.velocity.y -= self.physics.gravity;
+                self.velocity.y = self.velocity.y.max(-self.physics.max_velocity);
+                self.position.y = self.position.y.max(CEILING);
+            } else {
+                self.velocity.y += self.physics.gravity;
+                self.velocity.y = self.velocity.y.min(self.physics.max_velocity);
+                if let Some(floor) = self.floor {
+                    self.position.y = self.position.y.min(floor);
+                }
+            }
+            self
+        }
+
+        /// Gravity has no say, the boy climbs at a steady speed up to the
+        /// ceiling and stays there.
+        pub fn apply_rocket_velocity(mut self) -> Self {
+            self.velocity.y = ROCKET_SPEED;
+            self.position.y = (self.position.y + self.velocity.y).max(ROCKET_CEILING);
+            self
+        }
+
+        fn reset_frame(mut self) -> Self {
+            self.frame = 0;
+            self
+        }
+
+        fn run_right(mut self) -> Self {
+            self.velocity.x += self.physics.running_speed;
+            self.run_commanded_this_tick = true;
+            self
+        }
+
+        fn run_on(mut self) -> Self {
+            self.velocity.x = self.velocity.x.max(self.physics.running_speed);
+            self.run_commanded_this_tick = true;
+            self
+        }
+
+        pub fn decelerate_x(mut self) -> Self {
+            self.velocity.x = (self.velocity.x - DECELERATION_X).max(0);
+            self
+        }
+
+        /// Upside down, a jump goes down.
+        fn set_vertical_velocity(mut self, speed: i16) -> Self {
+            self.velocity.y = if self.gravity_flipped { -speed } else { speed };
+            self
+        }
+
+        fn stop(mut self) -> Self {
+            self.velocity.x = 0;
+            self
+        }
+
+        /// Puts the feet flush on `position`, however far the last step
+        /// sank them in, and stops the fall there so he doesn't bob.
+        /// Upside down his feet are at the top.
+        fn set_on(mut self, position: i16) -> Self {
+            self.position.y = if self.gravity_flipped {
+                position
+            } else {
+                position - PLAYER_HEIGHT
+            };
+            self.velocity.y = 0;
+            self
+        }
+    }
+
+    #[derive(Copy, Clone)]
+    pub struct Idle;
+
+    #[derive(Copy, Clone)]
+    pub struct Running;
+
+    #[derive(Copy, Clone)]
+    pub struct Sliding;
+
+    #[derive(Copy, Clone)]
+    pub struct Jumping;
+
+    #[derive(Copy, Clone)]
+    pub struct Falling;
+
+    #[derive(Copy, Clone)]
+    pub struct KnockedOut;
+}
+
+/// Which background themes to draw. Right after a milestone the incoming
+/// theme fades in over the outgoing one.
+struct ThemeBlend {
+    outgoing: usize,
+    incoming: usize,
+    alpha: f64,
 }
 
-impl RedHatBoyStateMachine {
-    fn transition(self, event: Event) -> Self {
-        match (self.clone(), event) {
-            (RedHatBoyStateMachine::Idle(state), Event::Run) => state.run().into(),
-            (RedHatBoyStateMachine::Running(state), Event::Slide) => state.slide().into(),
-            (RedHatBoyStateMachine::Running(state), Event::Jump) => state.jump().into(),
-            (RedHatBoyStateMachine::Running(state), Event::KnockOut) => state.knock_out().into(),
-            (RedHatBoyStateMachine::Running(state), Event::Land(position)) => {
-                state.land_on(position).into()
+fn theme_blend(distance: u32, theme_distance: u32, theme_count: usize) -> ThemeBlend {
+    let theme_distance = theme_distance.max(1);
+    let milestone = (distance / theme_distance) as usize;
+    let incoming = milestone % theme_count;
+    let fading = milestone > 0 && theme_count > 1;
+    let alpha = if fading {
+        (f64::from(distance % theme_distance) / f64::from(THEME_FADE_DISTANCE)).min(1.0)
+    } else {
+        1.0
+    };
+    ThemeBlend {
+        outgoing: (milestone + theme_count - 1) % theme_count,
+        incoming,
+        alpha,
+    }
+}
+
+async fn load_themes(sources: &[String]) -> Result<Vec<HtmlImageElement>> {
+    if sources.is_empty() {
+        return Err(anyhow!("At least one background theme is needed"));
+    }
+    let mut themes = Vec::with_capacity(sources.len());
+    for source in sources {
+        themes.push(engine::load_image(source).await?);
+    }
+    Ok(themes)
+}
+
+/// Generates `segments` segments from `seed` one after the other, throwing
+/// each away before the next. With `recycle` the obstacles go back into the
+/// pool, else they are dropped. `tests/obstacle_allocations.rs` counts the
+/// allocations this takes.
+#[cfg(feature = "noop-audio")]
+pub fn generate_throwaway_segments(seed: u64, segments: usize, recycle: bool) {
+    let mut walk = Walk::headless(
+        Sheet {
+            frames: HashMap::new(),
+        },
+        seed,
+    );
+    (0..segments).for_each(|_segment| {
+        // Start every segment at the same place so the timeline can't overflow
+        walk.timeline = 0;
+        walk.generate_next_segment();
+        if recycle {
+            walk.obstacle_pool.reclaim_all(&mut walk.obstacles);
+        } else {
+            walk.obstacles.clear();
+        }
+    });
+}
+
+/// The weight of each of `SEGMENTS`, leaving out the ones easier than
+/// `difficulty_floor`. The floor stops rising once it would leave fewer than
+/// `MIN_SEGMENT_VARIETY` segments, so the run never gets monotonous.
+fn segment_weights(segments: &HashMap<String, SegmentEntry>, difficulty_floor: u8) -> [u32; 8] {
+    let entry = |segment: &str| segments.get(segment).filter(|entry| entry.weight > 0);
+    let varied_enough = |floor: &u8| {
+        SEGMENTS
+            .iter()
+            .filter_map(|segment| entry(segment))
+            .filter(|entry| entry.difficulty >= *floor)
+            .count()
+            >= MIN_SEGMENT_VARIETY
+    };
+    let floor = (0..=difficulty_floor)
+        .rev()
+        .find(varied_enough)
+        .unwrap_or(0);
+    SEGMENTS.map(|segment| {
+        entry(segment)
+            .filter(|entry| entry.difficulty >= floor)
+            .map_or(0, |entry| entry.weight)
+    })
+}
+
+/// The segments after a start at `distance` begin `grace_distance` ahead,
+/// and the boy can't be hit until he got there.
+fn grace_ends_at(distance: u32, grace_distance: i16) -> Option<u32> {
+    Some(distance.saturating_add(grace_distance.unsigned_abs().into()))
+}
+
+fn air_time_bonus(air_frames: u16) -> u32 {
+    (air_frames.saturating_sub(AIR_TIME_THRESHOLD_FRAMES) / AIR_FRAMES_PER_POINT).into()
+}
+
+/// The widest gap any segment asks for, narrowed by `SAFE_GAP_STEP` for every
+/// level of `difficulty_floor`. It never gets narrower than the boy needs to
+/// land a jump before the next segment starts.
+fn max_safe_gap(config: &GameConfig, difficulty_floor: u8) -> i16 {
+    let narrowest = config.physics.minimum_safe_buffer();
+    let widest = config
+        .segments
+        .values()
+        .map(|entry| entry.max_buffer)
+        .max()
+        .unwrap_or(narrowest);
+    let narrowed = i16::from(difficulty_floor).saturating_mul(SAFE_GAP_STEP);
+    widest.saturating_sub(narrowed).max(narrowest)
+}
+
+fn in_view(obstacle: &dyn Obstacle, view_width: i16) -> bool {
+    obstacle.right() > 0 && obstacle.draw_area().x() < view_width
+}
+
+/// `Some(true)` while `on` is pressed and `Some(false)` while `off` is, the
+/// latter wins when both are.
+fn toggle_pressed(keystate: &KeyState, on: &str, off: &str) -> Option<bool> {
+    if keystate.is_pressed(off) {
+        Some(false)
+    } else if keystate.is_pressed(on) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// The keys a mouse movement stands for. Moving right runs, up jumps and down
+/// slides, so the mouse can do everything the keyboard can.
+fn mouse_presses(movement: Point, key_bindings: &KeyBindings) -> Vec<&str> {
+    let mut presses = Vec::new();
+    if movement.x > MOUSE_DEADZONE {
+        presses.push(key_bindings.run.as_str());
+    }
+    if movement.y < -MOUSE_DEADZONE {
+        presses.push(key_bindings.jump.as_str());
+    } else if movement.y > MOUSE_DEADZONE {
+        presses.push(key_bindings.slide.as_str());
+    }
+    presses
+}
+
+fn click_receiver(id: &str) -> Result<UnboundedReceiver<()>> {
+    Ok(engine::add_click_handler(
+        browser::expect_html_element_by_id(id)?,
+    ))
+}
+
+fn draw_continue_saved_ui() -> Result<UnboundedReceiver<()>> {
+    let ui = UiBuilder::new()
+        .button("Continue", CONTINUE_SAVED_BUTTON_ID)
+        .build()?;
+    browser::draw_ui(&ui)?;
+    click_receiver(CONTINUE_SAVED_BUTTON_ID)
+}
+
+/// What `draw_game_over_ui` puts on the page for `GameOver`.
+struct GameOverButtons {
+    new_game_button: HtmlElement,
+    new_game_event: UnboundedReceiver<()>,
+    continue_event: Option<UnboundedReceiver<()>>,
+    submit_score_event: Option<UnboundedReceiver<()>>,
+}
+
+fn draw_game_over_ui(can_continue: bool, has_leaderboard: bool) -> Result<GameOverButtons> {
+    browser::draw_ui(&game_over_ui(can_continue, has_leaderboard).build()?)?;
+    let new_game_button = browser::expect_html_element_by_id(NEW_GAME_BUTTON_ID)?;
+    Ok(GameOverButtons {
+        new_game_event: engine::add_click_handler(new_game_button.clone()),
+        new_game_button,
+        continue_event: can_continue
+            .then(|| click_receiver(CONTINUE_BUTTON_ID))
+            .transpose()?,
+        submit_score_event: has_leaderboard
+            .then(|| click_receiver(SUBMIT_SCORE_BUTTON_ID))
+            .transpose()?,
+    })
+}
+
+/// The buttons to go on with after a run, looked up by their ids once the
+/// overlay is drawn.
+fn game_over_ui(can_continue: bool, has_leaderboard: bool) -> UiBuilder {
+    let mut ui = UiBuilder::new()
+        .with_css_class("game-over")
+        .heading("Game Over")
+        .button("New Game", NEW_GAME_BUTTON_ID);
+    if can_continue {
+        ui = ui.button(
+            &format!("Continue (costs {} coins)", CONTINUE_COST),
+            CONTINUE_BUTTON_ID,
+        );
+    }
+    if has_leaderboard {
+        ui = ui
+            .text_input("Your name", LEADERBOARD_NAME_ID)
+            .button("Submit score", SUBMIT_SCORE_BUTTON_ID);
+    }
+    ui
+}
+
+/// Slide in the bottom left corner and jump in the bottom right one, where
+/// the thumbs are. Run sits in between, it is only needed to start a run.
+fn virtual_buttons(screen: &Screen, key_bindings: &KeyBindings) -> Vec<VirtualButton> {
+    let top = screen.height - VIRTUAL_BUTTON_HEIGHT - VIRTUAL_BUTTON_MARGIN;
+    let button = |x: i16, code: &str, label: &str| VirtualButton {
+        area: Rect::new_from_x_y(x, top, VIRTUAL_BUTTON_WIDTH, VIRTUAL_BUTTON_HEIGHT),
+        code: code.into(),
+        label: label.into(),
+    };
+    vec![
+        button(VIRTUAL_BUTTON_MARGIN, &key_bindings.slide, "SLIDE"),
+        button(
+            (screen.width - VIRTUAL_BUTTON_WIDTH) / 2,
+            &key_bindings.run,
+            "RUN",
+        ),
+        button(
+            screen.width - VIRTUAL_BUTTON_WIDTH - VIRTUAL_BUTTON_MARGIN,
+            &key_bindings.jump,
+            "JUMP",
+        ),
+    ]
+}
+
+pub const HIGH_PLATFORM: i16 = 375;
+pub const LOW_PLATFORM: i16 = 420;
+pub const FIRST_PLATFORM: i16 = 370;
+
+#[async_trait(? Send)]
+impl Game for WalkTheDog {
+    async fn initialize(&self) -> Result<Box<dyn Game>> {
+        match self.machine {
+            None => {
+                let mut config = GameConfig::load("config.json").await?;
+                let settings = config::load_settings();
+                if let Some(settings) = &settings {
+                    settings.apply_to(&mut config);
+                }
+                if config.control_scheme.is_none() {
+                    config.control_scheme = Some(if browser::is_touch_device()? {
+                        ControlScheme::Touch
+                    } else {
+                        ControlScheme::Keyboard
+                    });
+                }
+                let base_config = config.clone();
+                let config = base_config.preset.apply(&base_config);
+                let json = browser::fetch_json("rhb.json").await?;
+                let audio = WebAudioEngine::new()?;
+                let sound = audio.load_sound("SFX_Jump_23.mp3").await?;
+                let background_music = audio.load_sound("background_song.mp3").await?;
+                audio.play_looping_sound_fade_in(&background_music, MUSIC_FADE_IN_MS)?;
+                let rhb = RedHatBoy::new(
+                    json.into_serde()?,
+                    engine::load_image("rhb").await?,
+                    Rc::new(audio),
+                    sound,
+                    config.physics,
+                );
+                let themes = load_themes(&config.background_themes).await?;
+                let background = themes[0].clone();
+                let stone = engine::load_image("Stone").await?;
+                let tiles = browser::fetch_json("tiles.json").await?;
+                let sprite_sheet = Rc::new(SpriteSheet::new(
+                    tiles.into_serde::<Sheet>()?,
+                    engine::load_image("tiles").await?,
+                ));
+                let background_width = background.width();
+                let mut obstacle_pool = ObstaclePool::default();
+                let starting_obstacles = stone_and_platform(
+                    stone.clone(),
+                    sprite_sheet.clone(),
+                    config.grace_distance,
+                    &mut obstacle_pool,
+                );
+                let timeline = rightmost(&starting_obstacles);
+                let screen = Screen::new()?;
+                let walk = Walk {
+                    previous_boy_position: rhb.position(),
+                    current_scroll_velocity: 0,
+                    boy: rhb,
+                    backgrounds: [
+                        Image::new(background.clone(), Point { x: 0, y: 0 }),
+                        Image::new(
+                            background,
+                            Point {
+                                x: background_width as i16,
+                                y: 0,
+                            },
+                        ),
+                    ],
+                    themes,
+                    distance: 0,
+                    elapsed_seconds: 0.0,
+                    difficulty_floor: 0,
+                    max_safe_gap: max_safe_gap(&config, 0),
+                    obstacle_sheet: sprite_sheet,
+                    obstacles: starting_obstacles,
+                    obstacle_pool,
+                    stone: stone.clone(),
+                    music: background_music,
+                    timeline,
+                    difficulty: Difficulty {
+                        hard_mode: settings.map_or(false, |settings| settings.hard_mode),
+                    },
+                    rng: StdRng::from_entropy(),
+                    coins: 0,
+                    bonus_score: 0,
+                    boss_encountered: false,
+                    continue_used: false,
+                    tally: RunTally::default(),
+                    milestones: 0,
+                    milestone_banner_frames: 0,
+                    air_bonus: 0,
+                    air_popup_frames: 0,
+                    rests: 0,
+                    rest_gap_countdown: None,
+                    safe_zone_banner_frames: 0,
+                    rain: RainSystem::new(&screen),
+                    grace_ends_at: grace_ends_at(0, config.grace_distance),
+                    obstacle_cap_reached: false,
+                    lives: config.starting_lives,
+                    config,
+                    base_config,
+                    screen,
+                    active_power_up: None,
+                };
+                let machine = match load_saved_walk().await {
+                    Some(saved_walk) => WalkTheDogStateMachine::with_saved_walk(walk, saved_walk),
+                    None => WalkTheDogStateMachine::new(walk),
+                };
+                // Built for every scheme, the player can switch to touch later
+                let walk = machine.walk();
+                let virtual_buttons = virtual_buttons(&walk.screen, &walk.config.key_bindings);
+                self.status.publish(&machine);
+                Ok(Box::new(WalkTheDog {
+                    machine: Some(machine),
+                    status: self.status.clone(),
+                    last_frame: RefCell::new(None),
+                    virtual_buttons,
+                }))
             }
-            (RedHatBoyStateMachine::Jumping(state), Event::KnockOut) => state.knock_out().into(),
-            (RedHatBoyStateMachine::Jumping(state), Event::Land(position)) => {
-                state.land_on(position).into()
+            Some(_) => Err(anyhow!("Error: Game is already initialized!")),
+        }
+    }
+
+    fn update(&mut self, keystate: &mut engine::KeyState, delta: f64) {
+        if let Some(mut machine) = self.machine.take() {
+            if let Some(physics) = self.status.physics_request.take() {
+                machine.walk_mut().set_physics(physics);
             }
-            (RedHatBoyStateMachine::Running(state), Event::Land(position)) => {
-                state.land_on(position).into()
+            if let Some(preset) = self.status.preset_request.take() {
+                machine.walk_mut().select_preset(preset);
             }
-            (RedHatBoyStateMachine::Sliding(state), Event::KnockOut) => state.knock_out().into(),
-            (RedHatBoyStateMachine::Sliding(state), Event::Land(position)) => {
-                state.land_on(position).into()
+            let machine = if self.status.reset_requested.replace(false) {
+                machine.reset()
+            } else if machine.walk().control_scheme() == ControlScheme::Mouse {
+                let key_bindings = &machine.walk().config.key_bindings;
+                mouse_presses(keystate.mouse_movement(), key_bindings)
+                    .iter()
+                    .for_each(|code| keystate.inject_press(code));
+                machine.update(keystate, delta)
+            } else {
+                machine.update(keystate, delta)
+            };
+            self.status.publish(&machine);
+            self.machine.replace(machine);
+        }
+
+        assert!(self.machine.is_some())
+    }
+
+    fn debug_mode(&self) -> bool {
+        self.machine
+            .as_ref()
+            .map_or(false, |machine| machine.walk().config.debug_mode)
+    }
+
+    fn image_smoothing(&self) -> bool {
+        self.machine
+            .as_ref()
+            .map_or(false, |machine| machine.walk().config.image_smoothing)
+    }
+
+    fn target_fps(&self) -> u32 {
+        self.machine
+            .as_ref()
+            .map_or(engine::DEFAULT_TARGET_FPS, |machine| {
+                machine.walk().config.target_fps
+            })
+    }
+
+    fn virtual_buttons(&self) -> &[VirtualButton] {
+        self.touch_buttons()
+    }
+
+    fn pause(&mut self) {
+        // A finished game is already standing still, leave it alone
+        if let Some(machine) = self
+            .machine
+            .as_ref()
+            .filter(|machine| !machine.is_game_over())
+        {
+            let audio = machine.walk().boy.audio();
+            if let Err(err) = audio.mute().and_then(|_unit| audio.suspend()) {
+                log!("Error pausing audio {:#?}", err);
             }
-            (RedHatBoyStateMachine::KnockedOut(state), Event::Land(position)) => {
-                state.land_on(position).into()
+        }
+    }
+
+    fn resume(&mut self) {
+        if let Some(machine) = self
+            .machine
+            .as_ref()
+            .filter(|machine| !machine.is_game_over())
+        {
+            let audio = machine.walk().boy.audio();
+            if let Err(err) = audio.resume().and_then(|_unit| audio.unmute()) {
+                log!("Error resuming audio {:#?}", err);
             }
+        }
+    }
 
-            (RedHatBoyStateMachine::Idle(state), Event::Update) => state.update().into(),
-            (RedHatBoyStateMachine::Running(state), Event::Update) => state.update().into(),
-            (RedHatBoyStateMachine::Sliding(state), Event::Update) => state.update().into(),
-            (RedHatBoyStateMachine::Jumping(state), Event::Update) => state.update().into(),
-            (RedHatBoyStateMachine::Falling(state), Event::Update) => state.update().into(),
-            (RedHatBoyStateMachine::KnockedOut(state), Event::Update) => state.update().into(),
-            _ => self,
+    fn save(&self) {
+        // Leaving the page mid-run saves it, it can be continued on the next visit
+        if let Some(machine) = self.machine.as_ref().filter(|machine| machine.is_walking()) {
+            save_walk(machine.walk());
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer, interpolation: f32) {
+        if let Some(machine) = &self.machine {
+            let redraw = self.redraw(machine);
+            match &redraw {
+                Redraw::Full => {
+                    renderer.clear_with_color(&machine.walk().screen.game_rect(), CLEAR_COLOR);
+                    renderer.draw_gradient_background(SKY_TOP_COLOR, SKY_BOTTOM_COLOR);
+                    machine.draw(renderer, interpolation, &redraw);
+                    self.draw_virtual_buttons(renderer, &redraw);
+                }
+                Redraw::Regions(regions) if regions.is_empty() => {}
+                Redraw::Regions(regions) => {
+                    let walk = machine.walk();
+                    let screen_regions: Vec<Rect> = regions
+                        .iter()
+                        .map(|region| walk.world_to_screen(region))
+                        .collect();
+                    renderer.clip_to(&screen_regions);
+                    screen_regions
+                        .iter()
+                        .for_each(|region| renderer.clear_with_color(region, CLEAR_COLOR));
+                    renderer.draw_gradient_background(SKY_TOP_COLOR, SKY_BOTTOM_COLOR);
+                    machine.draw(renderer, interpolation, &redraw);
+                    self.draw_virtual_buttons(renderer, &redraw);
+                    renderer.end_clip();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::red_hat_boy_states::{
+        DASH_COOLDOWN_FRAMES, DASH_FRAMES, DECELERATION_X, LOW_JUMP_SPEED_PERCENT, ROCKET_SPEED,
+        SLIDE_JUMP_SPEED_PERCENT,
+    };
+    use super::*;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const SEED: u64 = 42;
+    const FRAMES: usize = 600;
+
+    fn test_sheet() -> Sheet {
+        let frames = ["Idle", "Run", "Slide", "Jump", "Dead"]
+            .iter()
+            .flat_map(|name| (1..=12).map(move |index| format!("{} ({}).png", name, index)))
+            .map(|frame_name| {
+                (
+                    frame_name,
+                    Cell {
+                        frame: SheetRect {
+                            x: 0,
+                            y: 0,
+                            w: 100,
+                            h: 120,
+                        },
+                        sprite_source_size: SheetRect {
+                            x: 0,
+                            y: 0,
+                            w: 100,
+                            h: 120,
+                        },
+                        duration: None,
+                    },
+                )
+            })
+            .collect();
+        Sheet { frames }
+    }
+
+    fn test_walk(seed: u64) -> Walk {
+        Walk::headless(test_sheet(), seed)
+    }
+
+    /// Walking right after the countdown, with no best run to beat.
+    fn fresh_walking() -> Walking {
+        Walking {
+            go_frames: 0,
+            knocked_out_frames: None,
+            previous_best: None,
         }
     }
 
-    fn frame_name(&self) -> &str {
-        match self {
-            RedHatBoyStateMachine::Idle(state) => state.frame_name(),
-            RedHatBoyStateMachine::Running(state) => state.frame_name(),
-            RedHatBoyStateMachine::Sliding(state) => state.frame_name(),
-            RedHatBoyStateMachine::Jumping(state) => state.frame_name(),
-            RedHatBoyStateMachine::Falling(state) => state.frame_name(),
-            RedHatBoyStateMachine::KnockedOut(state) => state.frame_name(),
-        }
+    fn frame_hashes(mut walk: Walk, frames: usize) -> Vec<u64> {
+        let keystate = KeyState::new();
+        walk.boy.run_right();
+        (0..frames)
+            .map(|_frame| {
+                walk.update(&keystate);
+                walk.frame_hash()
+            })
+            .collect()
+    }
+
+    #[wasm_bindgen_test]
+    fn ghost_disables_collisions_until_it_runs_out() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.activate_power_up(PowerUp::Ghost);
+        assert!(!walk.collision_enabled());
+
+        (1..POWER_UP_TICKS).for_each(|_tick| walk.update(&keystate));
+        assert!(!walk.collision_enabled());
+
+        walk.update(&keystate);
+        assert!(walk.collision_enabled());
+    }
+
+    #[wasm_bindgen_test]
+    fn ghost_flickers_only_in_its_last_second() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.activate_power_up(PowerUp::Ghost);
+        (0..POWER_UP_TICKS - POWER_UP_FLICKER_TICKS).for_each(|_tick| {
+            assert_eq!(walk.boy_alpha(), GHOST_ALPHA);
+            walk.update(&keystate);
+        });
+
+        let alphas: Vec<f64> = (0..POWER_UP_FLICKER_TICKS - 1)
+            .map(|_tick| {
+                walk.update(&keystate);
+                walk.boy_alpha()
+            })
+            .collect();
+        assert!(alphas.contains(&GHOST_ALPHA));
+        assert!(alphas.contains(&1.0));
+    }
+
+    fn expected_frame_name(boy: &RedHatBoy) -> String {
+        format!(
+            "{} ({}).png",
+            boy.state_machine.frame_name(),
+            (boy.state_machine.context().frame / FRAMES_PER_CELL) + 1
+        )
+    }
+
+    #[wasm_bindgen_test]
+    fn cached_frame_follows_frame_advances() {
+        let mut boy = test_walk(SEED).boy;
+        boy.run_right();
+        (0..FRAMES).for_each(|_frame| {
+            boy.update();
+            assert_eq!(boy.frame_name(), expected_frame_name(&boy));
+            assert!(boy.current_sprite().is_some());
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn cell_durations_drive_the_animation() {
+        let mut sheet = test_sheet();
+        // 50ms are 3 updates, 100ms are 6
+        sheet.frames.get_mut("Run (1).png").unwrap().duration = Some(50);
+        sheet.frames.get_mut("Run (2).png").unwrap().duration = Some(100);
+        let mut ends = sheet.cell_ends("Run");
+        ends.truncate(3);
+        assert_eq!(ends, vec![3, 9, 12]);
+
+        let walk = test_walk(SEED);
+        let mut boy = RedHatBoy::new(
+            sheet,
+            walk.boy.image.clone(),
+            Rc::new(engine::Audio::new_noop()),
+            walk.boy.state_machine.context().jump_sound.clone(),
+            PhysicsConfig::default(),
+        );
+        boy.run_right();
+        let cells: Vec<String> = (0..12)
+            .map(|_frame| {
+                boy.update();
+                boy.frame_name().to_string()
+            })
+            .collect();
+        assert_eq!(cells[1], "Run (1).png");
+        assert_eq!(cells[2], "Run (2).png");
+        assert_eq!(cells[7], "Run (2).png");
+        assert_eq!(cells[8], "Run (3).png");
+    }
+
+    #[wasm_bindgen_test]
+    fn cached_frame_follows_state_transitions() {
+        let mut boy = test_walk(SEED).boy;
+        assert_eq!(boy.frame_name(), "Idle (1).png");
+
+        boy.run_right();
+        boy.update();
+        assert_eq!(boy.frame_name(), expected_frame_name(&boy));
+        (0..FRAMES_PER_CELL).for_each(|_frame| boy.update());
+
+        boy.slide();
+        assert_eq!(boy.frame_name(), "Slide (1).png");
+
+        boy.knock_out();
+        assert_eq!(boy.frame_name(), "Dead (1).png");
+        assert!(boy.current_sprite().is_some());
+    }
+
+    #[wasm_bindgen_test]
+    fn points_add_subtract_and_scale() {
+        let a = Point { x: 10, y: -4 };
+        let b = Point { x: 3, y: 6 };
+        assert!(a + b == Point { x: 13, y: 2 });
+        assert!(a - b == Point { x: 7, y: -10 });
+        assert!(a.scale(0.25) == Point { x: 3, y: -1 });
+        assert!(b.scale(-2.0) == Point { x: -6, y: -12 });
+    }
+
+    #[wasm_bindgen_test]
+    fn point_arithmetic_saturates() {
+        let far = Point {
+            x: i16::MAX - 1,
+            y: i16::MIN + 1,
+        };
+        let step = Point { x: 5, y: 5 };
+        let sum = far + step;
+        let difference = far - step;
+        let scaled = far.scale(2.0);
+        assert_eq!((sum.x, sum.y), (i16::MAX, i16::MIN + 6));
+        assert_eq!((difference.x, difference.y), (i16::MAX - 6, i16::MIN));
+        assert_eq!((scaled.x, scaled.y), (i16::MAX, i16::MIN));
+    }
+
+    #[wasm_bindgen_test]
+    fn magnet_pulls_coins_within_its_radius() {
+        let mut walk = test_walk(SEED);
+        let boy_center = walk.boy.bounding_box().center();
+        let near = Point {
+            x: boy_center.x + MAGNET_RADIUS - 10,
+            y: boy_center.y,
+        };
+        let far = Point {
+            x: boy_center.x + MAGNET_RADIUS + 10,
+            y: boy_center.y,
+        };
+        walk.obstacles = vec![
+            Box::new(Coin::new(near, CoinKind::Bronze)).into(),
+            Box::new(Coin::new(far, CoinKind::Bronze)).into(),
+        ];
+        walk.activate_power_up(PowerUp::Magnet);
+
+        walk.attract_coins();
+
+        let centers: Vec<i16> = walk
+            .obstacles_of_type::<Coin>()
+            .map(|coin| coin.center().x)
+            .collect();
+        assert_eq!(centers, vec![near.x - MAGNET_SPEED as i16, far.x]);
+    }
+
+    #[wasm_bindgen_test]
+    fn obstacles_of_a_type_leave_out_the_others() {
+        let mut walk = test_walk(SEED);
+        walk.obstacles = vec![
+            Box::new(Ground::pit(0, 100)).into(),
+            Box::new(Coin::new(Point { x: 50, y: 300 }, CoinKind::Gold)).into(),
+            Box::new(WindZone::new(Rect::new_from_x_y(0, 0, 100, 100))).into(),
+        ];
+        let coins: Vec<CoinKind> = walk
+            .obstacles_of_type::<Coin>()
+            .map(|coin| coin.kind())
+            .collect();
+        assert!(matches!(coins[..], [CoinKind::Gold]));
+        assert_eq!(walk.obstacles_of_type::<Ground>().count(), 1);
+        assert_eq!(walk.obstacles_of_type::<Barrier>().count(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn touching_a_coin_collects_it() {
+        let mut walk = test_walk(SEED);
+        walk.obstacles = vec![Box::new(Coin::new(
+            walk.boy.bounding_box().center(),
+            CoinKind::Bronze,
+        ))
+        .into()];
+
+        walk.collect_coins();
+
+        assert!(walk.obstacles.is_empty());
+        assert_eq!(walk.coins, walk.config.coin_value);
+    }
+
+    #[wasm_bindgen_test]
+    fn touching_a_power_up_pickup_sets_it_off() {
+        let (stone, _sheet) = segment_images();
+        let mut walk = test_walk(SEED);
+        let center = walk.boy.bounding_box().center();
+        walk.obstacles = vec![Box::new(PowerUpPickup::new(stone, center, PowerUp::Magnet)).into()];
+
+        walk.collect_power_ups();
+
+        assert!(walk.obstacles.is_empty());
+        assert!(walk.power_up_active(PowerUp::Magnet));
+    }
+
+    #[wasm_bindgen_test]
+    fn power_up_pickups_out_of_reach_stay_put() {
+        let (stone, _sheet) = segment_images();
+        let mut walk = test_walk(SEED);
+        let center = Point {
+            x: walk.boy.bounding_box().right() + 100,
+            y: POWER_UP_PICKUP_Y,
+        };
+        walk.obstacles = vec![Box::new(PowerUpPickup::new(stone, center, PowerUp::Rocket)).into()];
+
+        walk.collect_power_ups();
+
+        assert_eq!(walk.obstacles_of_type::<PowerUpPickup>().count(), 1);
+        assert!(walk.active_power_up.is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn power_up_pickups_turn_up_between_segments() {
+        let mut walk = test_walk(SEED);
+        let mut pickups = 0;
+        (0..100).for_each(|_segment| {
+            walk.obstacle_pool.reclaim_all(&mut walk.obstacles);
+            // Start every segment at the same place so the timeline can't overflow
+            walk.timeline = 0;
+            walk.generate_next_segment();
+            pickups += walk.obstacles_of_type::<PowerUpPickup>().count();
+        });
+        assert!(pickups > 0);
     }
-    fn context(&self) -> &RedHatBoyContext {
-        match self {
-            RedHatBoyStateMachine::Idle(state) => &state.context(),
-            RedHatBoyStateMachine::Running(state) => &state.context(),
-            RedHatBoyStateMachine::Sliding(state) => &state.context(),
-            RedHatBoyStateMachine::Jumping(state) => &state.context(),
-            RedHatBoyStateMachine::Falling(state) => &state.context(),
-            RedHatBoyStateMachine::KnockedOut(state) => &state.context(),
-        }
+
+    #[wasm_bindgen_test]
+    fn coins_are_worth_their_kind_and_counted_by_it() {
+        let mut walk = test_walk(SEED);
+        let center = walk.boy.bounding_box().center();
+        walk.obstacles = vec![
+            walk.obstacle_pool.coin(center, CoinKind::Gold),
+            walk.obstacle_pool.coin(center, CoinKind::Silver),
+            walk.obstacle_pool.coin(center, CoinKind::Gold),
+        ];
+
+        walk.collect_coins();
+
+        let coin_value = walk.config.coin_value;
+        assert_eq!(walk.coins, 23 * coin_value);
+        let stats = RunStats::from_walk(&walk);
+        assert_eq!(stats.coins_by_kind, [0, 1, 2]);
+        assert_eq!(stats.coin_score, 23 * coin_value);
     }
 
-    fn update(self) -> Self {
-        self.transition(Event::Update)
+    #[wasm_bindgen_test]
+    fn coins_float_within_their_draw_area() {
+        let center = Point { x: 100, y: 300 };
+        let mut coin = Coin::new(center, CoinKind::Bronze);
+        let heights: Vec<i16> = (0..100)
+            .map(|_frame| {
+                coin.update();
+                Collectible::center(&coin).y - center.y
+            })
+            .collect();
+        let float_height = COIN_FLOAT_HEIGHT as i16;
+        assert!(heights.iter().all(|height| height.abs() <= float_height));
+        assert!(heights.iter().any(|height| *height > 0));
+        assert!(heights.iter().any(|height| *height < 0));
     }
 
-    fn knocked_out(&self) -> bool {
-        matches!(self, RedHatBoyStateMachine::KnockedOut(_))
+    #[wasm_bindgen_test]
+    fn power_up_pickups_float_within_their_draw_area() {
+        let (stone, _sheet) = segment_images();
+        let center = Point { x: 100, y: 300 };
+        let mut pickup = PowerUpPickup::new(stone, center, PowerUp::Ghost);
+        let heights: Vec<i16> = (0..100)
+            .map(|_frame| {
+                pickup.update();
+                let drawn = pickup.bounding_box();
+                let area = pickup.draw_area();
+                assert!(area.y() <= drawn.y() && drawn.bottom() <= area.bottom());
+                drawn.center().y - center.y
+            })
+            .collect();
+        assert!(heights.iter().any(|height| *height > 0));
+        assert!(heights.iter().any(|height| *height < 0));
     }
-}
 
-impl From<RedHatBoyState<Idle>> for RedHatBoyStateMachine {
-    fn from(state: RedHatBoyState<Idle>) -> Self {
-        RedHatBoyStateMachine::Idle(state)
+    #[wasm_bindgen_test]
+    fn slow_motion_halves_scroll_and_animation_speed() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.boy.run_right();
+        walk.update(&keystate);
+        let normal_velocity = walk.velocity();
+
+        walk.activate_power_up(PowerUp::SlowMotion);
+        assert_eq!(
+            walk.target_scroll_velocity(),
+            normal_velocity / SLOW_MOTION_FACTOR
+        );
+
+        let frame_before = walk.boy.state_machine.context().frame;
+        (0..4).for_each(|_tick| walk.update(&keystate));
+        assert_eq!(walk.boy.state_machine.context().frame, frame_before + 2);
     }
-}
 
-impl From<RedHatBoyState<Running>> for RedHatBoyStateMachine {
-    fn from(state: RedHatBoyState<Running>) -> Self {
-        RedHatBoyStateMachine::Running(state)
+    #[wasm_bindgen_test]
+    fn scrolling_slows_down_gradually_but_speeds_up_at_once() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.boy.run_right();
+        walk.update(&keystate);
+        let running_velocity = walk.velocity();
+        assert_eq!(running_velocity, walk.target_scroll_velocity());
+
+        walk.boy = RedHatBoy::reset(walk.boy);
+        let velocities: Vec<i16> = (0..running_velocity.abs())
+            .map(|_frame| {
+                walk.update(&keystate);
+                walk.velocity()
+            })
+            .collect();
+        let slowing: Vec<i16> = (running_velocity + 1..=0).collect();
+        assert_eq!(velocities, slowing);
     }
-}
 
-impl From<RedHatBoyState<Sliding>> for RedHatBoyStateMachine {
-    fn from(state: RedHatBoyState<Sliding>) -> Self {
-        RedHatBoyStateMachine::Sliding(state)
+    #[wasm_bindgen_test]
+    fn a_boy_nobody_tells_to_run_slows_to_a_stop() {
+        let mut boy = test_walk(SEED).boy;
+        boy.run_right();
+        boy.update();
+        let running_speed = PhysicsConfig::default().running_speed;
+        assert_eq!(boy.walking_speed(), running_speed);
+
+        boy.update();
+        assert_eq!(boy.walking_speed(), running_speed - DECELERATION_X);
+        (0..running_speed).for_each(|_tick| boy.update());
+        assert_eq!(boy.walking_speed(), 0);
+
+        boy.run_right();
+        assert_eq!(boy.walking_speed(), running_speed);
+        boy.update();
+        assert_eq!(boy.walking_speed(), running_speed);
     }
-}
 
-impl From<RedHatBoyState<Jumping>> for RedHatBoyStateMachine {
-    fn from(state: RedHatBoyState<Jumping>) -> Self {
-        RedHatBoyStateMachine::Jumping(state)
+    #[wasm_bindgen_test]
+    fn only_hold_to_run_needs_the_run_key_held() {
+        let keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        (0..10).for_each(|_tick| walk.update(&keystate));
+        let running_speed = PhysicsConfig::default().running_speed;
+        assert_eq!(walk.boy.walking_speed(), running_speed);
+
+        walk.config.hold_to_run = true;
+        (0..10).for_each(|_tick| walk.update(&keystate));
+        assert_eq!(walk.boy.walking_speed(), 0);
     }
-}
 
-impl From<RedHatBoyState<Falling>> for RedHatBoyStateMachine {
-    fn from(state: RedHatBoyState<Falling>) -> Self {
-        RedHatBoyStateMachine::Falling(state)
+    #[wasm_bindgen_test]
+    fn dash_speeds_up_and_then_cools_down() {
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        walk.boy.update();
+        let normal_velocity = walk.target_scroll_velocity();
+
+        walk.boy.dash();
+        assert_eq!(
+            walk.target_scroll_velocity(),
+            normal_velocity * DASH_SPEED_MULTIPLIER
+        );
+
+        // Left to himself, the boy would slow down
+        let run = |walk: &mut Walk| {
+            walk.boy.keep_running();
+            walk.boy.update();
+        };
+        (0..DASH_FRAMES).for_each(|_tick| run(&mut walk));
+        assert_eq!(walk.target_scroll_velocity(), normal_velocity);
+        walk.boy.dash();
+        assert!(!walk.boy.dashing());
+
+        (0..DASH_COOLDOWN_FRAMES).for_each(|_tick| run(&mut walk));
+        walk.boy.dash();
+        assert!(walk.boy.dashing());
     }
-}
 
-impl From<RedHatBoyState<KnockedOut>> for RedHatBoyStateMachine {
-    fn from(state: RedHatBoyState<KnockedOut>) -> Self {
-        RedHatBoyStateMachine::KnockedOut(state)
+    #[wasm_bindgen_test]
+    fn rocket_flies_at_a_steady_speed_until_it_runs_out() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.boy.run_right();
+        walk.activate_power_up(PowerUp::Rocket);
+
+        (0..ROCKET_TICKS - 1).for_each(|_tick| {
+            walk.update(&keystate);
+            assert!(walk.boy.frame_name().starts_with("Jump"));
+            assert_eq!(walk.boy.velocity_y(), ROCKET_SPEED);
+        });
+        assert!(walk.boy.pos_y() < 100);
+
+        walk.update(&keystate);
+        assert!(!walk.rocket_active());
+        walk.update(&keystate);
+        assert!(walk.boy.velocity_y() > ROCKET_SPEED);
     }
-}
 
-impl From<SlidingEndState> for RedHatBoyStateMachine {
-    fn from(end_state: SlidingEndState) -> Self {
-        match end_state {
-            SlidingEndState::Complete(running_state) => running_state.into(),
-            SlidingEndState::Sliding(sliding_state) => sliding_state.into(),
-        }
+    #[wasm_bindgen_test]
+    fn gravity_flip_runs_the_boy_along_the_ceiling() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.obstacles = vec![Box::new(Ground::raised(-1000, 3000, GROUND - 40)).into()];
+        // Nothing new scrolls in to get in the way
+        walk.timeline = i16::MAX;
+        walk.boy.run_right();
+        walk.activate_power_up(PowerUp::GravityFlip);
+
+        (0..100).for_each(|_tick| walk.update(&keystate));
+        assert_eq!(walk.boy.pos_y(), 0);
+        assert!(walk.boy.is_on_ground());
+
+        walk.boy.jump();
+        walk.update(&keystate);
+        assert!(walk.boy.pos_y() > 0 && walk.boy.velocity_y() > 0);
+
+        walk.active_power_up = None;
+        walk.update(&keystate);
+        assert_eq!(walk.boy.pos_y(), GROUND - 40 - PLAYER_HEIGHT);
+        assert!(!walk.knocked_out());
     }
-}
 
-impl From<JumpingEndState> for RedHatBoyStateMachine {
-    fn from(end_state: JumpingEndState) -> Self {
-        match end_state {
-            JumpingEndState::Complete(running_state) => running_state.into(),
-            JumpingEndState::Jumping(jumping_state) => jumping_state.into(),
-        }
+    #[wasm_bindgen_test]
+    fn segments_are_generated_past_a_wide_view() {
+        let mut walk = test_walk(SEED);
+        assert_eq!(walk.timeline_minimum(), walk.config.timeline_minimum);
+
+        walk.screen.width = 2000;
+        assert_eq!(walk.timeline_minimum(), 2000 + TIMELINE_LEAD);
+        walk.config.render_scale = 0.5;
+        assert_eq!(walk.timeline_minimum(), 4000 + TIMELINE_LEAD);
     }
-}
 
-impl From<FallingState> for RedHatBoyStateMachine {
-    fn from(falling_state: FallingState) -> Self {
-        match falling_state {
-            FallingState::Complete(knockout_state) => knockout_state.into(),
-            FallingState::Falling(falling_state) => falling_state.into(),
-        }
+    #[wasm_bindgen_test]
+    fn only_obstacles_on_screen_are_in_view() {
+        const OBSTACLES: i16 = 50;
+        let mut walk = test_walk(SEED);
+        walk.obstacles = (0..OBSTACLES)
+            .map(|index| {
+                Box::new(Coin::new(
+                    Point {
+                        x: index * 60 - 100,
+                        y: 300,
+                    },
+                    CoinKind::Bronze,
+                ))
+                .into()
+            })
+            .collect();
+
+        let drawn = walk.obstacles_in_view().count() as i16;
+
+        // The coins centered from x = 20 to x = 560
+        assert_eq!(drawn, 10);
+        assert!(drawn < OBSTACLES / 4);
     }
-}
 
-fn rightmost(obstacle_list: &Vec<Box<dyn Obstacle>>) -> i16 {
-    obstacle_list
-        .iter()
-        .map(|obstacle| obstacle.right())
-        .max_by(|x, y| x.cmp(&y))
-        .unwrap_or(0)
-}
+    #[wasm_bindgen_test]
+    fn themes_fade_in_at_every_milestone_and_wrap_around() {
+        let first = theme_blend(0, 1000, 3);
+        assert_eq!((first.incoming, first.alpha), (0, 1.0));
 
-pub struct Platform {
-    sheet: Rc<SpriteSheet>,
-    sprites: Vec<Cell>,
-    position: Point,
-    bounding_boxes: Vec<Rect>,
-}
+        let fading = theme_blend(1000 + THEME_FADE_DISTANCE / 2, 1000, 3);
+        assert_eq!((fading.outgoing, fading.incoming), (0, 1));
+        assert!((fading.alpha - 0.5).abs() < f64::EPSILON);
 
-impl Obstacle for Platform {
-    fn check_intersection(&self, boy: &mut RedHatBoy) {
-        if let Some(box_to_land_on) = self
-            .bounding_boxes()
-            .iter()
-            .find(|&bounding_box| boy.bounding_box().intersects(bounding_box))
-        {
-            if boy.velocity_y() > 0 && boy.pos_y() < self.position.y {
-                boy.land_on(box_to_land_on.y());
-            } else {
-                boy.knock_out();
-            }
-        }
+        let faded = theme_blend(1000 + THEME_FADE_DISTANCE, 1000, 3);
+        assert_eq!(faded.alpha, 1.0);
+
+        let wrapped = theme_blend(3000, 1000, 3);
+        assert_eq!((wrapped.outgoing, wrapped.incoming), (2, 0));
+
+        let single = theme_blend(3000, 1000, 1);
+        assert_eq!((single.incoming, single.alpha), (0, 1.0));
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        let mut x = 0;
-        self.sprites.iter().for_each(|sprite| {
-            self.sheet.draw(
-                renderer,
-                &Rect::new_from_x_y(
-                    sprite.frame.x,
-                    sprite.frame.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
-                // Just use position and the standard widths in the tileset
-                &Rect::new_from_x_y(
-                    self.position.x + x,
-                    self.position.y,
-                    sprite.frame.w,
-                    sprite.frame.h,
-                ),
-            );
-            x += sprite.frame.w;
-        });
+    #[wasm_bindgen_test]
+    fn difficulty_floor_leaves_out_easier_segments() {
+        let segments = GameConfig::default().segments;
+        assert_eq!(segment_weights(&segments, 0), [1, 1, 1, 1, 1, 1, 1, 1]);
+        assert_eq!(segment_weights(&segments, 1), [1, 1, 0, 1, 1, 1, 1, 1]);
+        assert_eq!(segment_weights(&segments, 2), [1, 0, 0, 0, 1, 1, 1, 0]);
+        // Past the hardest segments those keep being generated
+        assert_eq!(segment_weights(&segments, 9), [1, 0, 0, 0, 1, 1, 1, 0]);
     }
 
-    fn move_horizontally(&mut self, x: i16) {
-        self.position.x += x;
-        self.bounding_boxes.iter_mut().for_each(|bounding_box| {
-            bounding_box.set_x(bounding_box.position.x + x);
-        });
+    #[wasm_bindgen_test]
+    fn difficulty_floor_keeps_a_few_segments_to_pick_from() {
+        let mut segments = GameConfig::default().segments;
+        segments.values_mut().for_each(|entry| entry.difficulty = 0);
+        segments
+            .get_mut(SEGMENTS[0])
+            .expect("The default config has every segment")
+            .difficulty = 3;
+        // A floor of 3 would only leave the first one
+        assert_eq!(segment_weights(&segments, 3), [1, 1, 1, 1, 1, 1, 1, 1]);
     }
 
-    fn right(&self) -> i16 {
-        self.bounding_boxes()
-            .last()
-            .unwrap_or(&Rect::default())
-            .right()
+    #[wasm_bindgen_test]
+    fn milestone_banner_shows_once_per_milestone_and_fades() {
+        let mut walk = test_walk(SEED);
+        walk.distance = MILESTONE_DISTANCE - 1;
+        walk.pass_milestones();
+        assert_eq!(walk.milestone_banner_frames, 0);
+
+        walk.distance = MILESTONE_DISTANCE;
+        walk.pass_milestones();
+        assert_eq!(walk.milestones, 1);
+        assert_eq!(walk.milestone_banner_frames, MILESTONE_BANNER_FRAMES);
+
+        (0..MILESTONE_BANNER_FRAMES).for_each(|_frame| walk.pass_milestones());
+        assert_eq!(walk.milestone_banner_frames, 0);
+
+        let reset = Walk::reset(walk);
+        assert_eq!((reset.milestones, reset.milestone_banner_frames), (0, 0));
     }
-}
 
-impl Platform {
-    pub fn new(
-        sheet: Rc<SpriteSheet>,
-        position: Point,
-        sprite_names: &[&str],
-        bounding_boxes: &[Rect],
-    ) -> Self {
-        let sprites = sprite_names
+    #[wasm_bindgen_test]
+    fn a_new_run_starts_with_a_grace_period_free_of_obstacles() {
+        let mut walk = Walk::reset(test_walk(SEED));
+        assert!(walk
+            .obstacles
             .iter()
-            .filter_map(|sprite_name| sheet.cell(sprite_name).cloned())
-            .collect();
-        let bounding_boxes = bounding_boxes
+            .all(|obstacle| obstacle.draw_area().x() >= GRACE_DISTANCE));
+        assert!(!walk.collision_enabled());
+
+        walk.distance = GRACE_DISTANCE.unsigned_abs().into();
+        walk.end_grace_period();
+        assert!(walk.collision_enabled());
+    }
+
+    /// Every platform tile 128 pixels wide, the stone 90 by 54.
+    fn segment_images() -> (HtmlImageElement, Rc<SpriteSheet>) {
+        let image = HtmlImageElement::new().unwrap();
+        image.set_width(90);
+        image.set_height(54);
+        let frames = ["1.png", "3.png", "13.png", "14.png", "15.png"]
             .iter()
-            .map(|bounding_box| {
-                Rect::new_from_x_y(
-                    bounding_box.x() + position.x,
-                    bounding_box.y() + position.y,
-                    bounding_box.width,
-                    bounding_box.height,
+            .map(|name| {
+                let rect = SheetRect {
+                    x: 0,
+                    y: 0,
+                    w: 128,
+                    h: 128,
+                };
+                (
+                    name.to_string(),
+                    Cell {
+                        frame: rect.clone(),
+                        sprite_source_size: rect,
+                        duration: None,
+                    },
                 )
             })
             .collect();
-        Platform {
-            sheet,
-            bounding_boxes,
-            sprites,
-            position,
-        }
+        let sheet = Rc::new(SpriteSheet::new(Sheet { frames }, image.clone()));
+        (image, sheet)
     }
 
-    fn bounding_boxes(&self) -> &Vec<Rect> {
-        &self.bounding_boxes
+    #[wasm_bindgen_test]
+    fn stone_and_platform_puts_the_stone_and_platform_at_their_offsets() {
+        let (stone, sheet) = segment_images();
+        let mut pool = ObstaclePool::default();
+        let obstacles = stone_and_platform(stone, sheet, 1000, &mut pool);
+        assert_eq!(obstacles[0].right(), 1000 + 150 + 90);
+        assert_eq!(obstacles[1].right(), 1000 + FIRST_PLATFORM + 384);
+        assert_eq!(rightmost(&obstacles), 1000 + FIRST_PLATFORM + 384);
     }
-}
 
-pub trait Obstacle {
-    fn check_intersection(&self, boy: &mut RedHatBoy);
-    fn draw(&self, renderer: &Renderer);
-    fn move_horizontally(&mut self, x: i16);
-    fn right(&self) -> i16;
-}
+    #[wasm_bindgen_test]
+    fn other_platform_puts_the_cliff_at_its_offset() {
+        let (_stone, sheet) = segment_images();
+        let mut pool = ObstaclePool::default();
+        let obstacles = other_platform(sheet, -200, &mut pool);
+        assert_eq!(obstacles.len(), 1);
+        assert_eq!(rightmost(&obstacles), -200 + FIRST_PLATFORM + 384);
+    }
 
-mod red_hat_boy_states {
-    use crate::engine::{Audio, Sound};
-    use crate::game::{Point, HEIGHT};
+    #[wasm_bindgen_test]
+    fn gap_and_platform_puts_the_pit_under_the_platform() {
+        let (_stone, sheet) = segment_images();
+        let mut pool = ObstaclePool::default();
+        let obstacles = gap_and_platform(sheet, 1000, &mut pool);
+        assert_eq!(obstacles[0].right(), 1000 + FIRST_PLATFORM + 384);
+        // Its bounding boxes stop short of the right cap
+        assert_eq!(obstacles[1].right(), 1000 + FIRST_PLATFORM + 384 - 30);
+    }
 
-    const FLOOR: i16 = 479;
-    const STARTING_POINT: i16 = -20;
-    const PLAYER_HEIGHT: i16 = HEIGHT - FLOOR;
+    #[wasm_bindgen_test]
+    fn hanging_barrier_run_spaces_its_stones_evenly() {
+        let (stone, _sheet) = segment_images();
+        let mut pool = ObstaclePool::default();
+        let obstacles = hanging_barrier_run(stone, 500, &mut pool);
+        let rights: Vec<i16> = obstacles.iter().map(|obstacle| obstacle.right()).collect();
+        assert_eq!(rights, vec![590, 690, 790]);
+        assert_eq!(rightmost(&obstacles), 790);
+    }
 
-    const IDLE_FRAME_NAME: &str = "Idle";
-    const RUN_FRAME_NAME: &str = "Run";
-    const SLIDING_FRAME_NAME: &str = "Slide";
-    const JUMPING_FRAME_NAME: &str = "Jump";
-    const FALLING_FRAME_NAME: &str = "Dead";
+    #[wasm_bindgen_test]
+    fn no_segment_is_larger_than_the_smallest_obstacle_cap() {
+        let (stone, sheet) = segment_images();
+        let mut pool = ObstaclePool::default();
+        let segments = [
+            stone_and_platform(stone.clone(), sheet.clone(), 0, &mut pool),
+            other_platform(sheet.clone(), 0, &mut pool),
+            glowing_platform(sheet.clone(), 0, &mut pool),
+            windy_platform(sheet.clone(), 0, &mut pool),
+            gap_and_platform(sheet.clone(), 0, &mut pool),
+            long_platform(sheet.clone(), 0, &mut pool),
+            pit(0),
+            hanging_barrier_run(stone.clone(), 0, &mut pool),
+            boss_segment(sheet, stone, 0, &mut pool),
+        ];
+        assert!(segments
+            .iter()
+            .all(|segment| segment.len() <= crate::segment::MAX_SEGMENT_OBSTACLES));
+    }
 
-    const IDLE_FRAMES: u8 = 29;
-    const RUNNING_FRAMES: u8 = 23;
-    pub const SLIDING_FRAMES: u8 = 15;
-    const JUMPING_FRAMES: u8 = 35;
-    const FALLING_FRAMES: u8 = 29; // 10 'Dead' frames in the sheet, * 3 - 1.
+    #[wasm_bindgen_test]
+    fn segments_wait_while_the_obstacle_cap_is_reached() {
+        let mut walk = test_walk(SEED);
+        walk.config.max_obstacles = 30;
+        (0..100).for_each(|_segment| {
+            walk.generate_next_segment();
+            assert!(walk.obstacles.len() <= walk.config.max_obstacles);
+        });
+        assert!(walk.obstacle_cap_reached);
 
-    const RUNNING_SPEED: i16 = 4;
-    const JUMP_SPEED: i16 = -25;
-    const MAX_VELOCITY: i16 = 20;
+        walk.obstacle_pool.reclaim_all(&mut walk.obstacles);
+        walk.generate_next_segment();
+        assert!(!walk.obstacles.is_empty());
+        assert!(!walk.obstacle_cap_reached);
+    }
 
-    const GRAVITY: i16 = 1;
+    #[wasm_bindgen_test]
+    fn rest_gaps_hold_off_obstacles_every_rest_distance() {
+        let keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.obstacle_pool.reclaim_all(&mut walk.obstacles);
+        walk.distance = REST_EVERY_DISTANCE;
+        let gap_start = walk.view_width() + 100;
+        walk.timeline = gap_start;
+        walk.generate_next_segment();
+        assert!(walk.obstacles.is_empty());
+        assert_eq!(walk.rests, 1);
+        assert_eq!(walk.rest_gap_countdown, Some(walk.config.rest_duration));
+        assert_eq!(walk.timeline, gap_start + walk.config.rest_duration);
+        // Nothing to see before the gap scrolls in
+        assert_eq!(walk.safe_zone_banner_frames, 0);
+
+        walk.boy.run_right();
+        let mut updates = 0;
+        let mut banner_shown = false;
+        while walk.rest_gap_countdown.is_some() && updates < 1000 {
+            walk.update(&keystate);
+            assert!(walk.obstacles.is_empty());
+            if walk.safe_zone_banner_frames == SAFE_ZONE_BANNER_FRAMES {
+                assert!(!banner_shown);
+                assert!(walk.timeline - walk.config.rest_duration <= walk.view_width());
+                banner_shown = true;
+            }
+            updates += 1;
+        }
+        assert!(banner_shown);
+        assert!(walk.rest_gap_countdown.is_none());
 
-    #[derive(Clone)]
-    pub struct RedHatBoyState<S> {
-        pub context: RedHatBoyContext,
-        _state: S,
+        walk.update(&keystate);
+        assert!(!walk.obstacles.is_empty());
+        assert_eq!(walk.rests, 1);
     }
 
-    impl<S> RedHatBoyState<S> {
-        pub fn context(&self) -> &RedHatBoyContext {
-            &self.context
-        }
+    #[wasm_bindgen_test]
+    fn difficulty_floor_rises_with_distance_and_shrinks_the_gap() {
+        let mut walk = test_walk(SEED);
+        let widest = walk.max_safe_gap;
+        walk.distance = DIFFICULTY_FLOOR_DISTANCE * 2;
+        walk.raise_difficulty_floor();
+        assert_eq!(walk.difficulty_floor, 2);
+        assert_eq!(walk.max_safe_gap, widest - SAFE_GAP_STEP * 2);
     }
 
-    impl RedHatBoyState<Idle> {
-        // Transition from Idle to Running!
-        pub fn run(self) -> RedHatBoyState<Running> {
-            RedHatBoyState {
-                context: self.context.reset_frame().run_right(),
-                _state: Running {},
-            }
-        }
+    #[wasm_bindgen_test]
+    fn safe_gap_narrows_down_to_what_a_jump_needs() {
+        let config = GameConfig::default();
+        let widest = max_safe_gap(&config, 0);
+        assert!(config
+            .segments
+            .values()
+            .any(|entry| entry.max_buffer == widest));
+        assert!(max_safe_gap(&config, 2) < widest);
+        assert_eq!(
+            max_safe_gap(&config, u8::MAX),
+            config.physics.minimum_safe_buffer()
+        );
+    }
 
-        pub fn new(audio: Audio, jump_sound: Sound) -> Self {
-            RedHatBoyState {
-                context: RedHatBoyContext {
-                    frame: 0,
-                    position: Point {
-                        x: STARTING_POINT,
-                        y: FLOOR,
-                    },
-                    velocity: Point { x: 0, y: 0 },
-                    audio,
-                    jump_sound,
-                },
-                _state: Idle {},
-            }
-        }
+    #[wasm_bindgen_test]
+    fn buffers_vary_within_the_range_and_narrow_with_difficulty() {
+        let mut walk = test_walk(SEED);
+        let entry = SegmentEntry {
+            min_buffer: 250,
+            max_buffer: 300,
+            ..SegmentEntry::default()
+        };
+        let buffers: Vec<i16> = (0..50).map(|_segment| walk.next_buffer(&entry)).collect();
+        assert!(buffers.iter().all(|buffer| (250..=300).contains(buffer)));
+        assert!(buffers.iter().any(|buffer| *buffer != buffers[0]));
 
-        pub fn update(mut self) -> Self {
-            self.context = self.context.update(IDLE_FRAMES);
-            self
-        }
+        // The difficulty never makes a gap narrower than min_buffer
+        walk.max_safe_gap = 0;
+        assert_eq!(walk.next_buffer(&entry), 250);
+    }
 
-        pub fn frame_name(&self) -> &str {
-            IDLE_FRAME_NAME
-        }
+    #[wasm_bindgen_test]
+    fn jump_reach_follows_the_jump_arc() {
+        let physics = PhysicsConfig::default();
+        // Up for 25 updates, back on the floor after the 51st
+        assert_eq!(physics.jump_reach(4), 51 * 4);
+        assert_eq!(physics.jump_reach(0), 0);
     }
 
-    impl RedHatBoyState<Running> {
-        pub fn frame_name(&self) -> &str {
-            RUN_FRAME_NAME
-        }
+    #[wasm_bindgen_test]
+    fn physics_params_are_checked_and_clamped() {
+        let mut physics = PhysicsConfig::default();
+        physics.set("gravity", 2.4).unwrap();
+        physics.set("jumpSpeed", 15.0).unwrap();
+        physics.set("maxVelocity", 1000.0).unwrap();
+        assert_eq!(
+            (physics.gravity, physics.jump_speed, physics.max_velocity),
+            (2, -1, 60)
+        );
+        assert!(physics.set("friction", 1.0).is_err());
+        assert!(physics.set("gravity", f64::NAN).is_err());
+    }
 
-        pub fn update(mut self) -> Self {
-            self.context = self.context.update(RUNNING_FRAMES);
-            self
-        }
+    #[wasm_bindgen_test]
+    fn tuned_physics_leave_a_jump_under_way_alone() {
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        walk.boy.jump();
+        walk.boy.update();
+        let velocity = walk.boy.velocity_y();
+
+        let mut physics = walk.config.physics;
+        physics.set("gravity", 3.0).unwrap();
+        walk.set_physics(physics);
+        assert_eq!(walk.boy.velocity_y(), velocity);
+        walk.boy.update();
+        assert_eq!(walk.boy.velocity_y(), velocity + 3);
+    }
 
-        pub fn slide(self) -> RedHatBoyState<Sliding> {
-            RedHatBoyState {
-                context: self.context.reset_frame(),
-                _state: Sliding {},
-            }
-        }
+    #[wasm_bindgen_test]
+    fn a_restored_walk_picks_up_where_the_saved_one_left_off() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.boy.run_right();
+        (0..10).for_each(|_frame| walk.update(&keystate));
+        walk.coins = 7;
+
+        let saved: WalkSnapshot = JsValue::from_serde(&walk)
+            .and_then(|value| value.into_serde())
+            .unwrap();
+        let restored = Walk::restore(test_walk(SEED), saved);
+
+        assert_eq!(restored.score(), 7);
+        assert_eq!(restored.distance, walk.distance);
+        assert_eq!(restored.timeline, walk.timeline);
+        assert!(restored.boy.position() == walk.boy.position());
+        assert_eq!(
+            restored.target_scroll_velocity(),
+            walk.target_scroll_velocity()
+        );
+        let rights = |walk: &Walk| {
+            walk.obstacles
+                .iter()
+                .map(|obstacle| obstacle.right())
+                .collect::<Vec<i16>>()
+        };
+        assert_eq!(rights(&restored), rights(&walk));
+    }
 
-        pub fn knock_out(self) -> RedHatBoyState<Falling> {
-            RedHatBoyState {
-                context: self.context.reset_frame().stop(),
-                _state: Falling {},
-            }
-        }
+    #[wasm_bindgen_test]
+    fn a_platform_without_a_frame_duration_shows_every_frame_for_an_update() {
+        let walk = test_walk(SEED);
+        let mut platform = Platform::animated(
+            walk.obstacle_sheet.clone(),
+            Point { x: 0, y: 0 },
+            &[&["13.png"], &["14.png"]],
+            &[],
+            0,
+        );
+        (0..3).for_each(|_update| platform.update());
+        assert_eq!(platform.frame, 1);
+        assert_eq!(platform.draw_area().position.x, 0);
+    }
 
-        pub fn jump(self) -> RedHatBoyState<Jumping> {
-            RedHatBoyState {
-                context: self
-                    .context
-                    .set_vertical_velocity(JUMP_SPEED)
-                    .reset_frame()
-                    .play_jump_sound(),
-                _state: Jumping {},
-            }
-        }
+    #[wasm_bindgen_test]
+    fn a_saved_platform_only_names_its_sprites() {
+        let walk = test_walk(SEED);
+        let platform = Platform::new(
+            walk.obstacle_sheet.clone(),
+            Point { x: 0, y: 0 },
+            &FLOATING_PLATFORM_SPRITES,
+            &[],
+        );
 
-        pub fn land_on(self, position: i16) -> Self {
-            RedHatBoyState {
-                context: self.context.set_on(position),
-                _state: Running {},
+        let saved: ObstacleDescriptor = JsValue::from_serde(&platform.descriptor())
+            .and_then(|value| value.into_serde())
+            .unwrap();
+        match saved {
+            ObstacleDescriptor::Platform { frames, .. } => {
+                assert_eq!(frames, vec![FLOATING_PLATFORM_SPRITES.to_vec()])
             }
+            _ => panic!("A platform was saved as something else"),
         }
     }
 
-    pub enum SlidingEndState {
-        Complete(RedHatBoyState<Running>),
-        Sliding(RedHatBoyState<Sliding>),
+    #[wasm_bindgen_test]
+    fn landing_puts_the_feet_flush_on_the_platform() {
+        let mut boy = test_walk(SEED).boy;
+        boy.run_right();
+        boy.jump();
+        // Falling at full speed the last step sank the feet into the platform
+        let context = boy.state_machine.context_mut();
+        context.velocity.y = PhysicsConfig::default().max_velocity;
+        context.position.y = LOW_PLATFORM - PLAYER_HEIGHT + 17;
+
+        boy.land_on(LOW_PLATFORM);
+        assert_eq!(boy.pos_y(), LOW_PLATFORM - PLAYER_HEIGHT);
+        assert_eq!(boy.velocity_y(), 0);
+
+        // Still flush after running on for an update
+        boy.update();
+        boy.land_on(LOW_PLATFORM);
+        assert_eq!(boy.pos_y(), LOW_PLATFORM - PLAYER_HEIGHT);
     }
 
-    impl RedHatBoyState<Sliding> {
-        pub fn frame_name(&self) -> &str {
-            SLIDING_FRAME_NAME
+    #[wasm_bindgen_test]
+    fn a_slide_can_be_cancelled_into_a_low_jump() {
+        let mut boy = test_walk(SEED).boy;
+        boy.run_right();
+        boy.slide();
+        let sliding_frames = boy.state_machine.context().animation_frames.sliding;
+        while boy.state_machine.context().frame < sliding_frames / 2 {
+            boy.update();
         }
-        pub fn update(mut self) -> SlidingEndState {
-            self.context = self.context.update(SLIDING_FRAMES);
-            if self.context.frame >= SLIDING_FRAMES {
-                SlidingEndState::Complete(self.stand())
-            } else {
-                SlidingEndState::Sliding(self)
-            }
+        boy.jump();
+
+        assert!(boy.is_jumping());
+        let physics = PhysicsConfig::default();
+        assert_eq!(
+            boy.velocity_y(),
+            physics.jump_speed * LOW_JUMP_SPEED_PERCENT / 100
+        );
+        assert_eq!(boy.state_machine.context().frame, 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn jumping_early_in_a_slide_springs_higher_and_extends_the_combo() {
+        let mut keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        walk.boy.slide();
+        walk.boy.update();
+        keystate.inject_press(&walk.config.key_bindings.jump);
+        walk.update(&keystate);
+
+        assert!(walk.boy.is_jumping());
+        // One update in, gravity already pulls
+        let physics = PhysicsConfig::default();
+        assert_eq!(
+            walk.boy.velocity_y(),
+            physics.jump_speed * SLIDE_JUMP_SPEED_PERCENT / 100 + physics.gravity
+        );
+        assert_eq!(walk.tally.combo, 1);
+        assert!(!walk.boy.state_machine.context().slide_jumped);
+    }
+
+    #[wasm_bindgen_test]
+    fn a_slide_asked_for_mid_jump_starts_on_landing() {
+        let mut boy = test_walk(SEED).boy;
+        boy.run_right();
+        boy.jump();
+        boy.update();
+        boy.slide();
+        assert!(boy.is_jumping());
+
+        let mut updates = 0;
+        while boy.is_jumping() && updates < 100 {
+            boy.update();
+            updates += 1;
         }
-        pub fn stand(self) -> RedHatBoyState<Running> {
-            RedHatBoyState {
-                context: self.context.reset_frame(),
-                _state: Running {},
-            }
+        assert!(boy.state_machine.boy_move() == BoyMove::Sliding);
+        assert_eq!(boy.state_machine.context().frame, 0);
+        assert!(!boy.state_machine.context().slide_on_landing);
+
+        // The next jump lands running again
+        boy.jump();
+        while boy.is_jumping() {
+            boy.update();
         }
-        pub fn knock_out(self) -> RedHatBoyState<Falling> {
-            RedHatBoyState {
-                context: self.context.reset_frame().stop(),
-                _state: Falling {},
-            }
+        assert!(boy.state_machine.boy_move() == BoyMove::Running);
+    }
+
+    #[wasm_bindgen_test]
+    fn long_jumps_earn_air_time_points_on_landing() {
+        let keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        walk.boy.jump();
+        while walk.boy.is_jumping() {
+            walk.update(&keystate);
         }
-        pub fn land_on(self, position: i16) -> Self {
-            RedHatBoyState {
-                context: self.context.set_on(position),
-                _state: Sliding {},
-            }
+        assert_eq!((walk.bonus_score, walk.air_popup_frames), (0, 0));
+
+        walk.boy.jump();
+        walk.update(&keystate);
+        walk.boy.state_machine.context_mut().air_frames =
+            AIR_TIME_THRESHOLD_FRAMES + 2 * AIR_FRAMES_PER_POINT;
+        while walk.boy.is_jumping() {
+            walk.update(&keystate);
         }
+        assert!(walk.bonus_score >= 2);
+        assert_eq!(walk.air_bonus, walk.bonus_score);
+        assert_eq!(walk.air_popup_frames, AIR_POPUP_FRAMES);
+        assert_eq!(walk.boy.state_machine.context().air_frames, 0);
+        assert!(walk.boy.state_machine.context().landed_air_frames.is_none());
     }
 
-    pub enum JumpingEndState {
-        Complete(RedHatBoyState<Running>),
-        Jumping(RedHatBoyState<Jumping>),
+    #[wasm_bindgen_test]
+    fn only_a_sliding_boy_fits_under_a_hanging_barrier() {
+        let stone = HtmlImageElement::new().unwrap();
+        stone.set_width(60);
+        stone.set_height(50);
+        let hanging_barrier = |walk: &mut Walk| {
+            let position = Point {
+                x: walk.boy.bounding_box().x(),
+                y: DUCK_HEIGHT,
+            };
+            walk.obstacle_pool
+                .hanging_barrier(Image::new(stone.clone(), position))
+        };
+
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        hanging_barrier(&mut walk).check_intersection(&mut walk.boy, &Difficulty::default());
+        assert!(matches!(
+            walk.boy.state_machine,
+            RedHatBoyStateMachine::Falling(_)
+        ));
+
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        walk.boy.slide();
+        hanging_barrier(&mut walk).check_intersection(&mut walk.boy, &Difficulty::default());
+        assert!(walk.boy.is_sliding());
     }
 
-    impl RedHatBoyState<Jumping> {
-        pub fn update(mut self) -> JumpingEndState {
-            self.context = self.context.update(JUMPING_FRAMES);
-            if self.context.position.y >= FLOOR {
-                JumpingEndState::Complete(self.land_on(HEIGHT.into()))
-            } else {
-                JumpingEndState::Jumping(self)
-            }
-        }
+    #[wasm_bindgen_test]
+    fn the_boy_can_graze_the_stone_margin() {
+        let stone = HtmlImageElement::new().unwrap();
+        stone.set_width(90);
+        stone.set_height(54);
+        let barrier_overlapping = |boy: &RedHatBoy, overlap: i16| {
+            let position = Point {
+                x: boy.bounding_box().right() - overlap,
+                y: GROUND - 54,
+            };
+            Barrier::with_hitbox_inset(Image::new(stone.clone(), position), STONE_HITBOX_INSET)
+        };
 
-        pub fn frame_name(&self) -> &str {
-            JUMPING_FRAME_NAME
-        }
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        barrier_overlapping(&walk.boy, STONE_HITBOX_INSET - 2)
+            .check_intersection(&mut walk.boy, &Difficulty::default());
+        assert!(walk.boy.is_on_ground() && !walk.knocked_out());
+
+        barrier_overlapping(&walk.boy, STONE_HITBOX_INSET + 2)
+            .check_intersection(&mut walk.boy, &Difficulty::default());
+        assert!(matches!(
+            walk.boy.state_machine,
+            RedHatBoyStateMachine::Falling(_)
+        ));
+    }
 
-        pub fn land_on(self, position: i16) -> RedHatBoyState<Running> {
-            RedHatBoyState {
-                context: self.context.reset_frame().set_on(position),
-                _state: Running {},
-            }
+    #[wasm_bindgen_test]
+    fn the_slide_box_clears_a_hanging_barrier_the_standing_box_hits() {
+        let mut boy = test_walk(SEED).boy;
+        boy.run_right();
+        let barrier = Rect::new_from_x_y(boy.bounding_box().x(), DUCK_HEIGHT, 60, 50);
+        assert!(boy.bounding_box().intersects(&barrier));
+
+        boy.slide();
+        assert!(!boy.bounding_box().intersects(&barrier));
+        assert_eq!(boy.bounding_box().bottom(), boy.destination_box().bottom());
+    }
+
+    #[wasm_bindgen_test]
+    fn a_jumping_boy_leaves_the_ground_until_he_lands() {
+        let mut boy = test_walk(SEED).boy;
+        boy.run_right();
+        boy.update();
+        assert!(boy.is_on_ground() && !boy.is_jumping());
+
+        boy.jump();
+        boy.update();
+        assert!(!boy.is_on_ground() && boy.is_jumping());
+
+        while boy.is_jumping() {
+            boy.update();
         }
+        assert!(boy.is_on_ground());
+    }
 
-        pub fn knock_out(self) -> RedHatBoyState<Falling> {
-            RedHatBoyState {
-                context: self.context.reset_frame().stop(),
-                _state: Falling {},
-            }
+    #[wasm_bindgen_test]
+    fn holding_slide_keeps_the_boy_down() {
+        let mut boy = test_walk(SEED).boy;
+        boy.run_right();
+        (0..100).for_each(|_update| {
+            boy.slide();
+            boy.update();
+            assert!(boy.is_sliding());
+        });
+
+        let mut updates = 0;
+        while boy.is_sliding() && updates < 100 {
+            boy.update();
+            updates += 1;
         }
+        assert!(boy.state_machine.boy_move() == BoyMove::Running);
     }
 
-    pub enum FallingState {
-        Complete(RedHatBoyState<KnockedOut>),
-        Falling(RedHatBoyState<Falling>),
+    #[wasm_bindgen_test]
+    fn boy_runs_on_raised_ground_and_falls_down_a_pit() {
+        let keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.obstacles = vec![Box::new(Ground::raised(-1000, 3000, GROUND - 40)).into()];
+        walk.boy.run_right();
+        walk.update(&keystate);
+        assert_eq!(walk.boy.pos_y(), GROUND - 40 - PLAYER_HEIGHT);
+
+        walk.obstacles = vec![Box::new(Ground::pit(-1000, 3000)).into()];
+        (0..100).for_each(|_frame| walk.update(&keystate));
+        assert!(walk.boy.pos_y() > GROUND);
+        assert!(walk.knocked_out());
     }
 
-    impl RedHatBoyState<Falling> {
-        pub(crate) fn update(mut self) -> FallingState {
-            self.context = self.context.update(FALLING_FRAMES);
-            if self.context.frame >= FALLING_FRAMES {
-                FallingState::Complete(self.dead())
-            } else {
-                FallingState::Falling(self)
-            }
-        }
-        pub fn frame_name(&self) -> &str {
-            FALLING_FRAME_NAME
-        }
-        pub fn dead(self) -> RedHatBoyState<KnockedOut> {
-            RedHatBoyState {
-                context: self.context,
-                _state: KnockedOut {},
-            }
-        }
+    #[wasm_bindgen_test]
+    fn walking_counts_the_seconds_and_speeds_up_after_a_minute() {
+        let keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        walk.update(&keystate);
+        let speed = walk.target_scroll_velocity();
+        let mut state = WalkTheDogState {
+            _state: fresh_walking(),
+            walk,
+        };
+
+        state = match state.update(&keystate, 0.5) {
+            WalkingEndState::Continue(state) => state,
+            _ => panic!("the walk ended on its own"),
+        };
+        assert!(state.walk.elapsed_seconds == 0.5);
+
+        state.walk.elapsed_seconds = SPEED_UP_SECONDS;
+        assert_eq!(state.walk.target_scroll_velocity(), speed - SPEED_UP_BONUS);
     }
 
-    impl RedHatBoyState<KnockedOut> {
-        pub fn frame_name(&self) -> &str {
-            FALLING_FRAME_NAME
+    #[wasm_bindgen_test]
+    fn quitting_a_walk_starts_over_on_the_ready_screen() {
+        let mut walk = test_walk(SEED);
+        walk.distance = 500;
+        let state = WalkTheDogState {
+            _state: fresh_walking(),
+            walk,
+        };
+        let mut keystate = KeyState::new();
+        keystate.inject_press(&state.walk.config.key_bindings.quit);
+
+        match state.update(&keystate, engine::STEP_SECONDS) {
+            WalkingEndState::Quit(ready) => assert_eq!(ready.walk.distance, 0),
+            _ => panic!("quitting didn't go back to the ready screen"),
         }
+    }
 
-        pub fn update(mut self) -> Self {
-            self.context = self.context.apply_velocity();
-            self
+    #[wasm_bindgen_test]
+    fn a_knocked_out_boy_lies_there_a_while_before_game_over() {
+        let keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.boy.run_right();
+        walk.boy.knock_out();
+        (0..100).for_each(|_frame| walk.update(&keystate));
+        assert!(walk.knocked_out());
+
+        let mut state = WalkTheDogState {
+            _state: fresh_walking(),
+            walk,
+        };
+        for frames_left in (0..=KNOCKED_OUT_HOLD_FRAMES).rev() {
+            state = match state.update(&keystate, engine::STEP_SECONDS) {
+                WalkingEndState::Continue(state) => state,
+                _ => panic!("the walk ended before the hold ran out"),
+            };
+            assert!(state._state.knocked_out_frames == Some(frames_left));
         }
+        assert_eq!(state.walk.tally.knockouts, 1);
+    }
 
-        pub fn land_on(self, position: i16) -> Self {
-            RedHatBoyState {
-                context: self.context.set_on(position),
-                _state: KnockedOut {},
+    #[wasm_bindgen_test]
+    fn a_spare_life_gets_the_boy_up_instead_of_ending_the_run() {
+        let keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.lives = 2;
+        walk.boy.run_right();
+        walk.boy.knock_out();
+        (0..100).for_each(|_frame| walk.update(&keystate));
+
+        let state = WalkTheDogState {
+            _state: Walking {
+                knocked_out_frames: Some(0),
+                ..fresh_walking()
+            },
+            walk,
+        };
+        match state.update(&keystate, engine::STEP_SECONDS) {
+            WalkingEndState::Continue(state) => {
+                assert_eq!(state.walk.lives, 1);
+                assert!(!state.walk.knocked_out());
+                assert!(state._state.knocked_out_frames.is_none());
             }
+            _ => panic!("the spare life wasn't used"),
         }
     }
 
-    #[derive(Clone)]
-    pub struct RedHatBoyContext {
-        pub frame: u8,
-        pub position: Point,
-        pub velocity: Point,
-        pub(crate) audio: Audio,
-        pub(crate) jump_sound: Sound,
+    #[wasm_bindgen_test]
+    fn a_preset_only_tunes_the_runs_started_afterwards() {
+        let default_speed = PhysicsConfig::default().running_speed;
+        let mut walk = test_walk(SEED);
+        walk.select_preset(Preset::Kids);
+        assert_eq!(walk.config.preset, Preset::Standard);
+        assert_eq!(
+            walk.boy.state_machine.context().physics.running_speed,
+            default_speed
+        );
+
+        let mut walk = Walk::apply_preset(walk);
+        assert_eq!(walk.config.preset, Preset::Kids);
+        assert!(walk.lives > 1);
+        assert!(walk.config.physics.running_speed < default_speed);
+        assert_eq!(
+            walk.boy.state_machine.context().physics.running_speed,
+            walk.config.physics.running_speed
+        );
+        let pit = SEGMENTS
+            .iter()
+            .position(|segment| *segment == PIT_SEGMENT)
+            .unwrap();
+        assert_eq!(segment_weights(&walk.config.segments, 0)[pit], 0);
+        assert!(
+            walk.config.segments[STONE_AND_PLATFORM_SEGMENT].min_buffer
+                > SegmentEntry::default().min_buffer
+        );
+
+        walk.select_preset(Preset::Standard);
+        let walk = Walk::apply_preset(walk);
+        assert_eq!(walk.config.physics.running_speed, default_speed);
+        assert_eq!(walk.lives, 1);
     }
 
-    impl RedHatBoyContext {
-        pub fn update(mut self, frame_count: u8) -> Self {
-            if self.frame < frame_count {
-                self.frame += 1;
-            } else {
-                self.frame = 0;
-            }
+    #[wasm_bindgen_test]
+    fn tuning_the_physics_leaves_the_preset_tunings_behind() {
+        let default_physics = PhysicsConfig::default();
+        let mut walk = test_walk(SEED);
+        walk.select_preset(Preset::Kids);
+        let mut walk = Walk::apply_preset(walk);
+
+        let mut physics = walk.config.physics;
+        physics.jump_speed = -30;
+        walk.set_physics(physics);
+        walk.select_preset(Preset::Standard);
+        let walk = Walk::apply_preset(walk);
+
+        assert_eq!(walk.config.physics.jump_speed, -30);
+        assert_eq!(
+            walk.config.physics.running_speed,
+            default_physics.running_speed
+        );
+    }
 
-            self.apply_velocity()
+    #[wasm_bindgen_test]
+    fn falling_below_the_screen_ends_the_run_on_the_spot() {
+        let keystate = KeyState::new();
+        let mut walk = test_walk(SEED);
+        walk.obstacles = vec![Box::new(Ground::pit(-1000, 3000)).into()];
+        walk.boy.run_right();
+        walk.boy.dash();
+        walk.lives = 1;
+        let mut state = WalkTheDogState {
+            _state: fresh_walking(),
+            walk,
+        };
+        while state.walk.boy.pos_y() <= GROUND {
+            assert!(!state.walk.knocked_out());
+            state = match state.update(&keystate, engine::STEP_SECONDS) {
+                WalkingEndState::Continue(state) => state,
+                _ => panic!("the walk ended before the boy fell"),
+            };
         }
+        // No falling animation first, the walking state holds and ends the game
+        assert!(state.walk.knocked_out());
+
+        // The game over screen draws its buttons into the UI
+        let document = browser::document().unwrap();
+        let ui = document.create_element("div").unwrap();
+        ui.set_id("ui");
+        document.body().unwrap().append_child(&ui).unwrap();
+        let game_over = loop {
+            state = match state.update(&keystate, engine::STEP_SECONDS) {
+                WalkingEndState::Continue(state) => state,
+                WalkingEndState::Complete(game_over) => break game_over,
+                WalkingEndState::Quit(_ready) => panic!("falling quit the walk"),
+            };
+        };
+        ui.remove();
+        assert_eq!(game_over.walk.tally.knockouts, 1);
+    }
 
-        fn play_jump_sound(self) -> Self {
-            if let Err(err) = self.audio.play_sound(&self.jump_sound) {
-                log!("Error playing jump sound {:#?}", err);
-            }
-            self
-        }
+    #[wasm_bindgen_test]
+    fn tally_counts_every_move_once() {
+        let mut tally = RunTally::default();
+        tally.count_move(BoyMove::Running, BoyMove::Jumping);
+        tally.count_move(BoyMove::Jumping, BoyMove::Jumping);
+        tally.count_move(BoyMove::Jumping, BoyMove::Running);
+        tally.count_move(BoyMove::Running, BoyMove::Sliding);
+        assert_eq!((tally.jumps, tally.slides), (1, 1));
+    }
 
-        fn apply_velocity(mut self) -> Self {
-            self.position.y += self.velocity.y;
-            self.velocity.y += GRAVITY;
-            self.velocity.y = self.velocity.y.min(MAX_VELOCITY);
-            self.position.y = self.position.y.min(FLOOR);
-            self
-        }
+    #[wasm_bindgen_test]
+    fn stats_count_up_to_their_value_in_time() {
+        let mut lines = [
+            StatLine::new(StatIcon::Coin(CoinKind::Bronze), 0),
+            StatLine::new(StatIcon::Coin(CoinKind::Bronze), 7),
+            StatLine::new(StatIcon::Coin(CoinKind::Bronze), 12345),
+        ];
+        lines.iter_mut().for_each(StatLine::count_up);
+        assert_eq!(lines[1].display_value, 1);
+        assert!(lines[2].display_value < 12345);
+        (1..SUMMARY_TALLY_FRAMES).for_each(|_frame| lines.iter_mut().for_each(StatLine::count_up));
+        assert_eq!(lines.map(|line| line.display_value), [0, 7, 12345]);
+    }
 
-        fn reset_frame(mut self) -> Self {
-            self.frame = 0;
-            self
-        }
+    #[wasm_bindgen_test]
+    fn grade_rewards_score_and_distance_and_punishes_knockouts() {
+        let stats = |score, distance, knockouts| RunStats {
+            score,
+            distance,
+            coins_by_kind: [0; 3],
+            coin_score: 0,
+            jumps: 0,
+            slides: 0,
+            knockouts,
+            time_seconds: 0,
+            combo_max: 0,
+        };
+        assert_eq!(stats(0, 0, 1).grade(), "C");
+        assert_eq!(stats(40, 0, 0).grade(), "B");
+        assert_eq!(stats(100, 0, 0).grade(), "A");
+        assert_eq!(stats(100, 100_000, 0).grade(), "S");
+        assert_eq!(stats(100, 100_000, 4).grade(), "A");
+    }
 
-        fn run_right(mut self) -> Self {
-            self.velocity.x += RUNNING_SPEED;
-            self
-        }
+    #[wasm_bindgen_test]
+    fn the_best_run_keeps_the_best_of_every_stat() {
+        let stats = RunStats {
+            score: 120,
+            distance: 3000,
+            coins_by_kind: [4, 2, 1],
+            coin_score: 20,
+            jumps: 9,
+            slides: 3,
+            knockouts: 1,
+            time_seconds: 40,
+            combo_max: 5,
+        };
+        let this_run = BestRun::from_stats(&stats);
+        assert_eq!((this_run.coins, this_run.jumps), (7, 9));
+
+        let best = BestRun {
+            score: 200,
+            distance: 1000,
+            coins: 7,
+            jumps: 12,
+        };
+        assert_eq!(
+            this_run.max(best),
+            BestRun {
+                score: 200,
+                distance: 3000,
+                coins: 7,
+                jumps: 12,
+            }
+        );
+        let better: Vec<bool> = this_run
+            .compare(&best)
+            .iter()
+            .map(|(_icon, value, best_value)| value > best_value)
+            .collect();
+        assert_eq!(better, vec![false, true, false, false]);
+    }
 
-        fn set_vertical_velocity(mut self, speed: i16) -> Self {
-            self.velocity.y = speed;
-            self
-        }
+    #[wasm_bindgen_test]
+    fn mouse_moves_past_the_deadzone_press_keys() {
+        let key_bindings = KeyBindings::default();
+        let presses = |x, y| mouse_presses(Point { x, y }, &key_bindings);
+        assert!(presses(MOUSE_DEADZONE, -MOUSE_DEADZONE).is_empty());
+        assert_eq!(presses(10, 0), ["ArrowRight"]);
+        assert_eq!(presses(10, -10), ["ArrowRight", "Space"]);
+        assert_eq!(presses(0, 10), ["ArrowDown"]);
+    }
 
-        fn stop(mut self) -> Self {
-            self.velocity.x = 0;
-            self
-        }
+    #[wasm_bindgen_test]
+    fn game_over_ui_has_the_buttons_end_game_looks_up() {
+        let everything = game_over_ui(true, true);
+        [
+            NEW_GAME_BUTTON_ID,
+            CONTINUE_BUTTON_ID,
+            LEADERBOARD_NAME_ID,
+            SUBMIT_SCORE_BUTTON_ID,
+        ]
+        .iter()
+        .for_each(|id| assert!(everything.contains_id(id), "{} is missing", id));
 
-        fn set_on(mut self, position: i16) -> Self {
-            let position = position - PLAYER_HEIGHT;
-            self.position.y = position;
-            self
-        }
+        let only_new_game = game_over_ui(false, false);
+        assert!(only_new_game.contains_id(NEW_GAME_BUTTON_ID));
+        assert!(!only_new_game.contains_id(CONTINUE_BUTTON_ID));
+        assert!(!only_new_game.contains_id(SUBMIT_SCORE_BUTTON_ID));
     }
 
-    #[derive(Copy, Clone)]
-    pub struct Idle;
+    #[wasm_bindgen_test]
+    fn boss_paces_within_its_range() {
+        let mut boss = Boss::new(HtmlImageElement::new().unwrap(), Point { x: 300, y: 480 });
+        let lefts: Vec<i16> = (0..1000)
+            .map(|_frame| {
+                boss.update();
+                boss.area.x()
+            })
+            .collect();
+        assert_eq!(lefts.iter().min(), Some(&300));
+        assert_eq!(lefts.iter().max(), Some(&(300 + BOSS_PATROL_RANGE)));
+    }
 
-    #[derive(Copy, Clone)]
-    pub struct Running;
+    #[wasm_bindgen_test]
+    fn boss_scores_every_stomp_and_disappears_after_dying() {
+        let mut boss = Boss::new(HtmlImageElement::new().unwrap(), Point { x: 300, y: 480 });
+        (0..BOSS_HEALTH).for_each(|_stomp| boss.stomp());
+        assert_eq!(boss.take_score(), BOSS_SCORE * u32::from(BOSS_HEALTH));
+        assert_eq!(boss.take_score(), 0);
+
+        (0..BOSS_DYING_FRAMES - 1).for_each(|_frame| boss.update());
+        assert!(boss.right() > 0);
+        boss.update();
+        assert_eq!(boss.right(), i16::MIN);
+    }
 
-    #[derive(Copy, Clone)]
-    pub struct Sliding;
+    #[wasm_bindgen_test]
+    fn boss_comes_once_at_its_distance() {
+        let is_boss = |obstacle: &ObstacleKind| matches!(obstacle, ObstacleKind::Boss(_));
+        let mut walk = test_walk(SEED);
+        walk.obstacles.clear();
+        walk.distance = BOSS_DISTANCE;
+        walk.generate_next_segment();
+        assert_eq!(
+            walk.obstacles
+                .iter()
+                .filter(|obstacle| is_boss(*obstacle))
+                .count(),
+            1
+        );
 
-    #[derive(Copy, Clone)]
-    pub struct Jumping;
+        walk.obstacles.clear();
+        walk.generate_next_segment();
+        assert!(!walk.obstacles.iter().any(is_boss));
+    }
 
-    #[derive(Copy, Clone)]
-    pub struct Falling;
+    #[wasm_bindgen_test]
+    fn wind_slows_the_boy_only_inside_the_zone() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.boy.run_right();
+        walk.update(&keystate);
+        let calm_velocity = walk.velocity();
+
+        walk.obstacles = vec![walk.obstacle_pool.wind_zone(walk.boy.bounding_box())];
+        walk.update(&keystate);
+        assert_eq!(walk.target_scroll_velocity(), calm_velocity + WIND_FORCE);
+
+        walk.obstacles.clear();
+        walk.update(&keystate);
+        assert_eq!(walk.target_scroll_velocity(), calm_velocity);
+    }
 
-    #[derive(Copy, Clone)]
-    pub struct KnockedOut;
-}
+    #[wasm_bindgen_test]
+    fn only_obstacles_in_a_redrawn_region_are_drawn() {
+        let mut walk = test_walk(SEED);
+        walk.obstacles = vec![walk.obstacle_pool.wind_zone(walk.boy.bounding_box())];
 
-pub const HIGH_PLATFORM: i16 = 375;
-pub const LOW_PLATFORM: i16 = 420;
-pub const FIRST_PLATFORM: i16 = 370;
+        let full = Redraw::Full;
+        let nothing = Redraw::Regions(vec![]);
 
-#[async_trait(? Send)]
-impl Game for WalkTheDog {
-    async fn initialize(&self) -> Result<Box<dyn Game>> {
-        match self.machine {
-            None => {
-                let json = browser::fetch_json("rhb.json").await?;
-                let audio = Audio::new()?;
-                let sound = audio.load_sound("SFX_Jump_23.mp3").await?;
-                let background_music = audio.load_sound("background_song.mp3").await?;
-                audio.play_looping_sound(&background_music)?;
-                let rhb = RedHatBoy::new(
-                    json.into_serde()?,
-                    engine::load_image("rhb.png").await?,
-                    audio,
-                    sound,
-                );
-                let background = engine::load_image("BG.png").await?;
-                let stone = engine::load_image("Stone.png").await?;
-                let tiles = browser::fetch_json("tiles.json").await?;
-                let sprite_sheet = Rc::new(SpriteSheet::new(
-                    tiles.into_serde::<Sheet>()?,
-                    engine::load_image("tiles.png").await?,
-                ));
-                let background_width = background.width();
-                let starting_obstacles = stone_and_platform(stone.clone(), sprite_sheet.clone(), 0);
-                let timeline = rightmost(&starting_obstacles);
-                let machine = WalkTheDogStateMachine::new(Walk {
-                    boy: rhb,
-                    backgrounds: [
-                        Image::new(background.clone(), Point { x: 0, y: 0 }),
-                        Image::new(
-                            background,
-                            Point {
-                                x: background_width as i16,
-                                y: 0,
-                            },
-                        ),
-                    ],
-                    obstacle_sheet: sprite_sheet,
-                    obstacles: starting_obstacles,
-                    stone: stone.clone(),
-                    timeline,
-                });
-                Ok(Box::new(WalkTheDog {
-                    machine: Some(machine),
-                }))
-            }
-            Some(_) => Err(anyhow!("Error: Game is already initialized!")),
-        }
+        assert_eq!(walk.drawables_in_view(&full, Layer::Middle).count(), 1);
+        assert_eq!(walk.drawables_in_view(&nothing, Layer::Middle).count(), 0);
+        // Obstacles stay behind the boy unless they ask for the front layer
+        assert_eq!(walk.drawables_in_view(&full, Layer::Front).count(), 0);
     }
 
-    fn update(&mut self, keystate: &engine::KeyState) {
-        if let Some(machine) = self.machine.take() {
-            self.machine.replace(machine.update(keystate));
-        }
-
-        assert!(self.machine.is_some())
+    #[wasm_bindgen_test]
+    fn rain_starts_after_its_time_and_wraps_to_the_top() {
+        let mut walk = test_walk(SEED);
+        let keystate = KeyState::new();
+        walk.update(&keystate);
+        assert!(!walk.rain.active);
+
+        walk.elapsed_seconds = RAIN_START_SECONDS + 1.0;
+        walk.rain.drops[0].y = f64::from(walk.screen.height);
+        walk.update(&keystate);
+        assert!(walk.rain.active);
+        assert!(walk.rain.drops[0].y < 0.0);
+
+        walk.config.reduced_motion = true;
+        walk.update(&keystate);
+        assert!(!walk.rain.active);
     }
 
-    fn draw(&self, renderer: &Renderer) {
-        renderer.clear(&engine::Rect::new_from_x_y(0, 0, 600, 600));
+    #[wasm_bindgen_test]
+    fn rain_is_scattered_over_the_whole_screen() {
+        let screen = Screen {
+            width: 1200,
+            height: 300,
+        };
+        let rain = RainSystem::new(&screen);
+        assert!(rain.drops.iter().any(|drop| drop.x >= 600.0));
+        assert!(rain.drops.iter().all(|drop| drop.y < 300.0));
+    }
 
-        if let Some(machine) = &self.machine {
-            machine.draw(renderer);
-        }
+    #[wasm_bindgen_test]
+    fn same_seed_produces_same_frame_hashes() {
+        assert_eq!(
+            frame_hashes(test_walk(SEED), FRAMES),
+            frame_hashes(test_walk(SEED), FRAMES)
+        );
     }
 }
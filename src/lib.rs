@@ -1,27 +1,121 @@
 #[macro_use]
 mod browser;
+mod config;
 mod engine;
 mod game;
 mod segment;
 mod sound;
 
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
-use crate::engine::GameLoop;
-use crate::game::WalkTheDog;
+use crate::engine::{self, GameLoop};
+#[cfg(feature = "noop-audio")]
+#[doc(hidden)]
+pub use crate::game::generate_throwaway_segments;
+use crate::game::{GameStatus, WalkTheDog};
 
-// This is like the `main` function, except for JavaScript.
-#[wasm_bindgen(start)]
-pub fn main_js() -> Result<(), JsValue> {
+#[wasm_bindgen(typescript_custom_section)]
+const TS_DEFS: &str = r#"
+/** What `GameHandle.get_state` returns. */
+export type GameState = "Loading" | "Ready" | "Countdown" | "Walking" | "GameOver";
+
+/** What `GameHandle.get_difficulty` returns. */
+export type DifficultyLevel = "Normal" | "Hard";
+"#;
+
+/// Lets the page embedding the game control it from JavaScript.
+#[wasm_bindgen]
+pub struct GameHandle {
+    status: Rc<GameStatus>,
+}
+
+#[wasm_bindgen]
+impl GameHandle {
+    pub fn pause(&self) {
+        GameLoop::pause();
+    }
+
+    pub fn resume(&self) {
+        GameLoop::resume();
+    }
+
+    /// Caps the frame rate, 0 lifts the cap. See `GameConfig::target_fps`.
+    pub fn set_target_fps(&self, fps: u32) {
+        GameLoop::set_target_fps(fps);
+    }
+
+    /// Throws the current run away and goes back to the ready screen.
+    pub fn reset(&self) {
+        self.status.request_reset();
+    }
+
+    pub fn get_score(&self) -> u32 {
+        self.status.score()
+    }
+
+    /// One of the `GameState` names.
+    pub fn get_state(&self) -> String {
+        self.status.state().to_string()
+    }
+
+    /// One of the `DifficultyLevel` names.
+    pub fn get_difficulty(&self) -> String {
+        self.status.difficulty().to_string()
+    }
+
+    /// Tunes the physics while the game runs, `name` being one of the
+    /// `PhysicsConfig` fields. Throws for names there are none of.
+    pub fn set_physics_param(&self, name: &str, value: f64) -> Result<(), JsValue> {
+        self.status
+            .request_physics_param(name, value)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Picks one of the `Preset`s for the runs started afterwards, the one
+    /// under way keeps its own. Throws for names there are none of.
+    pub fn set_preset(&self, name: &str) -> Result<(), JsValue> {
+        self.status
+            .request_preset(name)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+}
+
+/// Starts loading the assets and then the game loop. The returned handle
+/// controls the game, its state is `Loading` until it runs. If loading
+/// fails the page says so.
+#[wasm_bindgen]
+pub fn start_game() -> GameHandle {
     console_error_panic_hook::set_once();
 
-    browser::spawn_local(async move {
-        let game = WalkTheDog::new();
+    let game = WalkTheDog::new();
+    let status = game.status();
+    browser::spawn_local_reporting(async move {
         GameLoop::start(game)
             .await
-            .expect("Could not start game loop")
+            .map_err(|err| JsValue::from_str(&format!("Failed to load the game: {:#}", err)))
     });
 
-    Ok(())
+    GameHandle { status }
+}
+
+#[wasm_bindgen]
+pub fn pause() {
+    GameLoop::pause();
+}
+
+#[wasm_bindgen]
+pub fn resume() {
+    GameLoop::resume();
+}
+
+/// The last frame drawn, as a PNG data URL, for share images and the like.
+/// Empty if the canvas can't be read.
+#[wasm_bindgen]
+pub fn capture_frame() -> String {
+    engine::capture_frame().unwrap_or_else(|err| {
+        log!("Could not capture the frame {:#?}", err);
+        String::new()
+    })
 }
@@ -1,68 +1,441 @@
 use crate::engine::{Image, Rect, SpriteSheet};
 use crate::game::{
-    Barrier, Obstacle, Platform, Point, FIRST_PLATFORM, HIGH_PLATFORM, LOW_PLATFORM,
+    Boss, CoinKind, Ground, ObstacleKind, ObstaclePool, Point, BOSS_HEIGHT, DUCK_HEIGHT,
+    FIRST_PLATFORM, GROUND, HIGH_PLATFORM, LOW_PLATFORM, STONE_HITBOX_INSET,
 };
 use std::rc::Rc;
 use web_sys::HtmlImageElement;
 
+/// No segment is made of more obstacles than this, coins and the power-up
+/// pickup the walk may put in front of it included.
+pub const MAX_SEGMENT_OBSTACLES: usize = 7;
+
 pub fn stone_and_platform(
     stone: HtmlImageElement,
     sprite_sheet: Rc<SpriteSheet>,
     offset_x: i16,
-) -> Vec<Box<dyn Obstacle>> {
+    pool: &mut ObstaclePool,
+) -> Vec<ObstacleKind> {
     const INITIAL_STONE_OFFSET: i16 = 150;
-    vec![
-        Box::new(Barrier::new(Image::new(
-            stone,
+    let mut obstacles = vec![
+        pool.barrier(
+            Image::new(
+                stone,
+                Point {
+                    x: offset_x + INITIAL_STONE_OFFSET,
+                    y: STONE_ON_GROUND,
+                },
+            ),
+            STONE_HITBOX_INSET,
+        ),
+        create_floating_platform(
+            sprite_sheet,
             Point {
-                x: offset_x + INITIAL_STONE_OFFSET,
-                y: STONE_ON_GROUND,
+                x: offset_x + FIRST_PLATFORM,
+                y: LOW_PLATFORM,
             },
-        ))),
-        Box::new(create_floating_platform(
+            pool,
+        ),
+    ];
+    obstacles.extend(coin_row(
+        Point {
+            x: offset_x + FIRST_PLATFORM + COIN_ROW_INSET,
+            y: LOW_PLATFORM - COIN_ROW_HEIGHT,
+        },
+        pool,
+    ));
+    obstacles
+}
+
+const COIN_ROW_LENGTH: i16 = 4;
+/// The last coins of a row are worth the most, for the boy who stays on
+/// the platform to the end.
+const COIN_ROW_KINDS: [CoinKind; COIN_ROW_LENGTH as usize] = [
+    CoinKind::Bronze,
+    CoinKind::Bronze,
+    CoinKind::Silver,
+    CoinKind::Gold,
+];
+const COIN_ROW_SPACING: i16 = 80;
+const COIN_ROW_INSET: i16 = 72;
+const COIN_ROW_HEIGHT: i16 = 40;
+
+/// A row of coins floating above a platform, starting at `start`.
+fn coin_row(start: Point, pool: &mut ObstaclePool) -> Vec<ObstacleKind> {
+    (0..COIN_ROW_LENGTH)
+        .map(|index| {
+            pool.coin(
+                Point {
+                    x: start.x + index * COIN_ROW_SPACING,
+                    y: start.y,
+                },
+                COIN_ROW_KINDS[index as usize],
+            )
+        })
+        .collect()
+}
+
+pub fn other_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    offset_x: i16,
+    pool: &mut ObstaclePool,
+) -> Vec<ObstacleKind> {
+    const INITIAL_STONE_OFFSET: i16 = 150;
+    vec![create_cliff_platform(
+        sprite_sheet,
+        Point {
+            x: offset_x + FIRST_PLATFORM,
+            y: HIGH_PLATFORM,
+        },
+        pool,
+    )]
+}
+
+pub fn glowing_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    offset_x: i16,
+    pool: &mut ObstaclePool,
+) -> Vec<ObstacleKind> {
+    vec![create_glowing_platform(
+        sprite_sheet,
+        Point {
+            x: offset_x + FIRST_PLATFORM,
+            y: LOW_PLATFORM,
+        },
+        pool,
+    )]
+}
+
+const WIND_ZONE_WIDTH: i16 = 500;
+
+/// A low platform with a headwind blowing along the ground in front of it,
+/// so running underneath takes longer than hopping on top.
+pub fn windy_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    offset_x: i16,
+    pool: &mut ObstaclePool,
+) -> Vec<ObstacleKind> {
+    let platform_bottom = LOW_PLATFORM + FLOATING_PLATFORM_MIDDLE_HEIGHT;
+    vec![
+        pool.wind_zone(Rect::new_from_x_y(
+            offset_x,
+            platform_bottom,
+            WIND_ZONE_WIDTH,
+            GROUND - platform_bottom,
+        )),
+        create_floating_platform(
             sprite_sheet,
             Point {
                 x: offset_x + FIRST_PLATFORM,
                 y: LOW_PLATFORM,
             },
-        )),
+            pool,
+        ),
     ]
 }
 
-pub fn other_platform(sprite_sheet: Rc<SpriteSheet>, offset_x: i16) -> Vec<Box<dyn Obstacle>> {
-    const INITIAL_STONE_OFFSET: i16 = 150;
-    vec![Box::new(create_cliff_platform(
+const GAP_PLATFORM_LANDING_INSET: i16 = 30;
+/// The caps of the platform over the gap are shaved off, whoever lands on
+/// the very edge drops into the pit.
+const GAP_PLATFORM_BOUNDING_BOXES: [Rect; 3] = [
+    Rect::new_from_x_y(
+        GAP_PLATFORM_LANDING_INSET,
+        0,
+        FLOATING_PLATFORM_EDGE_WIDTH - GAP_PLATFORM_LANDING_INSET,
+        FLOATING_PLATFORM_EDGE_HEIGHT,
+    ),
+    Rect::new_from_x_y(
+        FLOATING_PLATFORM_EDGE_WIDTH,
+        0,
+        FLOATING_PLATFORM_WIDTH - (FLOATING_PLATFORM_EDGE_WIDTH * 2),
+        FLOATING_PLATFORM_MIDDLE_HEIGHT,
+    ),
+    Rect::new_from_x_y(
+        FLOATING_PLATFORM_WIDTH - FLOATING_PLATFORM_EDGE_WIDTH,
+        0,
+        FLOATING_PLATFORM_EDGE_WIDTH - GAP_PLATFORM_LANDING_INSET,
+        FLOATING_PLATFORM_EDGE_HEIGHT,
+    ),
+];
+
+/// A pit right under a low platform, the only way across is over the top.
+pub fn gap_and_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    offset_x: i16,
+    pool: &mut ObstaclePool,
+) -> Vec<ObstacleKind> {
+    let platform_x = offset_x + FIRST_PLATFORM;
+    vec![
+        Box::new(Ground::pit(platform_x, FLOATING_PLATFORM_WIDTH)).into(),
+        create_custom_platform(
+            sprite_sheet,
+            Point {
+                x: platform_x,
+                y: LOW_PLATFORM,
+            },
+            &FLOATING_PLATFORM_SPRITES,
+            &GAP_PLATFORM_BOUNDING_BOXES,
+            pool,
+        ),
+    ]
+}
+
+const LONG_PLATFORM_SPRITES: [&str; 4] = ["13.png", "14.png", "14.png", "15.png"];
+const LONG_PLATFORM_WIDTH: i16 = 512;
+const LONG_PLATFORM_BOUNDING_BOXES: [Rect; 3] =
+    floating_platform_bounding_boxes(LONG_PLATFORM_WIDTH);
+
+/// A high platform two middle tiles long, with coins along it.
+pub fn long_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    offset_x: i16,
+    pool: &mut ObstaclePool,
+) -> Vec<ObstacleKind> {
+    let mut obstacles = vec![create_custom_platform(
         sprite_sheet,
         Point {
             x: offset_x + FIRST_PLATFORM,
             y: HIGH_PLATFORM,
         },
-    ))]
+        &LONG_PLATFORM_SPRITES,
+        &LONG_PLATFORM_BOUNDING_BOXES,
+        pool,
+    )];
+    obstacles.extend(coin_row(
+        Point {
+            x: offset_x + FIRST_PLATFORM + COIN_ROW_INSET,
+            y: HIGH_PLATFORM - COIN_ROW_HEIGHT,
+        },
+        pool,
+    ));
+    obstacles
+}
+
+const PIT_OFFSET: i16 = 200;
+const PIT_WIDTH: i16 = 160;
+const LEDGE_WIDTH: i16 = 300;
+const LEDGE_HEIGHT: i16 = 40;
+
+/// A pit to jump over onto a raised ledge. Whoever misses the jump falls
+/// off the screen.
+pub fn pit(offset_x: i16) -> Vec<ObstacleKind> {
+    let pit_x = offset_x + PIT_OFFSET;
+    vec![
+        Box::new(Ground::pit(pit_x, PIT_WIDTH)).into(),
+        Box::new(Ground::raised(
+            pit_x + PIT_WIDTH,
+            LEDGE_WIDTH,
+            GROUND - LEDGE_HEIGHT,
+        ))
+        .into(),
+    ]
+}
+
+const HANGING_BARRIER_COUNT: i16 = 3;
+const HANGING_BARRIER_SPACING: i16 = 100;
+
+/// Hanging barriers in a row too long to jump, the boy has to slide under
+/// them, holding the slide all the way.
+pub fn hanging_barrier_run(
+    stone: HtmlImageElement,
+    offset_x: i16,
+    pool: &mut ObstaclePool,
+) -> Vec<ObstacleKind> {
+    (0..HANGING_BARRIER_COUNT)
+        .map(|index| {
+            pool.hanging_barrier(Image::new(
+                stone.clone(),
+                Point {
+                    x: offset_x + index * HANGING_BARRIER_SPACING,
+                    y: DUCK_HEIGHT,
+                },
+            ))
+        })
+        .collect()
+}
+
+const BOSS_OFFSET: i16 = 1000;
+
+/// A warm-up platform with coins above it, then the boss pacing on the
+/// ground behind it.
+pub fn boss_segment(
+    sprite_sheet: Rc<SpriteSheet>,
+    stone: HtmlImageElement,
+    offset_x: i16,
+    pool: &mut ObstaclePool,
+) -> Vec<ObstacleKind> {
+    let mut obstacles = vec![create_floating_platform(
+        sprite_sheet,
+        Point {
+            x: offset_x + FIRST_PLATFORM,
+            y: LOW_PLATFORM,
+        },
+        pool,
+    )];
+    obstacles.extend(coin_row(
+        Point {
+            x: offset_x + FIRST_PLATFORM + COIN_ROW_INSET,
+            y: LOW_PLATFORM - COIN_ROW_HEIGHT,
+        },
+        pool,
+    ));
+    obstacles.push(
+        Box::new(Boss::new(
+            stone,
+            Point {
+                x: offset_x + BOSS_OFFSET,
+                y: GROUND - BOSS_HEIGHT,
+            },
+        ))
+        .into(),
+    );
+    obstacles
 }
 
 pub const STONE_ON_GROUND: i16 = 550;
 pub const FLOATING_PLATFORM_SPRITES: [&str; 3] = ["13.png", "14.png", "15.png"];
-pub const FLOATING_PLATFORM_BOUNDING_BOXES: [Rect; 3] = [
-    Rect::new_from_x_y(0, 0, 60, 54),
-    Rect::new_from_x_y(60, 0, 384 - (60 * 2), 93),
-    Rect::new_from_x_y(384 - 60, 0, 60, 54),
-];
+const FLOATING_PLATFORM_WIDTH: i16 = 384;
+const FLOATING_PLATFORM_EDGE_WIDTH: i16 = 60;
+const FLOATING_PLATFORM_EDGE_HEIGHT: i16 = 54;
+const FLOATING_PLATFORM_MIDDLE_HEIGHT: i16 = 93;
+pub const FLOATING_PLATFORM_BOUNDING_BOXES: [Rect; 3] =
+    floating_platform_bounding_boxes(FLOATING_PLATFORM_WIDTH);
+
+/// The left edge, the middle and the right edge of a floating platform that
+/// is `width` pixels wide. The boxes touch, so the landing surface has no gaps.
+pub const fn floating_platform_bounding_boxes(width: i16) -> [Rect; 3] {
+    [
+        Rect::new_from_x_y(
+            0,
+            0,
+            FLOATING_PLATFORM_EDGE_WIDTH,
+            FLOATING_PLATFORM_EDGE_HEIGHT,
+        ),
+        Rect::new_from_x_y(
+            FLOATING_PLATFORM_EDGE_WIDTH,
+            0,
+            width - (FLOATING_PLATFORM_EDGE_WIDTH * 2),
+            FLOATING_PLATFORM_MIDDLE_HEIGHT,
+        ),
+        Rect::new_from_x_y(
+            width - FLOATING_PLATFORM_EDGE_WIDTH,
+            0,
+            FLOATING_PLATFORM_EDGE_WIDTH,
+            FLOATING_PLATFORM_EDGE_HEIGHT,
+        ),
+    ]
+}
+
+fn create_floating_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    position: Point,
+    pool: &mut ObstaclePool,
+) -> ObstacleKind {
+    pool.platform_from_width(sprite_sheet, position, FLOATING_PLATFORM_WIDTH)
+}
 
-fn create_floating_platform(sprite_sheet: Rc<SpriteSheet>, position: Point) -> Platform {
-    Platform::new(
+/// A platform out of `sprite_names` that the boy collides with exactly at
+/// `bounding_boxes`, relative to `position`.
+pub fn create_custom_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    position: Point,
+    sprite_names: &[&'static str],
+    bounding_boxes: &[Rect],
+    pool: &mut ObstaclePool,
+) -> ObstacleKind {
+    pool.platform(sprite_sheet, position, sprite_names, bounding_boxes)
+}
+
+pub const CLIFF_SPRITES: [&str; 3] = ["1.png", "1.png", "3.png"];
+fn create_cliff_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    position: Point,
+    pool: &mut ObstaclePool,
+) -> ObstacleKind {
+    create_custom_platform(
         sprite_sheet,
         position,
-        &FLOATING_PLATFORM_SPRITES,
+        &CLIFF_SPRITES,
         &FLOATING_PLATFORM_BOUNDING_BOXES,
+        pool,
     )
 }
 
-pub const CLIFF_SPRITES: [&str; 3] = ["1.png", "1.png", "3.png"];
-fn create_cliff_platform(sprite_sheet: Rc<SpriteSheet>, position: Point) -> Platform {
-    Platform::new(
+pub const GLOWING_PLATFORM_FRAMES: [&[&str]; 2] = [
+    &["13.png", "14.png", "15.png"],
+    &["13.png", "17.png", "15.png"],
+];
+const GLOWING_PLATFORM_FRAME_DURATION: u16 = 20;
+fn create_glowing_platform(
+    sprite_sheet: Rc<SpriteSheet>,
+    position: Point,
+    pool: &mut ObstaclePool,
+) -> ObstacleKind {
+    pool.animated_platform(
         sprite_sheet,
         position,
-        &CLIFF_SPRITES,
+        &GLOWING_PLATFORM_FRAMES,
         &FLOATING_PLATFORM_BOUNDING_BOXES,
+        GLOWING_PLATFORM_FRAME_DURATION,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{rightmost, Obstacle};
+
+    #[test]
+    fn pit_is_followed_by_the_ledge() {
+        let obstacles = pit(1000);
+        assert_eq!(obstacles[0].right(), 1000 + 200 + 160);
+        assert_eq!(obstacles[1].right(), 1000 + 200 + 160 + 300);
+        assert_eq!(rightmost(&obstacles), 1660);
+    }
+
+    #[test]
+    fn rightmost_of_no_obstacles_is_zero() {
+        assert_eq!(rightmost(&vec![]), 0);
+    }
+
+    #[test]
+    fn floating_platform_boxes_line_up_edge_to_edge() {
+        [256, 384, 512, 1024].iter().for_each(|width| {
+            let [left, middle, right] = floating_platform_bounding_boxes(*width);
+            assert_eq!(left.x(), 0);
+            assert_eq!(left.right(), middle.x());
+            assert_eq!(middle.right(), right.x());
+            assert_eq!(right.right(), *width);
+        });
+    }
+
+    #[test]
+    fn floating_platform_landing_surface_is_level() {
+        let boxes = floating_platform_bounding_boxes(640);
+        assert!(boxes.iter().all(|bounding_box| bounding_box.y() == 0));
+    }
+
+    #[test]
+    fn default_floating_platform_keeps_its_boxes() {
+        let [left, middle, right] = FLOATING_PLATFORM_BOUNDING_BOXES;
+        assert_eq!((left.x(), left.width, left.height), (0, 60, 54));
+        assert_eq!((middle.x(), middle.width, middle.height), (60, 264, 93));
+        assert_eq!((right.x(), right.width, right.height), (324, 60, 54));
+    }
+
+    #[test]
+    fn gap_platform_lands_inside_its_caps() {
+        let [left, middle, right] = GAP_PLATFORM_BOUNDING_BOXES;
+        assert_eq!(left.x(), 30);
+        assert_eq!(left.right(), middle.x());
+        assert_eq!(middle.right(), right.x());
+        assert_eq!(right.right(), FLOATING_PLATFORM_WIDTH - 30);
+    }
+
+    #[test]
+    fn long_platform_widens_the_middle() {
+        let [_left, middle, _right] = LONG_PLATFORM_BOUNDING_BOXES;
+        let [_, default_middle, _] = FLOATING_PLATFORM_BOUNDING_BOXES;
+        assert_eq!(middle.width, default_middle.width + 128);
+    }
+}
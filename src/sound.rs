@@ -2,32 +2,102 @@ use anyhow::{anyhow, Result};
 use js_sys::ArrayBuffer;
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{AudioBuffer, AudioBufferSourceNode, AudioContext, AudioDestinationNode, AudioNode};
+use web_sys::{
+    AudioBuffer, AudioBufferSourceNode, AudioContext, AudioNode, GainNode, StereoPannerNode,
+};
 pub fn create_audio_context() -> Result<AudioContext> {
     AudioContext::new().map_err(|err| anyhow!("Could not create audio context: {:#?}", err))
 }
 
+pub fn suspend(ctx: &AudioContext) -> Result<()> {
+    ctx.suspend()
+        .map(|_promise| ())
+        .map_err(|err| anyhow!("Could not suspend audio context {:#?}", err))
+}
+
+pub fn resume(ctx: &AudioContext) -> Result<()> {
+    ctx.resume()
+        .map(|_promise| ())
+        .map_err(|err| anyhow!("Could not resume audio context {:#?}", err))
+}
+
+pub fn create_gain(ctx: &AudioContext) -> Result<GainNode> {
+    create_gain_to(ctx, &ctx.destination())
+}
+
+pub fn create_gain_to(ctx: &AudioContext, destination: &AudioNode) -> Result<GainNode> {
+    let gain = ctx
+        .create_gain()
+        .map_err(|err| anyhow!("Error creating gain node {:#?}", err))?;
+    connect_with_audio_node(&gain, destination)?;
+    Ok(gain)
+}
+
+pub fn create_stereo_panner(
+    ctx: &AudioContext,
+    pan: f32,
+    destination: &AudioNode,
+) -> Result<StereoPannerNode> {
+    let panner = ctx
+        .create_stereo_panner()
+        .map_err(|err| anyhow!("Error creating stereo panner {:#?}", err))?;
+    panner.pan().set_value(pan);
+    connect_with_audio_node(&panner, destination)?;
+    Ok(panner)
+}
+
 fn create_buffer_source(ctx: &AudioContext) -> Result<AudioBufferSourceNode> {
     ctx.create_buffer_source()
         .map_err(|err| anyhow!("Error creating buffer source {:#?}", err))
 }
-fn connect_with_audio_node(
-    buffer_source: &AudioBufferSourceNode,
-    destination: &AudioDestinationNode,
-) -> Result<AudioNode> {
-    buffer_source
-        .connect_with_audio_node(&destination)
+fn connect_with_audio_node(source: &AudioNode, destination: &AudioNode) -> Result<AudioNode> {
+    source
+        .connect_with_audio_node(destination)
         .map_err(|err| anyhow!("Error connecting audio source to destination {:#?}", err))
 }
 
-pub fn play_sound(ctx: &AudioContext, buffer: &AudioBuffer, looping: LOOPING) -> Result<()> {
-    let track_source = create_track_source(ctx, buffer)?;
-    if let (LOOPING::YES) = looping {
+pub fn play_sound(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    destination: &AudioNode,
+    looping: LOOPING,
+) -> Result<()> {
+    start_sound(ctx, buffer, destination, looping).map(|_track_source| ())
+}
+
+/// Like `play_sound`, but keeps the source around so it can be stopped.
+pub fn start_sound(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    destination: &AudioNode,
+    looping: LOOPING,
+) -> Result<AudioBufferSourceNode> {
+    let track_source = create_track_source(ctx, buffer, destination)?;
+    if let LOOPING::YES = looping {
         track_source.set_loop(true);
     }
     track_source
         .start()
-        .map_err(|err| anyhow!("Could not start sound {:#?}", err))
+        .map_err(|err| anyhow!("Could not start sound {:#?}", err))?;
+    Ok(track_source)
+}
+
+pub fn stop_sound(ctx: &AudioContext, source: &AudioBufferSourceNode, delay: f64) -> Result<()> {
+    source
+        .stop_with_when(ctx.current_time() + delay)
+        .map_err(|err| anyhow!("Could not stop sound {:#?}", err))
+}
+
+/// Moves the gain from where it is now to `value` over `duration` seconds.
+pub fn ramp_gain(ctx: &AudioContext, gain: &GainNode, value: f32, duration: f64) -> Result<()> {
+    let now = ctx.current_time();
+    let param = gain.gain();
+    param
+        .cancel_scheduled_values(now)
+        .and_then(|param| param.set_value_at_time(param.value(), now))
+        .and_then(|param| param.linear_ramp_to_value_at_time(value, now + duration))
+        .map(|_param| ())
+        .map_err(|err| anyhow!("Could not ramp gain {:#?}", err))
 }
 
 pub async fn decode_audio_data(
@@ -49,9 +119,13 @@ pub enum LOOPING {
     YES,
 }
 
-fn create_track_source(ctx: &AudioContext, buffer: &AudioBuffer) -> Result<AudioBufferSourceNode> {
+fn create_track_source(
+    ctx: &AudioContext,
+    buffer: &AudioBuffer,
+    destination: &AudioNode,
+) -> Result<AudioBufferSourceNode> {
     let track_source = create_buffer_source(ctx)?;
     track_source.set_buffer(Some(&buffer));
-    connect_with_audio_node(&track_source, &ctx.destination())?;
+    connect_with_audio_node(&track_source, destination)?;
     Ok(track_source)
 }
@@ -0,0 +1,47 @@
+//! Kept out of the unit tests, the counting allocator would count for every
+//! one of them.
+use rust_webpack_template::generate_throwaway_segments;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const SEED: u64 = 42;
+const SEGMENTS: usize = 200;
+
+fn segment_allocations(recycle: bool) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    generate_throwaway_segments(SEED, SEGMENTS, recycle);
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+#[wasm_bindgen_test]
+fn recycling_obstacles_allocates_less_than_dropping_them() {
+    let pooled_allocations = segment_allocations(true);
+    let unpooled_allocations = segment_allocations(false);
+    assert!(
+        pooled_allocations < unpooled_allocations,
+        "pooled {} vs unpooled {} allocations",
+        pooled_allocations,
+        unpooled_allocations
+    );
+}